@@ -1,6 +1,25 @@
+use std::{error, fmt};
+
+use crate::shorthand::{self, Component};
+
 #[derive(Debug)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>,
+    // parseしたときはAuthor。呼ぶ側で変える
+    pub origin: Origin,
+}
+
+// stylesheetをどこからもらったか
+// https://drafts.csswg.org/css-cascade/#cascading-origins
+// 普通のdeclarationは後ろのほうが強い。!importantだと逆になる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+    // ブラウザが持ってるもの
+    UserAgent,
+    // ユーザーが設定したもの
+    User,
+    // ページのもの
+    Author,
 }
 
 #[derive(Debug)]
@@ -14,6 +33,28 @@ pub struct Rule {
 #[derive(Debug)]
 pub enum Selector {
     Simple(SimpleSelector),
+    // div > pみたいにcombinatorでつないだselector
+    Complex(ComplexSelector),
+}
+
+// compoundsは書かれた順で、最後がstyleを当てる要素
+// combinators[i]はcompounds[i]とcompounds[i + 1]の関係
+#[derive(Debug)]
+pub struct ComplexSelector {
+    pub compounds: Vec<SimpleSelector>,
+    pub combinators: Vec<Combinator>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Combinator {
+    // div p: 祖先のどれか
+    Descendant,
+    // ul > li: 親
+    Child,
+    // h1 + p: すぐ前の兄弟
+    NextSibling,
+    // h1 ~ p: 前の兄弟のどれか
+    SubsequentSibling,
 }
 
 #[derive(Debug)]
@@ -21,6 +62,76 @@ pub struct SimpleSelector {
     pub tag_name: Option<String>,
     pub id: Option<String>,
     pub class: Vec<String>,
+    pub attributes: Vec<AttributeSelector>,
+    pub pseudo_classes: Vec<PseudoClass>,
+    // ::beforeとか。compoundの最後にしか書けない
+    pub pseudo_element: Option<PseudoElement>,
+}
+
+// :first-childとか:not(...)とか
+#[derive(Debug)]
+pub enum PseudoClass {
+    FirstChild,
+    LastChild,
+    // :nth-child(An+B)のAとB。odd、evenもこれにしてある
+    NthChild(i32, i32),
+    Not(Vec<Selector>),
+    Is(Vec<Selector>),
+    // :isと同じだけどspecificityは0
+    Where(Vec<Selector>),
+    // ここからはElementStateで決まる
+    Hover,
+    Focus,
+    Active,
+    Checked,
+    Disabled,
+    Enabled,
+    // hrefがあってまだ見てないリンク
+    Link,
+    Visited,
+    // :linkか:visited
+    AnyLink,
+}
+
+// [type=checkbox]みたいなattributeのselector
+#[derive(Debug)]
+pub struct AttributeSelector {
+    // 小文字にしてある
+    pub name: String,
+    // [disabled]みたいに名前だけならNone
+    pub matcher: Option<(AttributeOperator, String)>,
+    // [type=text i]のiとかs。なければHTMLの決まりに従う
+    pub flag: Option<CaseFlag>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttributeOperator {
+    // [attr=val]
+    Equals,
+    // [attr~=val]: 空白で区切ったどれか
+    Includes,
+    // [attr|=val]: valかval-から始まる
+    DashMatch,
+    // [attr^=val]
+    Prefix,
+    // [attr$=val]
+    Suffix,
+    // [attr*=val]
+    Substring,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseFlag {
+    // i: 大文字小文字を区別しない
+    Insensitive,
+    // s: 区別する
+    Sensitive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PseudoElement {
+    Before,
+    After,
 }
 
 // style の margin(name): auto(value); の部分
@@ -28,6 +139,10 @@ pub struct SimpleSelector {
 pub struct Declaration {
     pub name: String,
     pub value: Value,
+    // !importantがついてたらtrue
+    pub important: bool,
+    // stylesheetの中で何番目のdeclarationか。cascadeで同じ強さなら後ろが勝つ
+    pub position: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -35,7 +150,26 @@ pub enum Value {
     Keyword(String),
     // これはタプル
     Length(f32, Unit),
+    // flex: 1とかline-height: 1.5みたいな単位のない数
+    Number(f32),
     ColorValue(Color),
+    // background-position: left 10pxみたいに空白で並べた値
+    List(Vec<Value>),
+    // contentの"..."とかattr()とかの並び
+    Content(Vec<ContentItem>),
+    // counter-reset: a 1 bみたいな名前と数字の並び
+    Counters(Vec<(String, i32)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContentItem {
+    String(String),
+    // attr(data-icon)。名前は小文字にしてある
+    Attr(String),
+    // counter(name, style)
+    Counter(String, String),
+    // counters(name, "区切り", style)
+    Counters(String, String, String),
 }
 
 // 文字とかの高さとかの単位？
@@ -54,18 +188,158 @@ pub struct Color {
 
 impl Copy for Color {}
 
+/// transparentは透明な黒
+pub const TRANSPARENT: Color = Color {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 0,
+};
+
 pub type Specificity = (usize, usize, usize);
 
 impl Selector {
+    // 最後のcompoundについてる::beforeとか
+    pub fn pseudo_element(&self) -> Option<PseudoElement> {
+        match *self {
+            Selector::Simple(ref simple) => simple.pseudo_element,
+            Selector::Complex(ref complex) => complex.compounds.last()?.pseudo_element,
+        }
+    }
+
     // それぞれの長さとか数をとってる
     pub fn specificity(&self) -> Specificity {
         // http://www.w3.org/TR/selectors/#specificity
-        let Selector::Simple(ref simple) = *self;
-        let a: usize = simple.id.iter().count();
-        let b: usize = simple.class.len();
-        let c: usize = simple.tag_name.iter().count();
-        println!("specificity - ( {:?} )", (a, b, c));
-        (a, b, c)
+        match *self {
+            Selector::Simple(ref simple) => simple.specificity(),
+            // combinatorでつないだものは全部足す
+            Selector::Complex(ref complex) => complex.compounds.iter().fold(
+                (0, 0, 0),
+                |(a, b, c): Specificity, simple: &SimpleSelector| {
+                    let (x, y, z): Specificity = simple.specificity();
+                    (a + x, b + y, c + z)
+                },
+            ),
+        }
+    }
+}
+
+impl SimpleSelector {
+    fn specificity(&self) -> Specificity {
+        let a: usize = self.id.iter().count();
+        // attribute selectorはclassと同じ重さ
+        let b: usize = self.class.len() + self.attributes.len();
+        // pseudo-elementはtagと同じ重さ
+        let c: usize = self.tag_name.iter().count() + self.pseudo_element.iter().count();
+        self.pseudo_classes.iter().fold(
+            (a, b, c),
+            |(a, b, c): Specificity, pseudo: &PseudoClass| {
+                let (x, y, z): Specificity = pseudo.specificity();
+                (a + x, b + y, c + z)
+            },
+        )
+    }
+}
+
+impl PseudoClass {
+    // https://drafts.csswg.org/selectors/#specificity-rules
+    fn specificity(&self) -> Specificity {
+        match *self {
+            // :not()と:is()は中で一番強いselectorと同じ
+            PseudoClass::Not(ref selectors) | PseudoClass::Is(ref selectors) => selectors
+                .iter()
+                .map(Selector::specificity)
+                .max()
+                .unwrap_or((0, 0, 0)),
+            PseudoClass::Where(_) => (0, 0, 0),
+            // それ以外はclassと同じ
+            _ => (0, 1, 0),
+        }
+    }
+}
+
+// dumpしたときにCSSの書き方で出すため
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Keyword(ref s) => write!(f, "{}", s),
+            Value::Length(n, Unit::Px) => write!(f, "{}px", n),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::List(ref values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                Ok(())
+            }
+            Value::ColorValue(ref color) => write!(f, "{}", color),
+            Value::Content(ref items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            }
+            Value::Counters(ref counters) => {
+                for (i, (name, n)) in counters.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{} {}", name, n)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for ContentItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ContentItem::String(ref s) => write_string(f, s),
+            ContentItem::Attr(ref name) => write!(f, "attr({})", name),
+            ContentItem::Counter(ref name, ref style) => write!(f, "counter({}, {})", name, style),
+            ContentItem::Counters(ref name, ref separator, ref style) => {
+                write!(f, "counters({}, ", name)?;
+                write_string(f, separator)?;
+                write!(f, ", {})", style)
+            }
+        }
+    }
+}
+
+// "と\はエスケープして"..."で囲む
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            write!(f, "\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for PseudoElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PseudoElement::Before => write!(f, "::before"),
+            PseudoElement::After => write!(f, "::after"),
+        }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)?;
+        if self.a != 255 {
+            write!(f, "{:02x}", self.a)?;
+        }
+        Ok(())
     }
 }
 
@@ -79,176 +353,885 @@ impl Value {
     }
 }
 
+// parseに失敗したときのエラー。html::ParseErrorと同じで位置と期待してたものを持ってる
+#[derive(Debug, Clone, PartialEq)]
+pub struct CssError {
+    pub kind: CssErrorKind,
+    pub byte_offset: usize,
+    // lineとcolumnは1から数える。columnはバイトじゃなくて文字数
+    pub line: usize,
+    pub column: usize,
+    pub expected: String,
+    pub found: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CssErrorKind {
+    UnexpectedCharacter,
+    UnexpectedEof,
+    // 12pxのpxの部分が知らない単位
+    UnknownUnit,
+    // #のあとの16進数がおかしい
+    InvalidColor,
+    InvalidNumber,
+    // :hogeのhogeを知らない
+    UnknownPseudoClass,
+    UnknownPseudoElement,
+    // :not(::before)みたいに書けない場所にあるpseudo-element
+    MisplacedPseudoElement,
+    // shorthandの値の組み合わせがおかしい
+    InvalidValue,
+}
+
+impl fmt::Display for CssError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message: &str = match self.kind {
+            CssErrorKind::UnexpectedCharacter => "unexpected character",
+            CssErrorKind::UnexpectedEof => "unexpected end of input",
+            CssErrorKind::UnknownUnit => "unknown unit",
+            CssErrorKind::InvalidColor => "invalid color",
+            CssErrorKind::InvalidNumber => "invalid number",
+            CssErrorKind::UnknownPseudoClass => "unknown pseudo-class",
+            CssErrorKind::UnknownPseudoElement => "unknown pseudo-element",
+            CssErrorKind::MisplacedPseudoElement => "misplaced pseudo-element",
+            CssErrorKind::InvalidValue => "invalid value",
+        };
+        write!(
+            f,
+            "{}:{}: {}: expected {}, found {}",
+            self.line, self.column, message, self.expected, self.found
+        )
+    }
+}
+
+impl error::Error for CssError {}
+
 //　最終的に呼び出されるcssをparse関数
-pub fn parse(source: String) -> Stylesheet {
-    let mut parser: Parser = Parser {
-        pos: 0,
-        input: source,
-    };
-    Stylesheet {
-        rules: parser.parse_rules(),
+// 一つでもおかしいところがあればエラーを返す
+pub fn parse(source: String) -> Result<Stylesheet, CssError> {
+    let mut parser: Parser = Parser::new(source, false);
+    Ok(Stylesheet {
+        rules: parser.parse_rules()?,
+        origin: Origin::Author,
+    })
+}
+
+/// ブラウザと同じように、おかしいdeclarationやruleだけ読み飛ばしてparseする
+/// 読み飛ばした場所のエラーは一緒に返す
+pub fn parse_lenient(source: String) -> (Stylesheet, Vec<CssError>) {
+    let mut parser: Parser = Parser::new(source, true);
+    // lenientのときはparse_rulesはエラーを返さない
+    let rules: Vec<Rule> = parser.parse_rules().unwrap_or_default();
+    (
+        Stylesheet {
+            rules,
+            origin: Origin::Author,
+        },
+        parser.errors,
+    )
+}
+
+/// redやtransparentみたいな色の名前をColorにする。大文字小文字は区別しない
+/// currentcolorはcolorの値で決まるのでここではNone
+pub fn named_color(name: &str) -> Option<Color> {
+    let name: String = name.to_ascii_lowercase();
+    if name == "transparent" {
+        return Some(TRANSPARENT);
     }
+    let index: usize = NAMED_COLORS
+        .binary_search_by_key(&name.as_str(), |&(color_name, _): &(&str, u32)| color_name)
+        .ok()?;
+    let rgb: u32 = NAMED_COLORS[index].1;
+    Some(Color {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+        a: 255,
+    })
+}
+
+/// style="color: red; margin: 0"みたいな{}のないdeclarationの並びをparseする
+/// おかしいdeclarationは読み飛ばして、そのエラーを一緒に返す
+pub fn parse_declaration_block(source: String) -> (Vec<Declaration>, Vec<CssError>) {
+    let mut parser: Parser = Parser::new(source, true);
+    let declarations: Vec<Declaration> = parser.parse_declaration_list().unwrap_or_default();
+    (declarations, parser.errors)
 }
 
 struct Parser {
     pos: usize,
     input: String,
+    // trueならエラーがあってもそこを飛ばして続ける
+    lenient: bool,
+    errors: Vec<CssError>,
+    // 今までに読んだdeclarationの数
+    declarations: usize,
 }
 
 impl Parser {
+    fn new(input: String, lenient: bool) -> Parser {
+        Parser {
+            pos: 0,
+            input,
+            lenient,
+            errors: Vec::new(),
+            declarations: 0,
+        }
+    }
+
     // Vex<rule>を返しているのでstyleは実際ここがparseしている
-    fn parse_rules(&mut self) -> Vec<Rule> {
+    fn parse_rules(&mut self) -> Result<Vec<Rule>, CssError> {
         let mut rules: Vec<Rule> = Vec::new();
         loop {
             self.consume_whitespace();
             if self.eof() {
                 break;
             }
-            rules.push(self.parse_rule());
+            let start: usize = self.pos;
+            match self.parse_rule() {
+                Ok(rule) => rules.push(rule),
+                // CSS Syntax §5.4.3: おかしいruleは{}のブロックごと捨てる
+                Err(err) if self.lenient => {
+                    self.errors.push(err);
+                    self.pos = start;
+                    self.skip_rule();
+                }
+                Err(err) => return Err(err),
+            }
         }
-        rules
+        Ok(rules)
     }
 
     //　selectors(h1.style#style2) { declarations(margin: auto;) }　みたいなのをここで作ってる
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
-            declarations: self.parse_declarations(),
-        }
+    fn parse_rule(&mut self) -> Result<Rule, CssError> {
+        Ok(Rule {
+            selectors: self.parse_selectors()?,
+            declarations: self.parse_declarations()?,
+        })
     }
 
     //　selectors(h1.style)みたいな感じがあるからVec
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+    fn parse_selectors(&mut self) -> Result<Vec<Selector>, CssError> {
         let mut selectors: Vec<Selector> = Vec::new();
         loop {
-            // Selector::Simple(self.parse_simple_selector())を
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
-            self.consume_whitespace();
+            selectors.push(self.parse_selector()?);
             // 次の文字が,なら次のselectorに移る、{なら終わる
             match self.next_char() {
-                ',' => {
+                Some(',') => {
                     self.consume_char();
                     self.consume_whitespace();
                 }
-                '{' => break,
-                c => panic!("Unexpected character {} in selector list", c),
+                Some('{') => break,
+                _ => return Err(self.unexpected("',' or '{'")),
             }
         }
         // ちょっと意味わかってない、styleを適用する順番を決めてたりする
-        selectors.sort_by(|a: &Selector, b: &Selector| b.specificity().cmp(&a.specificity()));
-        selectors
+        selectors.sort_by_key(|s: &Selector| std::cmp::Reverse(s.specificity()));
+        Ok(selectors)
+    }
+
+    // div > p.noteみたいな,で区切られた1つのselector
+    fn parse_selector(&mut self) -> Result<Selector, CssError> {
+        let mut compounds: Vec<SimpleSelector> = vec![self.parse_simple_selector()?];
+        let mut combinators: Vec<Combinator> = Vec::new();
+        loop {
+            let start: usize = self.pos;
+            self.consume_whitespace();
+            let combinator: Combinator = match self.next_char() {
+                Some('>') => Combinator::Child,
+                Some('+') => Combinator::NextSibling,
+                Some('~') => Combinator::SubsequentSibling,
+                // )は:not(...)とかの中のselectorの終わり
+                Some(',' | '{' | ')') | None => break,
+                // 空白だけで区切られていたら子孫
+                Some(_) if self.pos > start => Combinator::Descendant,
+                Some(_) => break,
+            };
+            // pseudo-elementのあとには何も続けられない
+            if compounds[compounds.len() - 1].pseudo_element.is_some() {
+                return Err(self.unexpected("',' or '{'"));
+            }
+            if combinator != Combinator::Descendant {
+                self.consume_char();
+                self.consume_whitespace();
+            }
+            combinators.push(combinator);
+            compounds.push(self.parse_simple_selector()?);
+        }
+        if combinators.is_empty() {
+            return Ok(Selector::Simple(compounds.pop().unwrap()));
+        }
+        Ok(Selector::Complex(ComplexSelector {
+            compounds,
+            combinators,
+        }))
     }
 
     /// selectorを決定している
-    fn parse_simple_selector(&mut self) -> SimpleSelector {
+    fn parse_simple_selector(&mut self) -> Result<SimpleSelector, CssError> {
+        let start: usize = self.pos;
         // selectorの初期化
         let mut selector: SimpleSelector = SimpleSelector {
             tag_name: None,
             id: None,
             class: Vec::new(),
+            attributes: Vec::new(),
+            pseudo_classes: Vec::new(),
+            pseudo_element: None,
         };
         // 全て読み終わるまで実行
-        while !self.eof() {
-            match self.next_char() {
+        while let Some(c) = self.next_char() {
+            match c {
                 '#' => {
                     self.consume_char();
-                    selector.id = Some(self.parse_identifier());
+                    selector.id = Some(self.parse_name("an id")?);
                 }
                 '.' => {
                     self.consume_char();
-                    selector.class.push(self.parse_identifier());
+                    selector.class.push(self.parse_name("a class name")?);
                 }
                 '*' => {
                     // universal selector
                     self.consume_char();
                 }
+                '[' => selector.attributes.push(self.parse_attribute_selector()?),
+                ':' if self.peek_char(1) == Some(':') || self.at_legacy_pseudo_element() => {
+                    selector.pseudo_element = Some(self.parse_pseudo_element()?);
+                    // ここでcompoundは終わり
+                    break;
+                }
+                ':' => selector.pseudo_classes.push(self.parse_pseudo_class()?),
                 c if valid_identifier_char(c) => {
                     selector.tag_name = Some(self.parse_identifier());
                 }
                 _ => break,
             }
         }
-        selector
+        // 何も読めてないならselectorがない
+        if self.pos == start {
+            return Err(self.unexpected("a selector"));
+        }
+        Ok(selector)
+    }
+
+    // [attr], [attr=val], [attr^="val" i]とかを読む
+    fn parse_attribute_selector(&mut self) -> Result<AttributeSelector, CssError> {
+        self.expect_char('[')?;
+        self.consume_whitespace();
+        let name: String = self.parse_name("an attribute name")?.to_ascii_lowercase();
+        self.consume_whitespace();
+        if self.next_char() == Some(']') {
+            self.consume_char();
+            return Ok(AttributeSelector {
+                name,
+                matcher: None,
+                flag: None,
+            });
+        }
+
+        let operator: AttributeOperator = match self.next_char() {
+            Some('=') => AttributeOperator::Equals,
+            Some('~') => AttributeOperator::Includes,
+            Some('|') => AttributeOperator::DashMatch,
+            Some('^') => AttributeOperator::Prefix,
+            Some('$') => AttributeOperator::Suffix,
+            Some('*') => AttributeOperator::Substring,
+            _ => return Err(self.unexpected("']' or an attribute operator")),
+        };
+        self.consume_char();
+        if operator != AttributeOperator::Equals {
+            self.expect_char('=')?;
+        }
+        self.consume_whitespace();
+        // 値はクォートしてもしなくてもいい
+        let value: String = match self.next_char() {
+            Some('"' | '\'') => self.parse_string()?,
+            _ => self.parse_name("an attribute value")?,
+        };
+        self.consume_whitespace();
+
+        let flag: Option<CaseFlag> = match self.next_char() {
+            Some('i' | 'I') => Some(CaseFlag::Insensitive),
+            Some('s' | 'S') => Some(CaseFlag::Sensitive),
+            _ => None,
+        };
+        if flag.is_some() {
+            self.consume_char();
+            self.consume_whitespace();
+        }
+        self.expect_char(']')?;
+        Ok(AttributeSelector {
+            name,
+            matcher: Some((operator, value)),
+            flag,
+        })
+    }
+
+    // :first-childとか:nth-child(2n+1)とか:not(p, .a)とかを読む
+    fn parse_pseudo_class(&mut self) -> Result<PseudoClass, CssError> {
+        let start: usize = self.pos;
+        self.expect_char(':')?;
+        let name: String = self.parse_name("a pseudo-class name")?.to_ascii_lowercase();
+        let functional: bool = self.next_char() == Some('(');
+        if functional {
+            self.consume_char();
+            self.consume_whitespace();
+        }
+        let pseudo: PseudoClass = match (name.as_str(), functional) {
+            ("first-child", false) => return Ok(PseudoClass::FirstChild),
+            ("last-child", false) => return Ok(PseudoClass::LastChild),
+            ("hover", false) => return Ok(PseudoClass::Hover),
+            ("focus", false) => return Ok(PseudoClass::Focus),
+            ("active", false) => return Ok(PseudoClass::Active),
+            ("checked", false) => return Ok(PseudoClass::Checked),
+            ("disabled", false) => return Ok(PseudoClass::Disabled),
+            ("enabled", false) => return Ok(PseudoClass::Enabled),
+            ("link", false) => return Ok(PseudoClass::Link),
+            ("visited", false) => return Ok(PseudoClass::Visited),
+            ("any-link", false) => return Ok(PseudoClass::AnyLink),
+            ("nth-child", true) => {
+                let (a, b): (i32, i32) = self.parse_nth()?;
+                PseudoClass::NthChild(a, b)
+            }
+            ("not", true) => PseudoClass::Not(self.parse_selector_list()?),
+            ("is", true) => PseudoClass::Is(self.parse_selector_list()?),
+            ("where", true) => PseudoClass::Where(self.parse_selector_list()?),
+            _ => {
+                let found: String = format!("':{}{}'", name, if functional { "(" } else { "" });
+                self.pos = start;
+                return Err(self.error(
+                    CssErrorKind::UnknownPseudoClass,
+                    "a supported pseudo-class",
+                    found,
+                ));
+            }
+        };
+        self.consume_whitespace();
+        self.expect_char(')')?;
+        Ok(pseudo)
+    }
+
+    // ::beforeと::after
+    fn parse_pseudo_element(&mut self) -> Result<PseudoElement, CssError> {
+        let start: usize = self.pos;
+        self.expect_char(':')?;
+        if self.next_char() == Some(':') {
+            self.consume_char();
+        }
+        let name: String = self
+            .parse_name("a pseudo-element name")?
+            .to_ascii_lowercase();
+        match name.as_str() {
+            "before" => Ok(PseudoElement::Before),
+            "after" => Ok(PseudoElement::After),
+            _ => {
+                self.pos = start;
+                Err(self.error(
+                    CssErrorKind::UnknownPseudoElement,
+                    "'::before' or '::after'",
+                    format!("'::{}'", name),
+                ))
+            }
+        }
+    }
+
+    // CSS2の書き方で:1つの:beforeと:afterもpseudo-elementになる
+    fn at_legacy_pseudo_element(&self) -> bool {
+        let name: String = self.input[self.pos + 1..]
+            .chars()
+            .take_while(|c: &char| valid_identifier_char(*c))
+            .collect();
+        name.eq_ignore_ascii_case("before") || name.eq_ignore_ascii_case("after")
+    }
+
+    // :not(...)とかの中の,で区切ったselector。)の手前まで読む
+    fn parse_selector_list(&mut self) -> Result<Vec<Selector>, CssError> {
+        let mut selectors: Vec<Selector> = Vec::new();
+        loop {
+            let start: usize = self.pos;
+            let selector: Selector = self.parse_selector()?;
+            if let Some(pseudo_element) = selector.pseudo_element() {
+                self.pos = start;
+                return Err(self.error(
+                    CssErrorKind::MisplacedPseudoElement,
+                    "a selector without pseudo-elements",
+                    format!("'{}'", pseudo_element),
+                ));
+            }
+            selectors.push(selector);
+            match self.next_char() {
+                Some(',') => {
+                    self.consume_char();
+                    self.consume_whitespace();
+                }
+                Some(')') => return Ok(selectors),
+                _ => return Err(self.unexpected("',' or ')'")),
+            }
+        }
+    }
+
+    // An+Bを読んで(A, B)を返す
+    // https://drafts.csswg.org/css-syntax/#anb-microsyntax
+    // 空白を入れていいのはBの前の+と-のまわりだけ
+    fn parse_nth(&mut self) -> Result<(i32, i32), CssError> {
+        let keyword: String = self.consume_while(|c: char| c.is_ascii_alphabetic());
+        if keyword.eq_ignore_ascii_case("odd") {
+            return Ok((2, 1));
+        }
+        if keyword.eq_ignore_ascii_case("even") {
+            return Ok((2, 0));
+        }
+        // odd、even以外ならnか-nしかありえないので、読んだぶんは戻す
+        self.pos -= keyword.len();
+
+        let sign: i32 = match self.next_char() {
+            Some('-') => {
+                self.consume_char();
+                -1
+            }
+            Some('+') => {
+                self.consume_char();
+                1
+            }
+            _ => 1,
+        };
+        let digits: Option<i32> = self.parse_nth_integer()?;
+        if !matches!(self.next_char(), Some('n' | 'N')) {
+            // nがなければBだけ
+            return match digits {
+                Some(b) => Ok((0, sign * b)),
+                None => Err(self.unexpected("an An+B expression")),
+            };
+        }
+        self.consume_char();
+        let a: i32 = sign * digits.unwrap_or(1);
+        // 2ndみたいにnのあとに名前が続いていたらおかしい
+        if self
+            .next_char()
+            .is_some_and(|c: char| c != '-' && valid_identifier_char(c))
+        {
+            return Err(self.unexpected("an An+B expression"));
+        }
+
+        self.consume_whitespace();
+        let b_sign: i32 = match self.next_char() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => return Ok((a, 0)),
+        };
+        self.consume_char();
+        self.consume_whitespace();
+        match self.parse_nth_integer()? {
+            Some(b) => Ok((a, b_sign * b)),
+            None => Err(self.unexpected("an integer")),
+        }
+    }
+
+    // 符号なしの整数。数字がなければNone
+    fn parse_nth_integer(&mut self) -> Result<Option<i32>, CssError> {
+        let start: usize = self.pos;
+        let digits: String = self.consume_while(|c: char| c.is_ascii_digit());
+        if digits.is_empty() {
+            return Ok(None);
+        }
+        match digits.parse::<i32>() {
+            Ok(n) => Ok(Some(n)),
+            Err(_) => {
+                self.pos = start;
+                Err(self.error(CssErrorKind::InvalidNumber, "an integer", digits))
+            }
+        }
+    }
+
+    // "..."か'...'の中身を読む。\の次の文字はそのまま入れる
+    fn parse_string(&mut self) -> Result<String, CssError> {
+        let quote: char = self.consume_char();
+        let mut value: String = String::new();
+        loop {
+            match self.next_char() {
+                Some(c) if c == quote => {
+                    self.consume_char();
+                    return Ok(value);
+                }
+                Some('\\') => {
+                    self.consume_char();
+                    if self.next_char().is_some() {
+                        value.push(self.consume_char());
+                    }
+                }
+                // 文字列の途中で改行はできない
+                Some('\n') | None => return Err(self.unexpected(&format!("'{}'", quote))),
+                Some(_) => value.push(self.consume_char()),
+            }
+        }
     }
 
     /// styleのvecを返す
-    fn parse_declarations(&mut self) -> Vec<Declaration> {
-        assert_eq!(self.consume_char(), '{');
+    fn parse_declarations(&mut self) -> Result<Vec<Declaration>, CssError> {
+        self.expect_char('{')?;
+        self.parse_declaration_list()
+    }
+
+    // {の後ろから}までのdeclarationを読む
+    fn parse_declaration_list(&mut self) -> Result<Vec<Declaration>, CssError> {
         let mut declarations: Vec<Declaration> = Vec::new();
         loop {
             self.consume_whitespace();
-            if self.next_char() == '}' {
-                self.consume_char();
-                break;
+            match self.next_char() {
+                Some('}') => {
+                    self.consume_char();
+                    break;
+                }
+                // 空のdeclaration(;;)は無視する
+                Some(';') => {
+                    self.consume_char();
+                    continue;
+                }
+                // 閉じてないブロックは最後で閉じたことにする
+                None if self.lenient => break,
+                None => return Err(self.unexpected("'}'")),
+                Some(_) => {}
+            }
+            let start: usize = self.pos;
+            match self.parse_declaration() {
+                Ok(longhands) => declarations.extend(longhands),
+                // CSS Syntax §5.4.5: おかしいdeclarationは次の;まで捨てる
+                Err(err) if self.lenient => {
+                    self.errors.push(err);
+                    self.pos = start;
+                    self.skip_declaration();
+                }
+                Err(err) => return Err(err),
             }
-            declarations.push(self.parse_declaration());
         }
-        declarations
+        Ok(declarations)
     }
 
     /// :で区切っている。なんとなくわかる。
-    fn parse_declaration(&mut self) -> Declaration {
+    /// shorthandはlonghandに分けるので、declarationは複数になることがある
+    fn parse_declaration(&mut self) -> Result<Vec<Declaration>, CssError> {
         // :まで全て消費する
-        let property_name: String = self.parse_identifier();
+        let property_name: String = self.parse_name("a property name")?;
         self.consume_whitespace();
-        assert_eq!(self.consume_char(), ':');
+        self.expect_char(':')?;
         self.consume_whitespace();
         // 値によってそのまま入れるか、違う形で保存するか決める
         // margin: auto;のautoの部分
-        let value: Value = self.parse_value();
+        // contentとcounter-*は値を並べて書けるので別に読む
+        let values: Vec<(String, Value)> = match property_name.as_str() {
+            "content" => vec![(property_name.clone(), self.parse_content()?)],
+            "counter-reset" | "counter-set" => {
+                vec![(property_name.clone(), self.parse_counter_list(0)?)]
+            }
+            "counter-increment" => vec![(property_name.clone(), self.parse_counter_list(1)?)],
+            name if shorthand::longhands(name).is_some() => self.parse_shorthand(name)?,
+            name => {
+                let allow_number: bool = NUMBER_PROPERTIES.contains(&name);
+                vec![(property_name.clone(), self.parse_value(allow_number)?)]
+            }
+        };
         self.consume_whitespace();
-        assert_eq!(self.consume_char(), ';');
+        let important: bool = self.parse_important()?;
+        // ブロックの最後のdeclarationは;を省略できる
+        // lenientのときは途中で終わったファイルもそこで閉じたことにする
+        if self.next_char() != Some('}') && !(self.lenient && self.eof()) {
+            self.expect_char(';')?;
+        }
 
-        Declaration {
-            name: property_name,
-            value: value,
+        // 分けたlonghandはどれも同じpositionにする
+        let position: usize = self.declarations;
+        self.declarations += 1;
+        Ok(values
+            .into_iter()
+            .map(|(name, value): (String, Value)| Declaration {
+                name,
+                value,
+                important,
+                position,
+            })
+            .collect())
+    }
+
+    // margin: 10px autoとかの値を全部読んでからlonghandに分ける
+    fn parse_shorthand(&mut self, name: &str) -> Result<Vec<(String, Value)>, CssError> {
+        let start: usize = self.pos;
+        let components: Vec<Component> = self.parse_components()?;
+        match shorthand::expand(name, &components) {
+            Ok(longhands) => Ok(longhands),
+            Err(expected) => {
+                let found: String = format!("'{}'", self.input[start..self.pos].trim_end());
+                self.pos = start;
+                Err(self.error(CssErrorKind::InvalidValue, expected, found))
+            }
         }
     }
+
+    // ;か}か!importantの手前まで、空白と/と,で区切って読む
+    fn parse_components(&mut self) -> Result<Vec<Component>, CssError> {
+        let mut components: Vec<Component> = Vec::new();
+        loop {
+            match self.next_char() {
+                Some(';' | '}' | '!') | None => break,
+                Some('/') => {
+                    self.consume_char();
+                    components.push(Component::Slash);
+                }
+                Some(',') => {
+                    self.consume_char();
+                    components.push(Component::Comma);
+                }
+                Some('"' | '\'') => components.push(Component::String(self.parse_string()?)),
+                Some(_) => components.push(Component::Value(self.parse_component_value(true)?)),
+            }
+            self.consume_whitespace();
+        }
+        Ok(components)
+    }
+
+    // 値のあとの!important。!と importantの間には空白やコメントがあってもいい
+    fn parse_important(&mut self) -> Result<bool, CssError> {
+        if self.next_char() != Some('!') {
+            return Ok(false);
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        let start: usize = self.pos;
+        let name: String = self.parse_name("'important'")?;
+        if !name.eq_ignore_ascii_case("important") {
+            self.pos = start;
+            return Err(self.error(
+                CssErrorKind::UnexpectedCharacter,
+                "'important'",
+                format!("'{}'", name),
+            ));
+        }
+        self.consume_whitespace();
+        Ok(true)
+    }
     // margin: auto;のautoの部分を計算する
-    fn parse_value(&mut self) -> Value {
+    // allow_numberなら単位のない数をNumberにする。redみたいな色の名前はColorValueにする
+    fn parse_value(&mut self, allow_number: bool) -> Result<Value, CssError> {
+        match self.parse_component_value(allow_number)? {
+            Value::Keyword(name) => {
+                Ok(named_color(&name).map_or(Value::Keyword(name), Value::ColorValue))
+            }
+            value => Ok(value),
+        }
+    }
+
+    // shorthandの値の1つ。font-familyやgridの名前がredのこともあるのでkeywordのままにする
+    // 色かどうかはshorthand.rsが場所を見て決める
+    fn parse_component_value(&mut self, allow_number: bool) -> Result<Value, CssError> {
         match self.next_char() {
-            '0'..='9' => self.parse_length(),
-            '#' => self.parse_color(),
-            _ => Value::Keyword(self.parse_identifier()),
+            Some('0'..='9') => self.parse_length(allow_number),
+            // -10pxや.5pxみたいに符号や小数点から始まる数字
+            Some('-' | '+' | '.')
+                if self.peek_char(1).is_some_and(|c: char| c.is_ascii_digit()) =>
+            {
+                self.parse_length(allow_number)
+            }
+            Some('#') => self.parse_color(),
+            _ => Ok(Value::Keyword(self.parse_name("a value")?)),
+        }
+    }
+
+    // content: "[" attr(title) "]" counter(item)みたいなのを読む
+    // https://drafts.csswg.org/css-content/#content-property
+    fn parse_content(&mut self) -> Result<Value, CssError> {
+        let mut items: Vec<ContentItem> = Vec::new();
+        loop {
+            match self.next_char() {
+                Some('"' | '\'') => items.push(ContentItem::String(self.parse_string()?)),
+                Some(c) if valid_identifier_char(c) => {
+                    let start: usize = self.pos;
+                    let name: String = self.parse_identifier().to_ascii_lowercase();
+                    if self.next_char() == Some('(') {
+                        items.push(self.parse_content_function(start, &name)?);
+                    } else if items.is_empty() && (name == "none" || name == "normal") {
+                        // noneとnormalは1つだけで書く
+                        return Ok(Value::Keyword(name));
+                    } else {
+                        self.pos = start;
+                        return Err(self.error(
+                            CssErrorKind::UnexpectedCharacter,
+                            "a string, attr(), counter() or counters()",
+                            format!("'{}'", name),
+                        ));
+                    }
+                }
+                _ => break,
+            }
+            self.consume_whitespace();
         }
+        if items.is_empty() {
+            return Err(self.unexpected("a content value"));
+        }
+        Ok(Value::Content(items))
     }
 
-    fn parse_length(&mut self) -> Value {
-        Value::Length(self.parse_float(), self.parse_unit())
+    // attr(name), counter(name[, style]), counters(name, "sep"[, style])の(から)まで
+    fn parse_content_function(
+        &mut self,
+        start: usize,
+        name: &str,
+    ) -> Result<ContentItem, CssError> {
+        self.expect_char('(')?;
+        self.consume_whitespace();
+        let item: ContentItem = match name {
+            "attr" => ContentItem::Attr(self.parse_name("an attribute name")?.to_ascii_lowercase()),
+            "counter" => {
+                let counter: String = self.parse_name("a counter name")?;
+                ContentItem::Counter(counter, self.parse_counter_style()?)
+            }
+            "counters" => {
+                let counter: String = self.parse_name("a counter name")?;
+                self.consume_whitespace();
+                self.expect_char(',')?;
+                self.consume_whitespace();
+                let separator: String = match self.next_char() {
+                    Some('"' | '\'') => self.parse_string()?,
+                    _ => return Err(self.unexpected("a string")),
+                };
+                ContentItem::Counters(counter, separator, self.parse_counter_style()?)
+            }
+            _ => {
+                self.pos = start;
+                return Err(self.error(
+                    CssErrorKind::UnexpectedCharacter,
+                    "attr(), counter() or counters()",
+                    format!("'{}('", name),
+                ));
+            }
+        };
+        self.consume_whitespace();
+        self.expect_char(')')?;
+        Ok(item)
     }
 
-    fn parse_float(&mut self) -> f32 {
-        let s: String = self.consume_while(|c: char| match c {
-            '0'..='9' | '.' => true,
-            _ => false,
-        });
-        s.parse().unwrap()
+    // counter()の2つ目の引数。なければdecimal
+    fn parse_counter_style(&mut self) -> Result<String, CssError> {
+        self.consume_whitespace();
+        if self.next_char() != Some(',') {
+            return Ok("decimal".to_string());
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        Ok(self.parse_name("a counter style")?.to_ascii_lowercase())
     }
 
-    // pxならそう認識する。最終的にUnitを返してる
-    fn parse_unit(&mut self) -> Unit {
-        match &*self.parse_identifier().to_ascii_lowercase() {
-            "px" => Unit::Px,
-            _ => panic!("unrecognized unit"),
+    // counter-reset: a 1 bみたいなの。数字を省略したらdefault
+    fn parse_counter_list(&mut self, default: i32) -> Result<Value, CssError> {
+        let mut counters: Vec<(String, i32)> = Vec::new();
+        while self.next_char().is_some_and(valid_identifier_char) {
+            let name: String = self.parse_identifier();
+            // noneは1つだけで書く
+            if counters.is_empty() && name.eq_ignore_ascii_case("none") {
+                return Ok(Value::Keyword("none".to_string()));
+            }
+            self.consume_whitespace();
+            let n: i32 = match self.next_char() {
+                Some('0'..='9' | '-' | '+') => self.parse_integer()?,
+                _ => default,
+            };
+            counters.push((name, n));
+            self.consume_whitespace();
+        }
+        if counters.is_empty() {
+            return Err(self.unexpected("a counter name"));
         }
+        Ok(Value::Counters(counters))
     }
 
-    // これも色をなんとかしている
-    fn parse_color(&mut self) -> Value {
-        assert_eq!(self.consume_char(), '#');
-        Value::ColorValue(Color {
-            r: self.parse_hex_pair(),
-            g: self.parse_hex_pair(),
-            b: self.parse_hex_pair(),
-            a: 255,
+    // 符号つきの整数
+    fn parse_integer(&mut self) -> Result<i32, CssError> {
+        let start: usize = self.pos;
+        let mut s: String = String::new();
+        if let Some(sign @ ('-' | '+')) = self.next_char() {
+            s.push(sign);
+            self.consume_char();
+        }
+        s.push_str(&self.consume_while(|c: char| c.is_ascii_digit()));
+        s.parse().map_err(|_| {
+            self.pos = start;
+            self.error(
+                CssErrorKind::InvalidNumber,
+                "an integer",
+                format!("'{}'", s),
+            )
         })
     }
 
-    /// 色の決定のためになんかしてる
-    fn parse_hex_pair(&mut self) -> u8 {
-        let s: &str = &self.input[self.pos..self.pos + 2];
-        self.pos += 2;
-        u8::from_str_radix(s, 16).unwrap()
+    fn parse_length(&mut self, allow_number: bool) -> Result<Value, CssError> {
+        let number: f32 = self.parse_float()?;
+        let unit_start: usize = self.pos;
+        let unit: String = self.parse_identifier();
+        match &*unit.to_ascii_lowercase() {
+            "px" => Ok(Value::Length(number, Unit::Px)),
+            "" if allow_number => Ok(Value::Number(number)),
+            // 0だけは単位を省略できる
+            "" if number == 0.0 => Ok(Value::Length(0.0, Unit::Px)),
+            "" => Err(self.unexpected("a unit")),
+            _ => {
+                self.pos = unit_start;
+                Err(self.error(CssErrorKind::UnknownUnit, "'px'", format!("'{}'", unit)))
+            }
+        }
+    }
+
+    fn parse_float(&mut self) -> Result<f32, CssError> {
+        let start: usize = self.pos;
+        let mut s: String = String::new();
+        if let Some(sign @ ('-' | '+')) = self.next_char() {
+            s.push(sign);
+            self.consume_char();
+        }
+        s.push_str(&self.consume_while(|c: char| matches!(c, '0'..='9' | '.')));
+        s.parse().map_err(|_| {
+            self.pos = start;
+            self.error(CssErrorKind::InvalidNumber, "a number", format!("'{}'", s))
+        })
+    }
+
+    // これも色をなんとかしている
+    // #rgb, #rgba, #rrggbb, #rrggbbaaの4つの書き方がある
+    fn parse_color(&mut self) -> Result<Value, CssError> {
+        let start: usize = self.pos;
+        self.expect_char('#')?;
+        let hex: String = self.consume_while(|c: char| c.is_ascii_alphanumeric());
+        let digits: Vec<u8> = hex
+            .chars()
+            .filter_map(|c: char| c.to_digit(16).map(|d: u32| d as u8))
+            .collect();
+        if digits.len() != hex.len() {
+            self.pos = start;
+            return Err(self.error(
+                CssErrorKind::InvalidColor,
+                "a hex color",
+                format!("'#{}'", hex),
+            ));
+        }
+        // 短い書き方は同じ桁を2回並べたものになる(#f00 -> #ff0000)
+        let channels: Vec<u8> = match digits.len() {
+            3 | 4 => digits.iter().map(|d: &u8| d * 17).collect(),
+            6 | 8 => digits
+                .chunks(2)
+                .map(|pair: &[u8]| pair[0] * 16 + pair[1])
+                .collect(),
+            _ => {
+                self.pos = start;
+                return Err(self.error(
+                    CssErrorKind::InvalidColor,
+                    "3, 4, 6 or 8 hex digits",
+                    format!("'#{}'", hex),
+                ));
+            }
+        };
+        Ok(Value::ColorValue(Color {
+            r: channels[0],
+            g: channels[1],
+            b: channels[2],
+            a: channels.get(3).copied().unwrap_or(255),
+        }))
     }
 
     // validateしながら消費
@@ -256,9 +1239,133 @@ impl Parser {
         self.consume_while(valid_identifier_char)
     }
 
-    // 空白を無視
+    // 空だとエラーにするparse_identifier
+    fn parse_name(&mut self, expected: &str) -> Result<String, CssError> {
+        let name: String = self.parse_identifier();
+        if name.is_empty() {
+            return Err(self.unexpected(expected));
+        }
+        Ok(name)
+    }
+
+    // 空白とコメントを無視
     fn consume_whitespace(&mut self) {
-        self.consume_while(char::is_whitespace);
+        loop {
+            self.consume_while(char::is_whitespace);
+            if !self.starts_with("/*") {
+                break;
+            }
+            match self.input[self.pos + 2..].find("*/") {
+                Some(end) => self.pos += 2 + end + 2,
+                None => self.pos = self.input.len(),
+            }
+        }
+    }
+
+    // ruleを{}のブロックの終わりまで読み飛ばす
+    fn skip_rule(&mut self) {
+        let mut depth: usize = 0;
+        while let Some(c) = self.next_char() {
+            match c {
+                '"' | '\'' => {
+                    self.skip_string();
+                    continue;
+                }
+                '{' => depth += 1,
+                '}' if depth <= 1 => {
+                    self.consume_char();
+                    break;
+                }
+                '}' => depth -= 1,
+                // ブロックの外の;はそこで終わり(@importみたいなやつ)
+                ';' if depth == 0 => {
+                    self.consume_char();
+                    break;
+                }
+                _ => {}
+            }
+            self.consume_char();
+        }
+    }
+
+    // declarationを次の;まで読み飛ばす。ブロックを閉じる}は残しておく
+    fn skip_declaration(&mut self) {
+        let mut depth: usize = 0;
+        while let Some(c) = self.next_char() {
+            match c {
+                '"' | '\'' => {
+                    self.skip_string();
+                    continue;
+                }
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' if depth > 0 => depth -= 1,
+                '}' => break,
+                ';' if depth == 0 => {
+                    self.consume_char();
+                    break;
+                }
+                _ => {}
+            }
+            self.consume_char();
+        }
+    }
+
+    // 文字列を閉じる引用符まで読み飛ばす
+    fn skip_string(&mut self) {
+        let quote: char = self.consume_char();
+        while let Some(c) = self.next_char() {
+            self.consume_char();
+            if c == '\\' && !self.eof() {
+                self.consume_char();
+            } else if c == quote || c == '\n' {
+                break;
+            }
+        }
+    }
+
+    // 次の文字がcなら消費する。違うならエラー
+    fn expect_char(&mut self, c: char) -> Result<(), CssError> {
+        if self.next_char() == Some(c) {
+            self.consume_char();
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{}'", c)))
+        }
+    }
+
+    // 今の位置の文字が期待してたものと違うときのエラーを作る
+    fn unexpected(&self, expected: &str) -> CssError {
+        match self.next_char() {
+            Some(c) => self.error(
+                CssErrorKind::UnexpectedCharacter,
+                expected,
+                format!("'{}'", c),
+            ),
+            None => self.error(CssErrorKind::UnexpectedEof, expected, "end of input"),
+        }
+    }
+
+    fn error<E: Into<String>, F: Into<String>>(
+        &self,
+        kind: CssErrorKind,
+        expected: E,
+        found: F,
+    ) -> CssError {
+        // 今の位置までに何行あって、最後の改行から何文字目かを数える
+        let before: &str = &self.input[..self.pos];
+        let line: usize = before.matches('\n').count() + 1;
+        let column: usize = match before.rfind('\n') {
+            Some(i) => before[i + 1..].chars().count() + 1,
+            None => before.chars().count() + 1,
+        };
+        CssError {
+            kind,
+            byte_offset: self.pos,
+            line,
+            column,
+            expected: expected.into(),
+            found: found.into(),
+        }
     }
 
     // まあ、htmlの方と同じ
@@ -267,24 +1374,36 @@ impl Parser {
         F: Fn(char) -> bool,
     {
         let mut result: String = String::new();
-        while !self.eof() && test(self.next_char()) {
+        while let Some(c) = self.next_char() {
+            if !test(c) {
+                break;
+            }
             result.push(self.consume_char());
         }
         result
     }
 
-    //　読んだ文字を返して、次にposを進める
+    //　読んだ文字を返して、次にposを進める。呼ぶ前にeofでないことを確認しておく
     fn consume_char(&mut self) -> char {
         let mut iter: std::str::CharIndices = self.input[self.pos..].char_indices();
         let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
+        let (next_pos, _) = iter.next().unwrap_or((cur_char.len_utf8(), ' '));
         self.pos += next_pos;
         cur_char
     }
 
-    // 次に進むことなく次の文字を読む
-    fn next_char(&self) -> char {
-        self.input[self.pos..].chars().next().unwrap()
+    // 次に進むことなく次の文字を読む。最後まで読んでたらNone
+    fn next_char(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    // n文字先を読む
+    fn peek_char(&self, n: usize) -> Option<char> {
+        self.input[self.pos..].chars().nth(n)
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.input[self.pos..].starts_with(s)
     }
 
     /// 全て読み終わったらtrueを返す
@@ -293,6 +1412,170 @@ impl Parser {
     }
 }
 
+// 単位のない数を書けるproperty
+const NUMBER_PROPERTIES: [&str; 7] = [
+    "flex-grow",
+    "flex-shrink",
+    "font-weight",
+    "line-height",
+    "opacity",
+    "order",
+    "z-index",
+];
+
+// https://drafts.csswg.org/css-color/#named-colors
+// binary_searchするので名前順に並べておく
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
 // margin: auto;みたいなのでmarginとautoをとってくるためのvaidator
 // .style #style　とかのstyleの部分だけ取ってくる用途でも使ってる
 fn valid_identifier_char(c: char) -> bool {
@@ -301,3 +1584,279 @@ fn valid_identifier_char(c: char) -> bool {
         _ => false,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ruleごとにdeclarationの名前を並べる
+    fn names(sheet: &Stylesheet) -> Vec<Vec<&str>> {
+        sheet
+            .rules
+            .iter()
+            .map(|rule: &Rule| {
+                rule.declarations
+                    .iter()
+                    .map(|d: &Declaration| d.name.as_str())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn strict_parse_reports_position() {
+        let err: CssError =
+            parse("p { color: #fff; }\ndiv { width: 10em; }".to_string()).unwrap_err();
+        assert_eq!(err.kind, CssErrorKind::UnknownUnit);
+        assert_eq!((err.line, err.column, err.byte_offset), (2, 16, 34));
+        assert_eq!(
+            err.to_string(),
+            "2:16: unknown unit: expected 'px', found 'em'"
+        );
+
+        let err: CssError = parse("p { color: #ff; }".to_string()).unwrap_err();
+        assert_eq!(err.kind, CssErrorKind::InvalidColor);
+        assert_eq!(err.column, 12);
+    }
+
+    // おかしいdeclarationだけ捨てて、同じruleの残りは使う
+    #[test]
+    fn lenient_skips_bad_declaration() {
+        let (sheet, errors): (Stylesheet, Vec<CssError>) = parse_lenient(
+            "p { width: 10em; color: #fff; margin: (a; b) ; height: 1px }".to_string(),
+        );
+        assert_eq!(names(&sheet), vec![vec!["color", "height"]]);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind, CssErrorKind::UnknownUnit);
+    }
+
+    // @mediaはブロックごと読み飛ばして、次のruleから続ける
+    #[test]
+    fn lenient_skips_at_rule_block() {
+        let (sheet, errors): (Stylesheet, Vec<CssError>) = parse_lenient(
+            "@media screen { p { color: #fff; } }\n@import 'a.css';\ndiv { width: 1px; }"
+                .to_string(),
+        );
+        assert_eq!(names(&sheet), vec![vec!["width"]]);
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].line, errors[0].column), (1, 1));
+        assert_eq!((errors[1].line, errors[1].column), (2, 1));
+    }
+
+    // 閉じてないブロックはファイルの終わりで閉じたことにする
+    #[test]
+    fn lenient_closes_unclosed_block() {
+        let (sheet, errors): (Stylesheet, Vec<CssError>) =
+            parse_lenient("p { color: #fff; width: 1px".to_string());
+        assert_eq!(names(&sheet), vec![vec!["color", "width"]]);
+        assert!(errors.is_empty());
+        assert_eq!(
+            parse("p { color: #fff".to_string()).unwrap_err().kind,
+            CssErrorKind::UnexpectedEof
+        );
+    }
+
+    fn complex(css: &str) -> ComplexSelector {
+        let mut sheet: Stylesheet = parse(format!("{} {{}}", css)).unwrap();
+        match sheet.rules.remove(0).selectors.remove(0) {
+            Selector::Complex(selector) => selector,
+            Selector::Simple(_) => panic!("expected a complex selector: {}", css),
+        }
+    }
+
+    fn tag_names(selector: &ComplexSelector) -> Vec<&str> {
+        selector
+            .compounds
+            .iter()
+            .map(|compound: &SimpleSelector| compound.tag_name.as_deref().unwrap_or("*"))
+            .collect()
+    }
+
+    #[test]
+    fn parse_combinators() {
+        let selector: ComplexSelector = complex("div > ul li+p ~ *.a");
+        assert_eq!(tag_names(&selector), vec!["div", "ul", "li", "p", "*"]);
+        assert_eq!(
+            selector.combinators,
+            vec![
+                Combinator::Child,
+                Combinator::Descendant,
+                Combinator::NextSibling,
+                Combinator::SubsequentSibling,
+            ]
+        );
+        // 空白がなくても、前後に余計な空白があっても同じ
+        assert_eq!(complex("div>p").combinators, vec![Combinator::Child]);
+        assert_eq!(
+            complex("h1  +\n p").combinators,
+            vec![Combinator::NextSibling]
+        );
+        // specificityは全部のcompoundを足したもの
+        assert_eq!(
+            Selector::Complex(complex("div#a > p.b ~ p")).specificity(),
+            (1, 1, 3)
+        );
+    }
+
+    #[test]
+    fn dangling_combinator_is_an_error() {
+        assert!(parse("div > { width: 1px }".to_string()).is_err());
+        assert!(parse("> p { width: 1px }".to_string()).is_err());
+        assert!(parse("h1 + + p { width: 1px }".to_string()).is_err());
+    }
+
+    fn attribute(css: &str) -> AttributeSelector {
+        let mut sheet: Stylesheet = parse(format!("{} {{}}", css)).unwrap();
+        match sheet.rules.remove(0).selectors.remove(0) {
+            Selector::Simple(mut selector) => selector.attributes.remove(0),
+            Selector::Complex(_) => panic!("expected a simple selector: {}", css),
+        }
+    }
+
+    #[test]
+    fn parse_attribute_selectors() {
+        let selector: AttributeSelector = attribute("[ DATA-X ]");
+        assert_eq!(selector.name, "data-x");
+        assert_eq!(selector.matcher, None);
+
+        let operators: [(&str, AttributeOperator); 6] = [
+            ("=", AttributeOperator::Equals),
+            ("~=", AttributeOperator::Includes),
+            ("|=", AttributeOperator::DashMatch),
+            ("^=", AttributeOperator::Prefix),
+            ("$=", AttributeOperator::Suffix),
+            ("*=", AttributeOperator::Substring),
+        ];
+        for (text, operator) in operators {
+            let selector: AttributeSelector = attribute(&format!("[a{}b]", text));
+            assert_eq!(selector.matcher, Some((operator, "b".to_string())));
+            assert_eq!(selector.flag, None);
+        }
+
+        let selector: AttributeSelector = attribute(r#"a[title = "x \"y\"" i]"#);
+        assert_eq!(
+            selector.matcher,
+            Some((AttributeOperator::Equals, "x \"y\"".to_string()))
+        );
+        assert_eq!(selector.flag, Some(CaseFlag::Insensitive));
+        assert_eq!(attribute("[a='b' S]").flag, Some(CaseFlag::Sensitive));
+        // attribute selectorはclassと同じ重さ
+        assert_eq!(
+            parse("a[href][title] {}".to_string()).unwrap().rules[0].selectors[0].specificity(),
+            (0, 2, 1)
+        );
+    }
+
+    #[test]
+    fn invalid_attribute_selectors() {
+        assert!(parse("[a=] {}".to_string()).is_err());
+        assert!(parse("[a~b] {}".to_string()).is_err());
+        assert!(parse("[a=b x] {}".to_string()).is_err());
+        assert!(parse("[a='b] {}".to_string()).is_err());
+    }
+
+    // :nth-child(...)の中身を(A, B)にする
+    fn nth(expression: &str) -> Result<(i32, i32), CssError> {
+        let mut sheet: Stylesheet = parse(format!("li:nth-child({}) {{}}", expression))?;
+        match sheet.rules.remove(0).selectors.remove(0) {
+            Selector::Simple(mut selector) => match selector.pseudo_classes.remove(0) {
+                PseudoClass::NthChild(a, b) => Ok((a, b)),
+                other => panic!("expected :nth-child, got {:?}", other),
+            },
+            Selector::Complex(_) => panic!("expected a simple selector"),
+        }
+    }
+
+    #[test]
+    fn parse_an_plus_b() {
+        assert_eq!(nth("odd").unwrap(), (2, 1));
+        assert_eq!(nth("EVEN").unwrap(), (2, 0));
+        assert_eq!(nth("3").unwrap(), (0, 3));
+        assert_eq!(nth("-n+3").unwrap(), (-1, 3));
+        assert_eq!(nth("+n").unwrap(), (1, 0));
+        assert_eq!(nth(" 2n - 1 ").unwrap(), (2, -1));
+        assert_eq!(nth("-2n+ 4").unwrap(), (-2, 4));
+        assert_eq!(nth("n-0").unwrap(), (1, 0));
+    }
+
+    #[test]
+    fn invalid_an_plus_b() {
+        assert!(nth("2nd").is_err());
+        assert!(nth("3n+").is_err());
+        assert!(nth("n + -1").is_err());
+        assert!(nth("2 n").is_err());
+        assert!(nth("").is_err());
+    }
+
+    fn specificity(css: &str) -> Specificity {
+        parse(format!("{} {{}}", css)).unwrap().rules[0].selectors[0].specificity()
+    }
+
+    #[test]
+    fn pseudo_class_specificity() {
+        assert_eq!(specificity("li:first-child"), (0, 1, 1));
+        assert_eq!(specificity("li:nth-child(2n+1)"), (0, 1, 1));
+        // :whereの中身は数えない
+        assert_eq!(specificity(":where(#a, .b.c)"), (0, 0, 0));
+        assert_eq!(specificity("p:where(#a)"), (0, 0, 1));
+        // :isと:notは中で一番強いものになる
+        assert_eq!(specificity(":is(#a, .b)"), (1, 0, 0));
+        assert_eq!(specificity("p:not(.a, div.b)"), (0, 1, 2));
+    }
+
+    fn content(css: &str) -> Result<Value, CssError> {
+        let mut sheet: Stylesheet = parse(format!("p::before {{ content: {} }}", css))?;
+        Ok(sheet.rules.remove(0).declarations.remove(0).value)
+    }
+
+    #[test]
+    fn parse_content_values() {
+        assert_eq!(
+            content(r#""a\"b" attr(DATA-X) counter(n) counters(n, ".", upper-roman)"#).unwrap(),
+            Value::Content(vec![
+                ContentItem::String("a\"b".to_string()),
+                ContentItem::Attr("data-x".to_string()),
+                ContentItem::Counter("n".to_string(), "decimal".to_string()),
+                ContentItem::Counters("n".to_string(), ".".to_string(), "upper-roman".to_string()),
+            ])
+        );
+        assert_eq!(content("none").unwrap(), Value::Keyword("none".to_string()));
+        assert!(content("'a' none").is_err());
+        assert!(content("url(x.png)").is_err());
+        assert!(content("counters(n)").is_err());
+    }
+
+    // shorthandはparseのときにlonghandに分かれる
+    #[test]
+    fn shorthands_expand_when_parsed() {
+        let sheet: Stylesheet =
+            parse("p { margin: 1px 2px; font: bold 12px/1.5 serif !important }".to_string())
+                .unwrap();
+        let declarations: &[Declaration] = &sheet.rules[0].declarations;
+        assert_eq!(
+            names(&sheet),
+            vec![vec![
+                "margin-top",
+                "margin-right",
+                "margin-bottom",
+                "margin-left",
+                "font-style",
+                "font-variant",
+                "font-weight",
+                "font-size",
+                "line-height",
+                "font-family",
+            ]]
+        );
+        assert_eq!(declarations[1].value, Value::Length(2.0, Unit::Px));
+        assert!(declarations[4..].iter().all(|d: &Declaration| d.important));
+        assert!(!declarations[0].important);
+
+        let err: CssError = parse("p { margin: 1px solid }".to_string()).unwrap_err();
+        assert_eq!(err.kind, CssErrorKind::InvalidValue);
+        let (sheet, errors): (Stylesheet, Vec<CssError>) =
+            parse_lenient("p { padding: auto; width: 1px }".to_string());
+        assert_eq!(names(&sheet), vec![vec!["width"]]);
+        assert_eq!(errors.len(), 1);
+    }
+}
@@ -1,22 +1,138 @@
 use std::collections::{HashMap, HashSet};
 
 pub type AttrMap = HashMap<String, String>;
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ElementData {
     pub tag_name: String,
     pub attributes: AttrMap,
+    // :hoverとかで見る、ユーザーの操作で変わる状態
+    pub state: ElementState,
 }
-#[derive(Debug)]
+
+// 要素の状態のビットの集まり
+// ElementState::HOVER | ElementState::FOCUSみたいに重ねられる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ElementState(u8);
+
+impl ElementState {
+    pub const HOVER: ElementState = ElementState(1 << 0);
+    pub const FOCUS: ElementState = ElementState(1 << 1);
+    pub const ACTIVE: ElementState = ElementState(1 << 2);
+    pub const CHECKED: ElementState = ElementState(1 << 3);
+    pub const DISABLED: ElementState = ElementState(1 << 4);
+    pub const VISITED: ElementState = ElementState(1 << 5);
+    // hoverとactiveを直接付けられた要素。祖先のは子孫から決めるので区別しておく
+    const HOVER_TARGET: ElementState = ElementState(1 << 6);
+    const ACTIVE_TARGET: ElementState = ElementState(1 << 7);
+    // 祖先にも伝わる状態と、それを直接付けられたときの印
+    const PROPAGATED: [(ElementState, ElementState); 2] = [
+        (ElementState::HOVER, ElementState::HOVER_TARGET),
+        (ElementState::ACTIVE, ElementState::ACTIVE_TARGET),
+    ];
+
+    pub fn empty() -> ElementState {
+        ElementState(0)
+    }
+
+    // otherのビットが全部立っていればtrue
+    pub fn contains(self, other: ElementState) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: ElementState) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: ElementState) {
+        self.0 &= !other.0;
+    }
+
+    pub fn set(&mut self, other: ElementState, on: bool) {
+        if on {
+            self.insert(other);
+        } else {
+            self.remove(other);
+        }
+    }
+
+    // checkedとdisabledは最初はattributeで決まる
+    fn from_attributes(tag_name: &str, attributes: &AttrMap) -> ElementState {
+        let mut state: ElementState = ElementState::empty();
+        // 古いparserはタグ名の大文字をそのまま残すので小文字にして比べる
+        let tag_name: &str = &tag_name.to_ascii_lowercase();
+        let checkable: bool = match tag_name {
+            "input" => attributes.get("type").is_some_and(|kind: &String| {
+                kind.eq_ignore_ascii_case("checkbox") || kind.eq_ignore_ascii_case("radio")
+            }),
+            _ => false,
+        };
+        if checkable && attributes.contains_key("checked")
+            || tag_name == "option" && attributes.contains_key("selected")
+        {
+            state.insert(ElementState::CHECKED);
+        }
+        if is_form_control(tag_name) && attributes.contains_key("disabled") {
+            state.insert(ElementState::DISABLED);
+        }
+        state
+    }
+}
+
+impl std::ops::BitOr for ElementState {
+    type Output = ElementState;
+
+    fn bitor(self, other: ElementState) -> ElementState {
+        ElementState(self.0 | other.0)
+    }
+}
+
+/// disabledにできる要素
+/// https://html.spec.whatwg.org/multipage/semantics-other.html#concept-element-disabled
+pub fn is_form_control(tag_name: &str) -> bool {
+    matches!(
+        tag_name,
+        "button" | "input" | "select" | "textarea" | "optgroup" | "option" | "fieldset"
+    )
+}
+#[derive(Debug, Clone)]
 pub enum NodeType {
     Text(String),
     Element(ElementData),
+    // <!-- -->の中身。描画はされない
+    Comment(String),
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Node {
     pub children: Vec<Node>,
     pub node_type: NodeType,
 }
 
+// <!DOCTYPE html PUBLIC "..." "...">の中身
+#[derive(Debug, Clone, PartialEq)]
+pub struct Doctype {
+    pub name: String,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+}
+
+// doctypeによってどのモードで描画するか変わる
+// https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuirksMode {
+    // 標準モード
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+// parseしたドキュメント全体。rootの外にあるdoctypeの情報も持ってる
+#[derive(Debug)]
+pub struct Document {
+    pub doctype: Option<Doctype>,
+    pub mode: QuirksMode,
+    pub root: Node,
+}
+
 pub fn text(data: String) -> Node {
     Node {
         children: Vec::new(),
@@ -24,10 +140,18 @@ pub fn text(data: String) -> Node {
     }
 }
 
+pub fn comment(data: String) -> Node {
+    Node {
+        children: Vec::new(),
+        node_type: NodeType::Comment(data),
+    }
+}
+
 pub fn elem(name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
     Node {
-        children: children,
+        children,
         node_type: NodeType::Element(ElementData {
+            state: ElementState::from_attributes(&name, &attrs),
             tag_name: name,
             attributes: attrs,
         }),
@@ -46,3 +170,123 @@ impl ElementData {
         }
     }
 }
+
+impl Node {
+    /// children[path[0]].children[path[1]]...の要素のstateを付けたり外したりする
+    /// hoverとactiveは祖先にも付く。外すときは、他の子孫がまだhoverしていれば祖先には残す
+    /// pathが要素を指していなければfalseを返す
+    pub fn set_element_state(&mut self, path: &[usize], state: ElementState, on: bool) -> bool {
+        let found: bool = match path.split_first() {
+            None => matches!(self.node_type, NodeType::Element(_)),
+            Some((&index, rest)) => self
+                .children
+                .get_mut(index)
+                .is_some_and(|child: &mut Node| child.set_element_state(rest, state, on)),
+        };
+        if !found {
+            return false;
+        }
+        // 子の要素のどれかに付いている状態
+        let children_state: ElementState = self
+            .children
+            .iter()
+            .filter_map(|child: &Node| match child.node_type {
+                NodeType::Element(ref elem) => Some(elem.state),
+                _ => None,
+            })
+            .fold(ElementState::empty(), |a: ElementState, b: ElementState| {
+                a | b
+            });
+        if let NodeType::Element(ref mut elem) = self.node_type {
+            if path.is_empty() {
+                elem.state.set(state, on);
+                for (propagated, target) in ElementState::PROPAGATED {
+                    if state.contains(propagated) {
+                        elem.state.set(target, on);
+                    }
+                }
+            }
+            // 直接付けられたか、子のどれかに付いていればhoverやactive
+            for (propagated, target) in ElementState::PROPAGATED {
+                let on: bool = elem.state.contains(target) || children_state.contains(propagated);
+                elem.state.set(propagated, on);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn div(children: Vec<Node>) -> Node {
+        elem("div".to_string(), AttrMap::new(), children)
+    }
+
+    fn state(root: &Node, path: &[usize]) -> ElementState {
+        let node: &Node = path
+            .iter()
+            .fold(root, |node: &Node, &index: &usize| &node.children[index]);
+        match node.node_type {
+            NodeType::Element(ref elem) => elem.state,
+            _ => panic!("not an element"),
+        }
+    }
+
+    #[test]
+    fn hover_propagates_to_ancestors() {
+        let mut root: Node = div(vec![div(vec![div(vec![])])]);
+        assert!(root.set_element_state(&[0, 0], ElementState::HOVER, true));
+        assert!(state(&root, &[]).contains(ElementState::HOVER));
+        assert!(state(&root, &[0]).contains(ElementState::HOVER));
+        assert!(root.set_element_state(&[0, 0], ElementState::HOVER, false));
+        assert!(!state(&root, &[]).contains(ElementState::HOVER));
+        assert!(!state(&root, &[0]).contains(ElementState::HOVER));
+    }
+
+    #[test]
+    fn clearing_keeps_ancestor_with_other_hovered_descendant() {
+        let mut root: Node = div(vec![div(vec![]), div(vec![])]);
+        root.set_element_state(&[0], ElementState::HOVER, true);
+        root.set_element_state(&[1], ElementState::HOVER, true);
+        root.set_element_state(&[0], ElementState::HOVER, false);
+        assert!(!state(&root, &[0]).contains(ElementState::HOVER));
+        assert!(state(&root, &[1]).contains(ElementState::HOVER));
+        assert!(state(&root, &[]).contains(ElementState::HOVER));
+    }
+
+    #[test]
+    fn clearing_descendant_keeps_directly_active_ancestor() {
+        let mut root: Node = div(vec![div(vec![])]);
+        root.set_element_state(&[], ElementState::ACTIVE, true);
+        root.set_element_state(&[0], ElementState::ACTIVE, true);
+        root.set_element_state(&[0], ElementState::ACTIVE, false);
+        assert!(state(&root, &[]).contains(ElementState::ACTIVE));
+        root.set_element_state(&[], ElementState::ACTIVE, false);
+        assert!(!state(&root, &[]).contains(ElementState::ACTIVE));
+    }
+
+    #[test]
+    fn focus_does_not_propagate() {
+        let mut root: Node = div(vec![div(vec![])]);
+        root.set_element_state(&[0], ElementState::FOCUS, true);
+        assert!(!state(&root, &[]).contains(ElementState::FOCUS));
+        assert!(!root.set_element_state(&[3], ElementState::FOCUS, true));
+    }
+
+    #[test]
+    fn checked_and_disabled_from_attributes() {
+        let mut attributes: AttrMap = AttrMap::new();
+        attributes.insert("type".to_string(), "CHECKBOX".to_string());
+        attributes.insert("checked".to_string(), String::new());
+        attributes.insert("disabled".to_string(), String::new());
+        let input: Node = elem("input".to_string(), attributes.clone(), Vec::new());
+        assert!(state(&input, &[]).contains(ElementState::CHECKED | ElementState::DISABLED));
+        // divはdisabledにできないし、checkedも付かない
+        assert_eq!(
+            state(&elem("div".to_string(), attributes, Vec::new()), &[]),
+            ElementState::empty()
+        );
+    }
+}
@@ -1,30 +1,105 @@
-use crate::dom;
-use std::collections::HashMap;
+use crate::{dom, entities};
+use std::{collections::HashMap, error, fmt};
 
 pub struct Parser {
     pub pos: usize,    // posは現在のinputの位置を保存する
     pub input: String, // parseする文字列？
 }
 
+// parseに失敗したときのエラー。どこで何を期待して何があったかを持ってる
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub byte_offset: usize,
+    // lineとcolumnは1から数える。columnはバイトじゃなくて文字数
+    pub line: usize,
+    pub column: usize,
+    pub expected: String,
+    pub found: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseErrorKind {
+    // 期待してない文字が来た
+    UnexpectedCharacter,
+    // 途中で入力が終わった
+    UnexpectedEof,
+    // 閉じタグの名前が開きタグと違う
+    MismatchedEndTag,
+    // 開きタグがないのに閉じタグが来た
+    UnexpectedEndTag,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message: &str = match self.kind {
+            ParseErrorKind::UnexpectedCharacter => "unexpected character",
+            ParseErrorKind::UnexpectedEof => "unexpected end of input",
+            ParseErrorKind::MismatchedEndTag => "mismatched end tag",
+            ParseErrorKind::UnexpectedEndTag => "unexpected end tag",
+        };
+        write!(
+            f,
+            "{}:{}: {}: expected {}, found {}",
+            self.line, self.column, message, self.expected, self.found
+        )
+    }
+}
+
+impl error::Error for ParseError {}
+
 impl Parser {
     // rootのドキュメントを返す
-    pub fn parse(source: String) -> dom::Node {
-        let mut nodes: Vec<dom::Node> = Parser {
+    pub fn parse(source: String) -> Result<dom::Node, ParseError> {
+        Ok(Parser::parse_document(source)?.root)
+    }
+
+    /// rootに加えてdoctypeとそこから決まるquirks modeも返す
+    pub fn parse_document(source: String) -> Result<dom::Document, ParseError> {
+        let mut parser: Parser = Parser {
             pos: 0,
             input: source,
+        };
+
+        // doctypeより前には空白とコメントしか置けない
+        let mut nodes: Vec<dom::Node> = Vec::new();
+        let mut doctype: Option<dom::Doctype> = None;
+        loop {
+            parser.consume_whitespace();
+            if parser.starts_with("<!--") {
+                nodes.push(parser.parse_comment()?);
+            } else if parser.starts_with_ignore_case("<!doctype") {
+                doctype = Some(parser.parse_doctype()?);
+                break;
+            } else {
+                break;
+            }
+        }
+        nodes.extend(parser.parse_nodes()?);
+
+        // parse_nodesは閉じタグで止まるので、最後まで読めてないなら対応する開きタグがない
+        if !parser.eof() {
+            let found: String = parser.describe_tag_ahead();
+            return Err(parser.error(ParseErrorKind::UnexpectedEndTag, "end of input", found));
         }
-        .parse_nodes();
 
-        // 要素が一つしかないならswap_removeで最初の要素を消してる
-        if nodes.len() == 1 {
-            nodes.swap_remove(0)
+        // コメント以外の要素が一つしかないならそれをrootにする(外のコメントは捨てる)
+        let is_comment = |node: &dom::Node| matches!(node.node_type, dom::NodeType::Comment(_));
+        let root: dom::Node = if nodes.iter().filter(|node| !is_comment(node)).count() == 1 {
+            nodes.into_iter().find(|node| !is_comment(node)).unwrap()
         } else {
             dom::elem("html".to_string(), HashMap::new(), nodes)
-        }
+        };
+
+        Ok(dom::Document {
+            mode: quirks_mode(doctype.as_ref()),
+            doctype,
+            root,
+        })
     }
 
     // selfを受け取ってNodeの配列を返す
-    fn parse_nodes(&mut self) -> Vec<dom::Node> {
+    fn parse_nodes(&mut self) -> Result<Vec<dom::Node>, ParseError> {
         // 配列の初期化
         let mut nodes: Vec<dom::Node> = Vec::new();
         // 文字列の読み取りが終わる or 閉じるタグから文字列が始まってたらループから抜ける
@@ -34,92 +109,348 @@ impl Parser {
             if self.eof() || self.starts_with("</") {
                 break;
             }
+            // 途中に出てくるdoctypeは無視する
+            if self.starts_with_ignore_case("<!doctype") {
+                self.parse_doctype()?;
+                continue;
+            }
             // parse_nodeの中でまだelementがあるならparse_nodesがまた呼ばれるので再帰的にchildrenに入れられる
-            nodes.push(self.parse_node());
+            nodes.push(self.parse_node()?);
         }
-        return nodes;
+        Ok(nodes)
     }
 
     // nodeをparseする
-    fn parse_node(&mut self) -> dom::Node {
+    fn parse_node(&mut self) -> Result<dom::Node, ParseError> {
+        if self.starts_with("<!--") {
+            return self.parse_comment();
+        }
+        if self.starts_with("<![CDATA[") {
+            return self.parse_cdata();
+        }
+        // <!や<?で始まる知らないものはコメント扱いにする
+        if self.starts_with("<!") || self.starts_with("<?") {
+            return Ok(self.parse_bogus_comment());
+        }
         // 次の文字を見て'<'ならparse_element()でそれ以外ならparse_text()
         match self.next_char() {
-            '<' => self.parse_element(),
-            _ => self.parse_text(),
+            Some('<') => self.parse_element(),
+            _ => Ok(self.parse_text()),
+        }
+    }
+
+    // <!-- -->の中身をコメントにする
+    fn parse_comment(&mut self) -> Result<dom::Node, ParseError> {
+        self.pos += "<!--".len();
+        // <!-->と<!--->は空のコメントとして扱う
+        for empty_end in [">", "->"] {
+            if self.starts_with(empty_end) {
+                self.pos += empty_end.len();
+                return Ok(dom::comment(String::new()));
+            }
+        }
+        let data: String = self.consume_until("-->", "'-->'")?;
+        Ok(dom::comment(data))
+    }
+
+    // <![CDATA[ ]]>の中身はそのままテキストにする
+    fn parse_cdata(&mut self) -> Result<dom::Node, ParseError> {
+        self.pos += "<![CDATA[".len();
+        let data: String = self.consume_until("]]>", "']]>'")?;
+        Ok(dom::text(data))
+    }
+
+    // >までを中身にしたコメントを作る。閉じてなければ最後までコメントになる
+    fn parse_bogus_comment(&mut self) -> dom::Node {
+        // <?はxmlの処理命令なので?も中身に残す
+        self.pos += if self.starts_with("<?") { 1 } else { 2 };
+        let data: String = self.consume_while(|c: char| c != '>');
+        if !self.eof() {
+            self.consume_char();
+        }
+        dom::comment(data)
+    }
+
+    // <!DOCTYPE html PUBLIC "public id" "system id">を読む
+    fn parse_doctype(&mut self) -> Result<dom::Doctype, ParseError> {
+        self.pos += "<!doctype".len();
+        self.consume_whitespace();
+        let name: String = self
+            .consume_while(|c: char| !c.is_whitespace() && c != '>')
+            .to_ascii_lowercase();
+        self.consume_whitespace();
+
+        let mut public_id: Option<String> = None;
+        let mut system_id: Option<String> = None;
+        if self.starts_with_ignore_case("public") {
+            self.pos += "public".len();
+            self.consume_whitespace();
+            public_id = Some(self.parse_quoted()?);
+            self.consume_whitespace();
+            if matches!(self.next_char(), Some('"' | '\'')) {
+                system_id = Some(self.parse_quoted()?);
+            }
+        } else if self.starts_with_ignore_case("system") {
+            self.pos += "system".len();
+            self.consume_whitespace();
+            system_id = Some(self.parse_quoted()?);
+        }
+
+        // 知らないものが残ってたら>まで読み飛ばす
+        self.consume_while(|c: char| c != '>');
+        self.expect_char('>')?;
+        Ok(dom::Doctype {
+            name,
+            public_id,
+            system_id,
+        })
+    }
+
+    // </tag_name>の手前までの文字列を返す。大文字小文字は区別しない
+    fn parse_raw_text(&mut self, tag_name: &str) -> Result<String, ParseError> {
+        let rest: &str = &self.input[self.pos..];
+        let end: Option<usize> = rest
+            .match_indices("</")
+            .map(|(index, _): (usize, &str)| index)
+            .find(|&index: &usize| {
+                let after: &str = &rest[index + 2..];
+                after
+                    .get(..tag_name.len())
+                    .is_some_and(|name: &str| name.eq_ignore_ascii_case(tag_name))
+                    && after[tag_name.len()..]
+                        .chars()
+                        .next()
+                        .is_none_or(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            });
+        match end {
+            Some(len) => {
+                let text: String = rest[..len].to_string();
+                self.pos += len;
+                Ok(text)
+            }
+            None => {
+                self.pos = self.input.len();
+                Err(self.error(
+                    ParseErrorKind::UnexpectedEof,
+                    format!("'</{}>'", tag_name),
+                    "end of input",
+                ))
+            }
+        }
+    }
+
+    // endが出てくるまでの文字列を返して、endの後ろまで進める
+    fn consume_until(&mut self, end: &str, expected: &str) -> Result<String, ParseError> {
+        match self.input[self.pos..].find(end) {
+            Some(len) => {
+                let data: String = self.input[self.pos..self.pos + len].to_string();
+                self.pos += len + end.len();
+                Ok(data)
+            }
+            None => {
+                self.pos = self.input.len();
+                Err(self.error(ParseErrorKind::UnexpectedEof, expected, "end of input"))
+            }
         }
     }
 
     // elementをparseする
-    fn parse_element(&mut self) -> dom::Node {
+    fn parse_element(&mut self) -> Result<dom::Node, ParseError> {
         // '<'でないならエラー
-        assert!(self.consume_char() == '<');
+        self.expect_char('<')?;
         // '<' から始まっているので 空白か'/>'がきたら終わってタグ名が取れる
-        let tag_name: String = self.parse_tag_name();
+        let tag_name: String = self.parse_tag_name()?;
+
+        let attrs: HashMap<String, String> = self.parse_attributes()?;
+
+        // <br/>みたいに/>で閉じてるなら中身も閉じタグもない
+        if self.starts_with("/>") {
+            self.pos += 2;
+            return Ok(dom::elem(tag_name, attrs, Vec::new()));
+        }
+        self.expect_char('>')?;
 
-        let attrs: HashMap<String, String> = self.parse_attributes();
-        assert!(self.consume_char() == '>');
+        // <br>や<img>みたいなvoid要素は中身を持てないので閉じタグを探さない
+        if is_void_element(&tag_name) {
+            return Ok(dom::elem(tag_name, attrs, Vec::new()));
+        }
 
         // 中身、Nodeの中のchildrenにNodeが入るのはこれ
-        let children: Vec<dom::Node> = self.parse_nodes();
+        // <script>とかの中身はタグとして読まないで閉じタグまでをそのまま文字にする
+        let children: Vec<dom::Node> = if let Some(escapable) = raw_text_kind(&tag_name) {
+            let text: String = self.parse_raw_text(&tag_name)?;
+            let text: String = if escapable {
+                entities::decode(&text, false)
+            } else {
+                text
+            };
+            if text.is_empty() {
+                Vec::new()
+            } else {
+                vec![dom::text(text)]
+            }
+        } else {
+            self.parse_nodes()?
+        };
 
         // '</'がないなら閉じてないのならエラー
-        assert!(self.consume_char() == '<');
-        assert!(self.consume_char() == '/');
-        assert!(self.parse_tag_name() == tag_name);
-        assert!(self.consume_char() == '>');
+        if self.eof() {
+            return Err(self.error(
+                ParseErrorKind::UnexpectedEof,
+                format!("'</{}>'", tag_name),
+                "end of input",
+            ));
+        }
+        let end_tag_start: usize = self.pos;
+        self.expect_char('<')?;
+        self.expect_char('/')?;
+        let end_tag_name: String = self.consume_while(is_tag_name_char);
+        if !end_tag_name.eq_ignore_ascii_case(&tag_name) {
+            self.pos = end_tag_start;
+            let found: String = self.describe_tag_ahead();
+            return Err(self.error(
+                ParseErrorKind::MismatchedEndTag,
+                format!("'</{}>'", tag_name),
+                found,
+            ));
+        }
+        self.expect_char('>')?;
 
-        return dom::elem(tag_name, attrs, children);
+        Ok(dom::elem(tag_name, attrs, children))
     }
 
     // タグの名前をとってくる
-    fn parse_tag_name(&mut self) -> String {
-        self.consume_while(|c: char| match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' => true,
-            _ => false,
-        })
+    // HTMLのタグ名は大文字小文字を区別しないので小文字にそろえる
+    fn parse_tag_name(&mut self) -> Result<String, ParseError> {
+        let name: String = self.consume_while(is_tag_name_char);
+        if name.is_empty() {
+            return Err(self.unexpected("a tag name"));
+        }
+        Ok(name.to_ascii_lowercase())
     }
 
     // attributesを見る
-    fn parse_attributes(&mut self) -> dom::AttrMap {
+    fn parse_attributes(&mut self) -> Result<dom::AttrMap, ParseError> {
         let mut attributes: HashMap<String, String> = HashMap::new();
         loop {
             self.consume_whitespace();
             // タグが終了するまで見る
-            if self.next_char() == '>' {
+            if self.next_char() == Some('>') || self.starts_with("/>") || self.eof() {
                 break;
             }
             // parse_attr()で次のattributesまで飛んでる
-            let (name, value) = self.parse_attr();
-            attributes.insert(name, value);
+            let (name, value) = self.parse_attr()?;
+            // 同じ名前が2回出てきたら最初のものを使う
+            attributes.entry(name).or_insert(value);
         }
-        return attributes;
+        Ok(attributes)
     }
 
     // class = "className"とかとってきてくれる
-    fn parse_attr(&mut self) -> (String, String) {
-        println!("parse_attr");
-        // '='になるまで見るのでtag_nameを見れる
-        let name: String = self.parse_tag_name();
-        assert!(self.consume_char() == '=');
-        // valueはどこで終わる？
-        let value: String = self.parse_attr_value();
-        return (name, value);
-    }
-
-    // class = "className"の""の中をとってきてくれる
-    fn parse_attr_value(&mut self) -> String {
-        let open_quote: char = self.consume_char();
+    // disabledみたいに値がないときは空文字列にする
+    fn parse_attr(&mut self) -> Result<(String, String), ParseError> {
+        // 名前は大文字小文字を区別しないので小文字にそろえる
+        let name: String = self.consume_while(is_attr_name_char).to_ascii_lowercase();
+        if name.is_empty() {
+            return Err(self.unexpected("an attribute name or '>'"));
+        }
+        // '='の前後には空白があってもいい
+        self.consume_whitespace();
+        if self.next_char() != Some('=') {
+            return Ok((name, String::new()));
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        let value: String = self.parse_attr_value()?;
+        Ok((name, value))
+    }
+
+    // class = "className"の""の中とか、colspan=2の2をとってきてくれる
+    fn parse_attr_value(&mut self) -> Result<String, ParseError> {
+        let value: String = match self.next_char() {
+            Some('"' | '\'') => self.parse_quoted()?,
+            // クォートなしなら空白か'>'まで
+            _ => {
+                let value: String = self.consume_while(|c: char| !c.is_whitespace() && c != '>');
+                if value.is_empty() {
+                    return Err(self.unexpected("an attribute value"));
+                }
+                value
+            }
+        };
+        Ok(entities::decode(&value, true))
+    }
+
+    // "..."か'...'の中身をとってくる
+    fn parse_quoted(&mut self) -> Result<String, ParseError> {
         // ' とか " でないとエラー
-        assert!(open_quote == '"' || open_quote == '\'');
+        let open_quote: char = match self.next_char() {
+            Some(c) if c == '"' || c == '\'' => self.consume_char(),
+            _ => return Err(self.unexpected("a quoted string")),
+        };
         // ' or " まで消費する
         let value: String = self.consume_while(|c: char| c != open_quote);
-        assert!(self.consume_char() == open_quote);
-        return value;
+        self.expect_char(open_quote)?;
+        Ok(value)
+    }
+
+    // 次の文字がcなら消費する。違うならエラー
+    fn expect_char(&mut self, c: char) -> Result<(), ParseError> {
+        if self.next_char() == Some(c) {
+            self.consume_char();
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{}'", c)))
+        }
+    }
+
+    // 今の位置の文字が期待してたものと違うときのエラーを作る
+    fn unexpected(&self, expected: &str) -> ParseError {
+        match self.next_char() {
+            Some(c) => self.error(
+                ParseErrorKind::UnexpectedCharacter,
+                expected,
+                format!("'{}'", c),
+            ),
+            None => self.error(ParseErrorKind::UnexpectedEof, expected, "end of input"),
+        }
+    }
+
+    fn error<E: Into<String>, F: Into<String>>(
+        &self,
+        kind: ParseErrorKind,
+        expected: E,
+        found: F,
+    ) -> ParseError {
+        // 今の位置までに何行あって、最後の改行から何文字目かを数える
+        let before: &str = &self.input[..self.pos];
+        let line: usize = before.matches('\n').count() + 1;
+        let column: usize = match before.rfind('\n') {
+            Some(i) => before[i + 1..].chars().count() + 1,
+            None => before.chars().count() + 1,
+        };
+        ParseError {
+            kind,
+            byte_offset: self.pos,
+            line,
+            column,
+            expected: expected.into(),
+            found: found.into(),
+        }
     }
 
-    // 次の文字の値を見る
-    fn next_char(&self) -> char {
-        self.input[self.pos..].chars().next().unwrap()
+    // エラーメッセージ用に、今の位置から始まるタグを'>'まで切り出す
+    fn describe_tag_ahead(&self) -> String {
+        let rest: &str = &self.input[self.pos..];
+        match rest.find('>') {
+            Some(end) => format!("'{}'", &rest[..=end]),
+            None => format!("'{}'", rest),
+        }
+    }
+
+    // 次の文字の値を見る。最後まで読んでたらNone
+    fn next_char(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
     }
 
     // inputがsで始まっているのかどうか見る
@@ -127,22 +458,30 @@ impl Parser {
         self.input[self.pos..].starts_with(s)
     }
 
+    // 大文字小文字を区別しないstarts_with。sはASCIIだけ
+    fn starts_with_ignore_case(&self, s: &str) -> bool {
+        self.input.as_bytes()[self.pos..]
+            .get(..s.len())
+            .is_some_and(|head: &[u8]| head.eq_ignore_ascii_case(s.as_bytes()))
+    }
+
     //　全ての文字列を見終わったかどうか見る
     fn eof(&self) -> bool {
         self.pos >= self.input.len()
     }
 
+    // 呼ぶ前にeofでないことを確認しておく
     fn consume_char(&mut self) -> char {
         // 文字列をスライスにしている
         let mut iter: std::str::CharIndices = self.input[self.pos..].char_indices();
         // 現在の文字を1つ後ろにずらして保存
         let (_, cur_char) = iter.next().unwrap();
-        // 次の文字の位置を取得している(iterはすでに見られた値は入っていないので常に1になる？)
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
+        // 次の文字の位置を取得している(最後の文字ならその文字のバイト数だけ進める)
+        let (next_pos, _) = iter.next().unwrap_or((cur_char.len_utf8(), ' '));
         // 現在の位置に次の文字の位置を足している（だいたい１）
         self.pos += next_pos;
         // 現在の見ている文字を返している
-        return cur_char;
+        cur_char
     }
 
     fn consume_while<F>(&mut self, test: F) -> String
@@ -153,11 +492,14 @@ impl Parser {
         // 空のString型を作る
         let mut result: String = String::new();
         // 見る文字列がなくなる and 関数の条件を満たさなくなる　まではループ
-        while !self.eof() && test(self.next_char()) {
+        while let Some(c) = self.next_char() {
+            if !test(c) {
+                break;
+            }
             // 見た文字列をresultに入れてる
             result.push(self.consume_char());
         }
-        return result;
+        result
     }
     // 空白が出た時点でループを中断する
     fn consume_whitespace(&mut self) {
@@ -165,7 +507,385 @@ impl Parser {
     }
 
     // 次の文字列が'<'でないところまでの文字列を返す
+    // &amp;とかの文字参照はここでデコードする
     fn parse_text(&mut self) -> dom::Node {
-        dom::text(self.consume_while(|c: char| c != '<'))
+        let text: String = self.consume_while(|c: char| c != '<');
+        dom::text(entities::decode(&text, false))
+    }
+}
+
+// 中身をタグとして読まない要素
+// https://html.spec.whatwg.org/multipage/syntax.html#elements-2
+// Some(false)ならraw text element、Some(true)なら文字参照だけはデコードするescapable raw text element
+fn raw_text_kind(tag_name: &str) -> Option<bool> {
+    match tag_name.to_ascii_lowercase().as_str() {
+        "script" | "style" => Some(false),
+        "textarea" | "title" => Some(true),
+        _ => None,
+    }
+}
+
+// https://html.spec.whatwg.org/multipage/syntax.html#void-elements
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+fn is_void_element(tag_name: &str) -> bool {
+    VOID_ELEMENTS
+        .iter()
+        .any(|name: &&str| name.eq_ignore_ascii_case(tag_name))
+}
+
+// <my-element>みたいなcustom elementのために'-'も入れる
+fn is_tag_name_char(c: char) -> bool {
+    matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-')
+}
+
+// attributeの名前は空白と'/', '>', '='以外なら何でもいい(data-fooとかaria-labelとか)
+fn is_attr_name_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '/' | '>' | '=')
+}
+
+/// doctypeからquirks modeを決める
+/// https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+pub fn quirks_mode(doctype: Option<&dom::Doctype>) -> dom::QuirksMode {
+    // doctypeがないならquirks mode
+    let doctype: &dom::Doctype = match doctype {
+        Some(doctype) => doctype,
+        None => return dom::QuirksMode::Quirks,
+    };
+    if doctype.name != "html" {
+        return dom::QuirksMode::Quirks;
+    }
+
+    // 比較は大文字小文字を区別しない
+    let public_id: Option<String> = doctype.public_id.as_ref().map(|id| id.to_ascii_lowercase());
+    let system_id: Option<String> = doctype.system_id.as_ref().map(|id| id.to_ascii_lowercase());
+    let public: &str = public_id.as_deref().unwrap_or("");
+    let public_starts_with = |prefixes: &[&str]| {
+        public_id.is_some()
+            && prefixes
+                .iter()
+                .any(|prefix: &&str| public.starts_with(&prefix.to_ascii_lowercase()))
+    };
+
+    const HTML401_FRAMESET_OR_TRANSITIONAL: [&str; 2] = [
+        "-//W3C//DTD HTML 4.01 Frameset//",
+        "-//W3C//DTD HTML 4.01 Transitional//",
+    ];
+    const XHTML10_FRAMESET_OR_TRANSITIONAL: [&str; 2] = [
+        "-//W3C//DTD XHTML 1.0 Frameset//",
+        "-//W3C//DTD XHTML 1.0 Transitional//",
+    ];
+
+    if matches!(
+        public,
+        "-//w3o//dtd w3 html strict 3.0//en//" | "-/w3c/dtd html 4.0 transitional/en" | "html"
+    ) || system_id.as_deref()
+        == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        || public_starts_with(&QUIRKS_PUBLIC_ID_PREFIXES)
+        || (system_id.is_none() && public_starts_with(&HTML401_FRAMESET_OR_TRANSITIONAL))
+    {
+        return dom::QuirksMode::Quirks;
+    }
+
+    if public_starts_with(&XHTML10_FRAMESET_OR_TRANSITIONAL)
+        || (system_id.is_some() && public_starts_with(&HTML401_FRAMESET_OR_TRANSITIONAL))
+    {
+        return dom::QuirksMode::LimitedQuirks;
+    }
+
+    dom::QuirksMode::NoQuirks
+}
+
+// public idがこれで始まるならquirks mode
+const QUIRKS_PUBLIC_ID_PREFIXES: [&str; 55] = [
+    "+//Silmaril//dtd html Pro v0r11 19970101//",
+    "-//AS//DTD HTML 3.0 asWedit + extensions//",
+    "-//AdvaSoft Ltd//DTD HTML 3.0 asWedit + extensions//",
+    "-//IETF//DTD HTML 2.0 Level 1//",
+    "-//IETF//DTD HTML 2.0 Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict Level 1//",
+    "-//IETF//DTD HTML 2.0 Strict Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict//",
+    "-//IETF//DTD HTML 2.0//",
+    "-//IETF//DTD HTML 2.1E//",
+    "-//IETF//DTD HTML 3.0//",
+    "-//IETF//DTD HTML 3.2 Final//",
+    "-//IETF//DTD HTML 3.2//",
+    "-//IETF//DTD HTML 3//",
+    "-//IETF//DTD HTML Level 0//",
+    "-//IETF//DTD HTML Level 1//",
+    "-//IETF//DTD HTML Level 2//",
+    "-//IETF//DTD HTML Level 3//",
+    "-//IETF//DTD HTML Strict Level 0//",
+    "-//IETF//DTD HTML Strict Level 1//",
+    "-//IETF//DTD HTML Strict Level 2//",
+    "-//IETF//DTD HTML Strict Level 3//",
+    "-//IETF//DTD HTML Strict//",
+    "-//IETF//DTD HTML//",
+    "-//Metrius//DTD Metrius Presentational//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 2.0 Tables//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 3.0 Tables//",
+    "-//Netscape Comm. Corp.//DTD HTML//",
+    "-//Netscape Comm. Corp.//DTD Strict HTML//",
+    "-//O'Reilly and Associates//DTD HTML 2.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended 1.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended Relaxed 1.0//",
+    "-//SQ//DTD HTML 2.0 HoTMetaL + extensions//",
+    "-//SoftQuad Software//DTD HoTMetaL PRO 6.0::19990601::extensions to HTML 4.0//",
+    "-//SoftQuad//DTD HoTMetaL PRO 4.0::19971010::extensions to HTML 4.0//",
+    "-//Spyglass//DTD HTML 2.0 Extended//",
+    "-//Sun Microsystems Corp.//DTD HotJava HTML//",
+    "-//Sun Microsystems Corp.//DTD HotJava Strict HTML//",
+    "-//W3C//DTD HTML 3 1995-03-24//",
+    "-//W3C//DTD HTML 3.2 Draft//",
+    "-//W3C//DTD HTML 3.2 Final//",
+    "-//W3C//DTD HTML 3.2//",
+    "-//W3C//DTD HTML 3.2S Draft//",
+    "-//W3C//DTD HTML 4.0 Frameset//",
+    "-//W3C//DTD HTML 4.0 Transitional//",
+    "-//W3C//DTD HTML Experimental 19960712//",
+    "-//W3C//DTD HTML Experimental 970421//",
+    "-//W3C//DTD W3 HTML//",
+    "-//W3O//DTD W3 HTML 3.0//",
+    "-//WebTechs//DTD Mozilla HTML 2.0//",
+    "-//WebTechs//DTD Mozilla HTML//",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 木の形だけを p(br, "a") みたいな文字列にする
+    fn outline(node: &dom::Node) -> String {
+        match node.node_type {
+            dom::NodeType::Text(ref text) => format!("{:?}", text),
+            dom::NodeType::Comment(ref data) => format!("<!--{}-->", data),
+            dom::NodeType::Element(ref element) if node.children.is_empty() => {
+                element.tag_name.clone()
+            }
+            dom::NodeType::Element(ref element) => format!(
+                "{}({})",
+                element.tag_name,
+                node.children
+                    .iter()
+                    .map(outline)
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    fn parse(source: &str) -> String {
+        outline(&Parser::parse(source.to_string()).unwrap())
+    }
+
+    fn parse_error(source: &str) -> ParseError {
+        Parser::parse(source.to_string()).unwrap_err()
+    }
+
+    #[test]
+    fn mismatched_end_tag_position() {
+        let err: ParseError = parse_error("<div>\n  <p>héllo</b></div>");
+        assert_eq!(err.kind, ParseErrorKind::MismatchedEndTag);
+        // columnは文字数、byte_offsetはバイト数で数える
+        assert_eq!((err.line, err.column), (2, 11));
+        assert_eq!(err.byte_offset, 17);
+        assert_eq!(err.expected, "'</p>'");
+        assert_eq!(err.found, "'</b>'");
+        assert_eq!(
+            err.to_string(),
+            "2:11: mismatched end tag: expected '</p>', found '</b>'"
+        );
+    }
+
+    #[test]
+    fn unexpected_end_tag_and_eof() {
+        assert_eq!(
+            parse_error("<p>a</p></div>").kind,
+            ParseErrorKind::UnexpectedEndTag
+        );
+        assert_eq!(parse_error("<p>a").kind, ParseErrorKind::UnexpectedEof);
+        assert_eq!(
+            parse_error("<p class=>x</p>").kind,
+            ParseErrorKind::UnexpectedCharacter
+        );
+    }
+
+    // void要素と/>で閉じた要素は子を持たない
+    #[test]
+    fn void_and_self_closing_elements() {
+        assert_eq!(
+            parse("<p>a<br>b<img src=\"x.png\"/>c<br/>d</p>"),
+            r#"p("a", br, "b", img, "c", br, "d")"#
+        );
+        assert_eq!(parse("<div><span/><p>x</p></div>"), r#"div(span, p("x"))"#);
+        // brは開いたままにならないので、</br>はpの閉じタグと食い違う
+        assert_eq!(
+            parse_error("<p><br></br></p>").kind,
+            ParseErrorKind::MismatchedEndTag
+        );
+    }
+
+    #[test]
+    fn comments_and_cdata() {
+        assert_eq!(
+            parse("<p>a<!-- x <b> -->b<!---->c</p>"),
+            r#"p("a", <!-- x <b> -->, "b", <!---->, "c")"#
+        );
+        // CDATAの中のタグや&はただの文字
+        assert_eq!(
+            parse("<p><![CDATA[<b>&amp;</b>]]></p>"),
+            r#"p("<b>&amp;</b>")"#
+        );
+        // <!で始まるほかのものは>までをコメントにする
+        assert_eq!(parse("<p><!foo>x</p>"), r#"p(<!--foo-->, "x")"#);
+        // rootの外のコメントは捨てる
+        assert_eq!(parse("<!-- a --><p>x</p><!-- b -->"), r#"p("x")"#);
+    }
+
+    fn document(source: &str) -> dom::Document {
+        Parser::parse_document(source.to_string()).unwrap()
+    }
+
+    #[test]
+    fn doctype_decides_quirks_mode() {
+        let doc: dom::Document = document("<!-- c --><!DOCTYPE html><p>x</p>");
+        assert_eq!(doc.mode, dom::QuirksMode::NoQuirks);
+        assert_eq!(
+            doc.doctype,
+            Some(dom::Doctype {
+                name: "html".to_string(),
+                public_id: None,
+                system_id: None,
+            })
+        );
+
+        assert_eq!(document("<p>x</p>").mode, dom::QuirksMode::Quirks);
+        assert_eq!(
+            document(r#"<!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 4.01 Transitional//EN"><p>x</p>"#)
+                .mode,
+            dom::QuirksMode::Quirks
+        );
+        assert_eq!(
+            document(
+                r#"<!DOCTYPE html PUBLIC "-//W3C//DTD HTML 4.01 Transitional//EN" "http://www.w3.org/TR/html4/loose.dtd"><p>x</p>"#
+            )
+            .mode,
+            dom::QuirksMode::LimitedQuirks
+        );
+        assert_eq!(
+            document(r#"<!doctype html public "-//W3C//DTD XHTML 1.0 Strict//EN"><p>x</p>"#).mode,
+            dom::QuirksMode::NoQuirks
+        );
+    }
+
+    // rootの属性を取ってくる
+    fn attribute(source: &str, name: &str) -> Option<String> {
+        match Parser::parse(source.to_string()).unwrap().node_type {
+            dom::NodeType::Element(element) => element.attributes.get(name).cloned(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn character_references_in_text() {
+        assert_eq!(
+            parse("<p>&lt;a&gt; &amp;amp; &#x41;&#66; &unknown; & x</p>"),
+            r#"p("<a> &amp; AB &unknown; & x")"#
+        );
+        // ;がなくても昔からある名前なら一番長く合うところまで読む
+        assert_eq!(parse("<p>&notit; &notin; &copy2</p>"), r#"p("¬it; ∉ ©2")"#);
+        // 0x80..0x9FはWindows-1252として読む。0や範囲外はU+FFFD
+        assert_eq!(
+            parse("<p>&#128;&#x99;&#0;&#x110000;&#xD800;</p>"),
+            "p(\"€™\u{FFFD}\u{FFFD}\u{FFFD}\")"
+        );
+    }
+
+    #[test]
+    fn character_references_in_attributes() {
+        assert_eq!(
+            attribute(r#"<a title="&lt;&quot;&#x3e;">x</a>"#, "title").as_deref(),
+            Some("<\">")
+        );
+        // 属性の中では;なしの名前のあとに=や英数字が続いたらそのまま
+        assert_eq!(
+            attribute(r#"<a href="?a=1&copy=2&copy;">x</a>"#, "href").as_deref(),
+            Some("?a=1&copy=2©")
+        );
+        assert_eq!(
+            attribute(r#"<a href="?x&copy">x</a>"#, "href").as_deref(),
+            Some("?x©")
+        );
+    }
+
+    #[test]
+    fn boolean_and_unquoted_attributes() {
+        let source: &str = "<input disabled type=checkbox value = 'a b' CHECKED>";
+        assert_eq!(attribute(source, "disabled").as_deref(), Some(""));
+        assert_eq!(attribute(source, "type").as_deref(), Some("checkbox"));
+        assert_eq!(attribute(source, "value").as_deref(), Some("a b"));
+        // 属性名は小文字にそろえる
+        assert_eq!(attribute(source, "checked").as_deref(), Some(""));
+        assert_eq!(
+            attribute("<td colspan=2>x</td>", "colspan").as_deref(),
+            Some("2")
+        );
+    }
+
+    // 同じ名前の属性は最初のものを使う
+    #[test]
+    fn first_duplicate_attribute_wins() {
+        assert_eq!(
+            attribute(r#"<p id="a" ID="b" id=c>x</p>"#, "id").as_deref(),
+            Some("a")
+        );
+    }
+
+    #[test]
+    fn dashes_and_colons_in_attribute_names() {
+        let source: &str = r#"<div data-user-id="7" aria-hidden="true" xml:lang="ja">x</div>"#;
+        assert_eq!(attribute(source, "data-user-id").as_deref(), Some("7"));
+        assert_eq!(attribute(source, "aria-hidden").as_deref(), Some("true"));
+        assert_eq!(attribute(source, "xml:lang").as_deref(), Some("ja"));
+    }
+
+    // scriptとstyleの中身はタグも文字参照もそのまま
+    #[test]
+    fn raw_text_elements() {
+        assert_eq!(
+            parse("<script>if (a < b && c) { x = '<p>&amp;'; }</script>"),
+            r#"script("if (a < b && c) { x = '<p>&amp;'; }")"#
+        );
+        // 名前が続いているものは閉じタグじゃない
+        assert_eq!(
+            parse("<style>a::after { content: '</styles>' }</STYLE>"),
+            r#"style("a::after { content: '</styles>' }")"#
+        );
+        assert_eq!(parse("<script></script>"), "script");
+        assert_eq!(
+            parse_error("<script>a</b>").kind,
+            ParseErrorKind::UnexpectedEof
+        );
+    }
+
+    // titleとtextareaは文字参照だけデコードする
+    #[test]
+    fn escapable_raw_text_elements() {
+        assert_eq!(
+            parse("<title>a &lt;b&gt; <i>c</i></title>"),
+            r#"title("a <b> <i>c</i>")"#
+        );
+        assert_eq!(
+            parse("<textarea><p>&amp;</p></textarea>"),
+            r#"textarea("<p>&</p>")"#
+        );
     }
 }
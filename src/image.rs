@@ -0,0 +1,158 @@
+use crate::{css::Color, painting::Canvas};
+use std::io::{self, Write};
+
+// image.rsはcanvasのピクセルを画像ファイルの形式に書き出すファイル
+// 外部のcrateは使わないので、PNGのzlibやCRCもここで計算してる
+
+/// バイナリ形式(P6)のPPMを書き出す。PPMはalphaを持てないので捨てる
+pub fn write_ppm<W: Write>(canvas: &Canvas, out: &mut W) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", canvas.width, canvas.height)?;
+    let mut data: Vec<u8> = Vec::with_capacity(canvas.pixels.len() * 3);
+    for pixel in &canvas.pixels {
+        data.extend_from_slice(&[pixel.r, pixel.g, pixel.b]);
+    }
+    out.write_all(&data)
+}
+
+/// RGBA 8bitのPNGを書き出す
+pub fn write_png<W: Write>(canvas: &Canvas, out: &mut W) -> io::Result<()> {
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    // IHDR: 幅、高さ、bit depth 8、color type 6(RGBA)、圧縮、フィルタ、インターレースなし
+    let mut header: Vec<u8> = Vec::with_capacity(13);
+    header.extend_from_slice(&(canvas.width as u32).to_be_bytes());
+    header.extend_from_slice(&(canvas.height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &header)?;
+
+    // 各行の先頭にフィルタの種類(0: None)をつける
+    let mut raw: Vec<u8> = Vec::with_capacity((canvas.width * 4 + 1) * canvas.height);
+    for row in canvas.pixels.chunks(canvas.width.max(1)) {
+        raw.push(0);
+        for pixel in row {
+            let Color { r, g, b, a } = *pixel;
+            raw.extend_from_slice(&[r, g, b, a]);
+        }
+    }
+    write_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(out, b"IEND", &[])
+}
+
+// 長さ、種類、データ、CRCの順でchunkを書く
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc: u32 = crc32(kind.iter().chain(data.iter()).copied());
+    out.write_all(&crc.to_be_bytes())
+}
+
+// 圧縮しない(stored)deflateブロックだけでzlibのデータを作る
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // storedブロックは一つで最大65535バイトまで
+    const MAX_BLOCK: usize = 0xFFFF;
+
+    // CMF(deflate, 32Kのwindow)とFLG。(CMF * 256 + FLG)が31の倍数になるようにしてる
+    let mut out: Vec<u8> = vec![0x78, 0x01];
+    let mut chunks = data.chunks(MAX_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        // 空でも最後のブロックは一つ必要
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last: u8 = if chunks.peek().is_none() { 1 } else { 0 };
+        let len: u16 = chunk.len() as u16;
+        out.push(last);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b): (u32, u32) = (1, 0);
+    // 5552バイトまでならmodを取らなくてもu32からあふれない
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+fn crc32<I: Iterator<Item = u8>>(bytes: I) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask: u32 = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_of_iend() {
+        assert_eq!(crc32(b"IEND".iter().copied()), 0xAE42_6082);
+    }
+
+    #[test]
+    fn adler32_of_wikipedia() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    // storedブロックのヘッダを順にたどって、元のデータに戻ることを確かめる
+    fn inflate_stored(zlib: &[u8]) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        let mut pos: usize = 2;
+        loop {
+            let last: u8 = zlib[pos];
+            let len: u16 = u16::from_le_bytes([zlib[pos + 1], zlib[pos + 2]]);
+            let nlen: u16 = u16::from_le_bytes([zlib[pos + 3], zlib[pos + 4]]);
+            assert_eq!(nlen, !len);
+            data.extend_from_slice(&zlib[pos + 5..pos + 5 + len as usize]);
+            pos += 5 + len as usize;
+            if last == 1 {
+                break;
+            }
+        }
+        assert_eq!(zlib[pos..], adler32(&data).to_be_bytes());
+        data
+    }
+
+    // 65535バイトを超える生データは複数のstoredブロックに分かれる
+    #[test]
+    fn png_with_multiple_stored_blocks() {
+        let canvas: Canvas = Canvas::new(200, 100);
+        let mut png: Vec<u8> = Vec::new();
+        write_png(&canvas, &mut png).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+        // IHDRは8 + 13 + 4バイト
+        let idat: usize = 8 + 8 + 13 + 4;
+        let len: usize = u32::from_be_bytes(png[idat..idat + 4].try_into().unwrap()) as usize;
+        assert_eq!(&png[idat + 4..idat + 8], b"IDAT");
+        let zlib: &[u8] = &png[idat + 8..idat + 8 + len];
+        // 1行は1 + 200 * 4バイト
+        let raw: Vec<u8> = inflate_stored(zlib);
+        assert_eq!(raw.len(), 801 * 100);
+        assert!(raw.len() > 0xFFFF);
+        assert_eq!(zlib[2], 0);
+        assert_eq!(&raw[..5], &[0, 255, 255, 255, 255]);
+
+        let crc: u32 = crc32(png[idat + 4..idat + 8 + len].iter().copied());
+        assert_eq!(png[idat + 8 + len..idat + 12 + len], crc.to_be_bytes());
+        assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xAEB`\x82");
+    }
+}
@@ -1,12 +1,12 @@
 use crate::{
-    css,
+    css, dom, font,
     style::{self, StyledNode},
 };
 use std::default::Default;
 
 // layout.rsは要素の位置を計算するためのファイル
 
-#[derive(Clone, Copy, Default, Debug)]
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
 pub struct Rect {
     pub x: f32,
     pub y: f32,
@@ -23,6 +23,18 @@ impl Rect {
             height: self.height + edge.top + edge.bottom,
         }
     }
+
+    // 両方を囲む矩形
+    fn union(self, other: Rect) -> Rect {
+        let x: f32 = self.x.min(other.x);
+        let y: f32 = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Default, Debug)]
@@ -35,11 +47,11 @@ pub struct Dimensions {
 
 impl Dimensions {
     // The area covered by the content area plus its padding.
-    fn padding_box(self) -> Rect {
+    pub fn padding_box(self) -> Rect {
         self.content.expanded_by(self.padding)
     }
     // The area covered by the content area plus padding and borders.
-    fn border_box(self) -> Rect {
+    pub fn border_box(self) -> Rect {
         self.padding_box().expanded_by(self.border)
     }
     // The area covered by the content area plus padding, borders, and margin.
@@ -61,15 +73,37 @@ pub struct EdgeSizes {
 pub enum BoxType<'a> {
     BlockNode(&'a StyledNode<'a>),
     InlineNode(&'a StyledNode<'a>),
-    AnonymousBlock,
+    // 匿名ブロックはstyleを持たないので、継承するプロパティ用に親のblockのstyleを持っておく
+    AnonymousBlock(&'a StyledNode<'a>),
 }
 
 // これが全体を表してそう
-
+#[derive(Debug)]
 pub struct LayoutBox<'a> {
     pub dimensions: Dimensions,
     pub box_type: BoxType<'a>,
     pub children: Vec<LayoutBox<'a>>,
+    // inline要素が行ごとに分割された断片。inlineのときだけ入る
+    pub fragments: Vec<Fragment>,
+    // このboxの中に並べられた行。inline要素を並べる側のboxのときだけ入る
+    pub line_boxes: Vec<LineBox>,
+}
+
+// inline要素は行をまたぐと分割されるので、1行分をFragmentとして持つ
+// 分割されたとき左のmargin, border, paddingは最初の断片、右のは最後の断片にだけつく
+#[derive(Clone, Debug, Default)]
+pub struct Fragment {
+    pub dimensions: Dimensions,
+    // テキストノードのときだけ、その行に入った文字列が入る
+    pub text: Option<String>,
+    pub font_size: f32,
+}
+
+// 1行分の領域。baselineはrect.yからの距離
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LineBox {
+    pub rect: Rect,
+    pub baseline: f32,
 }
 
 impl<'a> LayoutBox<'a> {
@@ -78,13 +112,15 @@ impl<'a> LayoutBox<'a> {
             box_type,
             dimensions: Default::default(), // initially set all fields to 0.0
             children: Vec::new(),
+            fragments: Vec::new(),
+            line_boxes: Vec::new(),
         }
     }
 
     fn get_style_node(&self) -> &'a StyledNode<'a> {
         match self.box_type {
             BoxType::BlockNode(node) | BoxType::InlineNode(node) => node,
-            BoxType::AnonymousBlock => panic!("Anonymous block box has no style node"),
+            BoxType::AnonymousBlock(_) => panic!("Anonymous block box has no style node"),
         }
     }
 }
@@ -93,8 +129,10 @@ impl<'a> LayoutBox<'a> {
     fn layout(&mut self, containing_block: Dimensions) {
         match self.box_type {
             BoxType::BlockNode(_) => self.layout_block(containing_block),
-            BoxType::InlineNode(_) => {}  // TODO
-            BoxType::AnonymousBlock => {} // TODO
+            BoxType::InlineNode(_) => self.layout_inline_root(containing_block),
+            BoxType::AnonymousBlock(parent) => {
+                self.layout_anonymous_block(containing_block, parent)
+            }
         }
     }
 
@@ -125,15 +163,95 @@ impl<'a> LayoutBox<'a> {
         // marginとか色々初期値は0
         let zero: css::Value = css::Value::Length(0.0, css::Unit::Px);
 
-        // lookupは引数があるかどうか順に試している？？
-        let mut margin_left: css::Value = style.lookup("margin-left", "margin", &zero);
-        let mut margin_right: css::Value = style.lookup("margin-right", "margin", &zero);
+        let mut margin_left: css::Value = style.lookup("margin-left", &zero);
+        let mut margin_right: css::Value = style.lookup("margin-right", &zero);
+
+        let border_left: css::Value = style.lookup("border-left-width", &zero);
+        let border_right: css::Value = style.lookup("border-right-width", &zero);
 
-        let border_left: css::Value = style.lookup("border-left-width", "border-width", &zero);
-        let border_right: css::Value = style.lookup("border-right-width", "border-width", &zero);
+        let padding_left: css::Value = style.lookup("padding-left", &zero);
+        let padding_right: css::Value = style.lookup("padding-right", &zero);
 
-        let padding_left: css::Value = style.lookup("padding-left", "padding", &zero);
-        let padding_right: css::Value = style.lookup("padding-right", "padding", &zero);
+        // 左右のmargin, border, padding, widthを全部足した値
+        let total: f32 = [
+            &margin_left,
+            &margin_right,
+            &border_left,
+            &border_right,
+            &padding_left,
+            &padding_right,
+            &width,
+        ]
+        .iter()
+        .map(|v: &&css::Value| v.to_px())
+        .sum();
+
+        // widthがautoじゃなくて親より大きいならautoのmarginは0として扱う
+        if width != auto && total > containing_block.content.width {
+            if margin_left == auto {
+                margin_left = zero.clone();
+            }
+            if margin_right == auto {
+                margin_right = zero.clone();
+            }
+        }
+
+        // 親の幅に対してどれだけ余ってるか。マイナスならはみ出してる
+        let underflow: f32 = containing_block.content.width - total;
+
+        // https://www.w3.org/TR/CSS2/visudet.html#blockwidth の条件分岐
+        match (width == auto, margin_left == auto, margin_right == auto) {
+            // 全部決まってる(over-constrained)ならmargin_rightで帳尻を合わせる
+            (false, false, false) => {
+                margin_right = css::Value::Length(margin_right.to_px() + underflow, css::Unit::Px);
+            }
+
+            // どっちかのmarginだけautoならそっちに余りを入れる
+            (false, false, true) => {
+                margin_right = css::Value::Length(underflow, css::Unit::Px);
+            }
+            (false, true, false) => {
+                margin_left = css::Value::Length(underflow, css::Unit::Px);
+            }
+
+            // widthがautoなら他のautoは0になる
+            (true, _, _) => {
+                if margin_left == auto {
+                    margin_left = zero.clone();
+                }
+                if margin_right == auto {
+                    margin_right = zero.clone();
+                }
+
+                if underflow >= 0.0 {
+                    // 余った分を全部widthにする
+                    width = css::Value::Length(underflow, css::Unit::Px);
+                } else {
+                    // widthはマイナスにできないのでmargin_rightを削る
+                    width = zero.clone();
+                    margin_right =
+                        css::Value::Length(margin_right.to_px() + underflow, css::Unit::Px);
+                }
+            }
+
+            // 左右のmarginがautoなら半分ずつにして真ん中に寄せる
+            (false, true, true) => {
+                margin_left = css::Value::Length(underflow / 2.0, css::Unit::Px);
+                margin_right = css::Value::Length(underflow / 2.0, css::Unit::Px);
+            }
+        }
+
+        let d: &mut Dimensions = &mut self.dimensions;
+        d.content.width = width.to_px();
+
+        d.padding.left = padding_left.to_px();
+        d.padding.right = padding_right.to_px();
+
+        d.border.left = border_left.to_px();
+        d.border.right = border_right.to_px();
+
+        d.margin.left = margin_left.to_px();
+        d.margin.right = margin_right.to_px();
     }
 
     fn calculate_block_position(&mut self, containing_block: Dimensions) {
@@ -144,18 +262,14 @@ impl<'a> LayoutBox<'a> {
         let zero: css::Value = css::Value::Length(0.0, css::Unit::Px);
 
         // If margin-top or margin-bottom is `auto`, the used value is zero.
-        d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
-        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
+        d.margin.top = style.lookup("margin-top", &zero).to_px();
+        d.margin.bottom = style.lookup("margin-bottom", &zero).to_px();
 
-        d.border.top = style
-            .lookup("border-top-width", "border-width", &zero)
-            .to_px();
-        d.border.bottom = style
-            .lookup("border-bottom-width", "border-width", &zero)
-            .to_px();
+        d.border.top = style.lookup("border-top-width", &zero).to_px();
+        d.border.bottom = style.lookup("border-bottom-width", &zero).to_px();
 
-        d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
-        d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
+        d.padding.top = style.lookup("padding-top", &zero).to_px();
+        d.padding.bottom = style.lookup("padding-bottom", &zero).to_px();
 
         d.content.x = containing_block.content.x + d.margin.left + d.border.left + d.padding.left;
 
@@ -172,7 +286,7 @@ impl<'a> LayoutBox<'a> {
         for child in &mut self.children {
             child.layout(*d);
             // Track the height so each child is laid out below the previous content.
-            d.content.height = d.content.height + child.dimensions.margin_box().height;
+            d.content.height += child.dimensions.margin_box().height;
         }
     }
 
@@ -184,18 +298,52 @@ impl<'a> LayoutBox<'a> {
         }
     }
 
+    // rootがinline要素のときは、自分だけが入った行を包含ブロックの中に並べる
+    fn layout_inline_root(&mut self, containing_block: Dimensions) {
+        let context: InlineContext = InlineContext::new(self.get_style_node(), None);
+        let area: Rect = Rect {
+            x: containing_block.content.x,
+            y: containing_block.content.y + containing_block.content.height,
+            width: containing_block.content.width,
+            height: 0.0,
+        };
+        let line_boxes: Vec<LineBox> =
+            layout_inline_boxes(std::slice::from_mut(self), area, &context);
+        self.line_boxes = line_boxes;
+    }
+
+    // 匿名ブロックは包含ブロックの幅いっぱいに広がって、中のinline要素を行として縦に積む
+    // margin, border, paddingは持たないので、高さは行の高さの合計になる
+    // text-alignやfont-sizeのような継承されるプロパティは親のblockから引き継ぐ
+    fn layout_anonymous_block(&mut self, containing_block: Dimensions, parent: &StyledNode) {
+        let d: &mut Dimensions = &mut self.dimensions;
+        d.content.x = containing_block.content.x;
+        d.content.y = containing_block.content.y + containing_block.content.height;
+        d.content.width = containing_block.content.width;
+
+        let context: InlineContext = InlineContext::new(parent, None);
+        self.line_boxes =
+            layout_inline_boxes(&mut self.children, self.dimensions.content, &context);
+        self.dimensions.content.height = self
+            .line_boxes
+            .iter()
+            .fold(0.0, |height: f32, line: &LineBox| height + line.rect.height);
+    }
+
     pub fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
         match self.box_type {
-            BoxType::InlineNode(_) | BoxType::AnonymousBlock => self,
-            BoxType::BlockNode(_) => {
+            BoxType::InlineNode(_) | BoxType::AnonymousBlock(_) => self,
+            BoxType::BlockNode(style) => {
                 // もしBlockなら空のオブジェクトを返してそうでないなら匿名のBlockを返している？？
                 // これは良くやってることがわかってない
                 match self.children.last() {
                     Some(&LayoutBox {
-                        box_type: BoxType::AnonymousBlock,
+                        box_type: BoxType::AnonymousBlock(_),
                         ..
                     }) => {}
-                    _ => self.children.push(LayoutBox::new(BoxType::AnonymousBlock)),
+                    _ => self
+                        .children
+                        .push(LayoutBox::new(BoxType::AnonymousBlock(style))),
                 }
                 self.children.last_mut().unwrap()
             }
@@ -203,26 +351,835 @@ impl<'a> LayoutBox<'a> {
     }
 }
 
+// viewportの大きさを受け取って位置まで計算済みのlayout treeを返す
+pub fn layout_tree<'a>(node: &'a StyledNode<'a>, mut viewport: Dimensions) -> LayoutBox<'a> {
+    // layout_block_childrenは親のheightを足していって子のyを決めてるので、
+    // 初期包含ブロックのheightは0から始めないとrootがviewportの下に置かれてしまう
+    viewport.content.height = 0.0;
+
+    let mut root_box: LayoutBox = build_layout_tree(node);
+    root_box.layout(viewport);
+    root_box
+}
+
 // layout_treeを作る
+// rootがdisplay: noneなら何も表示しないので、中身のない匿名ブロックを返す
 pub fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
+    if style_node.display() == style::Display::None {
+        return LayoutBox::new(BoxType::AnonymousBlock(style_node));
+    }
+    build_box(style_node, false)
+}
+
+// in_inlineならinline boxの中にある。display: noneのnodeは呼ぶ側で飛ばしている
+// inlineの中のblockは本当はinlineを前後に分けないといけないけど、まだできないのでinlineとして並べる
+// https://www.w3.org/TR/CSS2/visuren.html#anonymous-block-level
+fn build_box<'a>(style_node: &'a StyledNode<'a>, in_inline: bool) -> LayoutBox<'a> {
+    let inline: bool = in_inline || style_node.display() == style::Display::Inline;
     // 親のbox作る
-    let mut root: LayoutBox = LayoutBox::new(match style_node.display() {
-        style::Display::Block => BoxType::BlockNode(style_node),
-        style::Display::Inline => BoxType::InlineNode(style_node),
-        style::Display::None => panic!("Root node has display: none."),
+    let mut root: LayoutBox = LayoutBox::new(if inline {
+        BoxType::InlineNode(style_node)
+    } else {
+        BoxType::BlockNode(style_node)
     });
 
     // 子孫のboxを作る
     // forで回してる。どっかで再帰的に読んでそう
     for child in &style_node.children {
         match child.display() {
-            style::Display::Block => root.children.push(build_layout_tree(child)),
-            style::Display::Inline => root
+            style::Display::Block if !inline => root.children.push(build_box(child, false)),
+            style::Display::Block | style::Display::Inline => root
                 .get_inline_container()
                 .children
-                .push(build_layout_tree(child)),
+                .push(build_box(child, inline)),
             style::Display::None => {} // Skip nodes with `display: none;`
         }
     }
-    return root;
+    root
+}
+
+// ---- inline layout ----
+// inline要素は上から下ではなく左から右に並べて、幅が足りなくなったら次の行に折り返す
+// まずinline boxの木を単語や開始/終了の並びに平らにしてから、行に詰めていく
+
+// 行を並べる側(包含ブロック)から引き継ぐ値
+struct InlineContext {
+    text_align: TextAlign,
+    font_size: f32,
+    line_height: LineHeight,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TextAlign {
+    Left,
+    Right,
+    Center,
+}
+
+impl InlineContext {
+    fn new(style: &StyledNode, parent: Option<&InlineContext>) -> InlineContext {
+        let parent_font_size: f32 = parent.map_or(font::DEFAULT_FONT_SIZE, |p| p.font_size);
+        let parent_line_height: LineHeight = parent.map_or(LineHeight::Normal, |p| p.line_height);
+        InlineContext {
+            text_align: match style.value("text-align") {
+                Some(css::Value::Keyword(s)) => match &*s {
+                    "right" | "end" => TextAlign::Right,
+                    "center" => TextAlign::Center,
+                    _ => TextAlign::Left,
+                },
+                _ => parent.map_or(TextAlign::Left, |p| p.text_align),
+            },
+            font_size: font_size(style, parent_font_size),
+            line_height: line_height(style, parent_line_height),
+        }
+    }
+}
+
+// font-sizeを解決する。キーワードはstyleのcomputed valueでもうpxになってる
+fn font_size(style: &StyledNode, parent_size: f32) -> f32 {
+    match style.value("font-size") {
+        Some(css::Value::Length(size, css::Unit::Px)) => size,
+        _ => parent_size,
+    }
+}
+
+// line-heightの値。normalと数はfont-sizeによって変わるので、pxにせずそのまま子に引き継ぐ
+#[derive(Clone, Copy)]
+enum LineHeight {
+    Normal,
+    Number(f32),
+    Px(f32),
+}
+
+impl LineHeight {
+    fn used(self, font_size: f32) -> f32 {
+        match self {
+            LineHeight::Normal => font_size * font::NORMAL_LINE_HEIGHT,
+            LineHeight::Number(n) => font_size * n,
+            LineHeight::Px(h) => h,
+        }
+    }
+}
+
+fn line_height(style: &StyledNode, parent: LineHeight) -> LineHeight {
+    match style.value("line-height") {
+        Some(css::Value::Length(h, css::Unit::Px)) => LineHeight::Px(h),
+        Some(css::Value::Number(n)) => LineHeight::Number(n),
+        Some(css::Value::Keyword(s)) if s == "normal" => LineHeight::Normal,
+        _ => parent,
+    }
+}
+
+// 平らにしたinline boxの中身。usizeはboxを行きがけ順に数えた番号
+enum InlineItem {
+    Start(usize),
+    End(usize),
+    Word {
+        id: usize,
+        text: String,
+        // この単語の前で改行してよいかどうか
+        break_before: bool,
+    },
+    Space(usize),
+}
+
+// 行に詰めるときに必要なbox毎の値
+struct InlineBoxInfo {
+    font_size: f32,
+    line_height: f32,
+    margin: EdgeSizes,
+    border: EdgeSizes,
+    padding: EdgeSizes,
+}
+
+impl InlineBoxInfo {
+    fn left_edge(&self) -> f32 {
+        self.margin.left + self.border.left + self.padding.left
+    }
+
+    fn right_edge(&self) -> f32 {
+        self.margin.right + self.border.right + self.padding.right
+    }
+}
+
+#[derive(Default)]
+struct InlineCollector {
+    items: Vec<InlineItem>,
+    boxes: Vec<InlineBoxInfo>,
+    // 連続する空白は一つにまとめるので、次の単語が来るまで持っておく
+    pending_space: Option<usize>,
+    last_was_wide: bool,
+}
+
+impl InlineCollector {
+    fn collect(
+        &mut self,
+        layout_box: &LayoutBox,
+        parent_font_size: f32,
+        parent_line_height: LineHeight,
+    ) {
+        let style: &StyledNode = layout_box.get_style_node();
+        let size: f32 = font_size(style, parent_font_size);
+        let line_height: LineHeight = line_height(style, parent_line_height);
+        let id: usize = self.boxes.len();
+
+        match style.node.node_type {
+            dom::NodeType::Text(_) | dom::NodeType::Comment(_) => {
+                self.boxes.push(InlineBoxInfo {
+                    font_size: size,
+                    line_height: line_height.used(size),
+                    margin: Default::default(),
+                    border: Default::default(),
+                    padding: Default::default(),
+                });
+                if let dom::NodeType::Text(ref text) = style.node.node_type {
+                    self.collect_text(id, text);
+                }
+            }
+            dom::NodeType::Element(_) => {
+                let zero: css::Value = css::Value::Length(0.0, css::Unit::Px);
+                let edges = |prefix: &str, suffix: &str| -> EdgeSizes {
+                    let side = |side: &str| -> f32 {
+                        let name: String = format!("{}-{}{}", prefix, side, suffix);
+                        style.lookup(&name, &zero).to_px()
+                    };
+                    EdgeSizes {
+                        left: side("left"),
+                        right: side("right"),
+                        top: side("top"),
+                        bottom: side("bottom"),
+                    }
+                };
+                self.boxes.push(InlineBoxInfo {
+                    font_size: size,
+                    line_height: line_height.used(size),
+                    margin: edges("margin", ""),
+                    border: edges("border", "-width"),
+                    padding: edges("padding", ""),
+                });
+                // 手前に空白があるならboxの外側に置いておく
+                if let Some(space_id) = self.pending_space.take() {
+                    self.items.push(InlineItem::Space(space_id));
+                }
+                self.items.push(InlineItem::Start(id));
+                for child in &layout_box.children {
+                    self.collect(child, size, line_height);
+                }
+                self.items.push(InlineItem::End(id));
+            }
+        }
+    }
+
+    // テキストを単語に分ける。全角文字は一文字ずつ単語にして、前後で改行できるようにしてる
+    fn collect_text(&mut self, id: usize, text: &str) {
+        let mut word: String = String::new();
+        for c in text.chars() {
+            if c.is_whitespace() {
+                self.push_word(id, &mut word);
+                if self.pending_space.is_none() {
+                    self.pending_space = Some(id);
+                }
+            } else if font::is_wide(c) {
+                self.push_word(id, &mut word);
+                word.push(c);
+                self.push_word(id, &mut word);
+            } else {
+                word.push(c);
+            }
+        }
+        self.push_word(id, &mut word);
+    }
+
+    fn push_word(&mut self, id: usize, word: &mut String) {
+        if word.is_empty() {
+            return;
+        }
+        let wide: bool = word.chars().next().is_some_and(font::is_wide);
+        let space: Option<usize> = self.pending_space.take();
+        if let Some(space_id) = space {
+            self.items.push(InlineItem::Space(space_id));
+        }
+        let break_before: bool = space.is_some() || wide || self.last_was_wide;
+        self.last_was_wide = wide;
+        self.items.push(InlineItem::Word {
+            id,
+            text: std::mem::take(word),
+            break_before,
+        });
+    }
+}
+
+// 行の中に置かれたboxの断片。xは行の左端からの距離
+struct LinePiece {
+    id: usize,
+    x: f32,
+    width: f32,
+    text: Option<String>,
+    // このboxの開始/終了がこの行にあるかどうか(左右のedgeをつけるか)
+    has_start: bool,
+    has_end: bool,
+}
+
+struct LineBuilder<'b> {
+    boxes: &'b [InlineBoxInfo],
+    context: &'b InlineContext,
+    area: Rect,
+    // 今詰めている行
+    pieces: Vec<LinePiece>,
+    x: f32,
+    y: f32,
+    // 開始したけどまだ終わってないbox(id, piecesの位置)
+    open: Vec<(usize, usize)>,
+    // 直前に置いたのがテキストならそのid。同じテキストなら一つの断片にまとめる
+    last_text: Option<usize>,
+    lines: Vec<LineBox>,
+    fragments: Vec<Vec<Fragment>>,
+}
+
+impl<'b> LineBuilder<'b> {
+    fn new(boxes: &'b [InlineBoxInfo], context: &'b InlineContext, area: Rect) -> LineBuilder<'b> {
+        LineBuilder {
+            boxes,
+            context,
+            area,
+            pieces: Vec::new(),
+            x: 0.0,
+            y: area.y,
+            open: Vec::new(),
+            last_text: None,
+            lines: Vec::new(),
+            fragments: boxes.iter().map(|_| Vec::new()).collect(),
+        }
+    }
+
+    fn item_width(&self, item: &InlineItem) -> f32 {
+        match *item {
+            InlineItem::Start(id) => self.boxes[id].left_edge(),
+            InlineItem::End(id) => self.boxes[id].right_edge(),
+            InlineItem::Word { id, ref text, .. } => {
+                font::text_width(text, self.boxes[id].font_size)
+            }
+            InlineItem::Space(id) => font::char_width(' ', self.boxes[id].font_size),
+        }
+    }
+
+    // 改行できない塊(run)をまとめて置く。入りきらないなら先に改行する
+    fn place_run(&mut self, run: &[InlineItem], space: Option<usize>) {
+        if run.is_empty() {
+            return;
+        }
+        let space_width: f32 = space.map_or(0.0, |id| self.item_width(&InlineItem::Space(id)));
+        let run_width: f32 = run
+            .iter()
+            .map(|item: &InlineItem| self.item_width(item))
+            .sum();
+
+        if self.x > 0.0 && self.x + space_width + run_width > self.area.width {
+            // 行末の空白は捨てる
+            self.break_line();
+        } else if let Some(id) = space {
+            // 行頭の空白も捨てる
+            if self.x > 0.0 {
+                self.push_text(id, " ");
+            }
+        }
+
+        for item in run {
+            self.place_item(item);
+        }
+    }
+
+    fn place_item(&mut self, item: &InlineItem) {
+        match *item {
+            InlineItem::Start(id) => {
+                self.pieces.push(LinePiece {
+                    id,
+                    x: self.x,
+                    width: 0.0,
+                    text: None,
+                    has_start: true,
+                    has_end: false,
+                });
+                self.open.push((id, self.pieces.len() - 1));
+                self.x += self.boxes[id].left_edge();
+                self.last_text = None;
+            }
+            InlineItem::End(id) => {
+                self.x += self.boxes[id].right_edge();
+                if let Some((_, index)) = self.open.pop() {
+                    let piece: &mut LinePiece = &mut self.pieces[index];
+                    piece.width = self.x - piece.x;
+                    piece.has_end = true;
+                }
+                self.last_text = None;
+            }
+            InlineItem::Word { id, ref text, .. } => self.push_text(id, text),
+            InlineItem::Space(id) => self.push_text(id, " "),
+        }
+    }
+
+    fn push_text(&mut self, id: usize, text: &str) {
+        let width: f32 = font::text_width(text, self.boxes[id].font_size);
+        match self.pieces.last_mut() {
+            Some(piece) if self.last_text == Some(id) => {
+                piece.text.get_or_insert_with(String::new).push_str(text);
+                piece.width += width;
+            }
+            _ => self.pieces.push(LinePiece {
+                id,
+                x: self.x,
+                width,
+                text: Some(text.to_string()),
+                has_start: true,
+                has_end: true,
+            }),
+        }
+        self.x += width;
+        self.last_text = Some(id);
+    }
+
+    // 今の行を閉じて次の行に移る。終わってないboxは次の行で続きの断片を作る
+    fn break_line(&mut self) {
+        for &(_, index) in &self.open {
+            let piece: &mut LinePiece = &mut self.pieces[index];
+            piece.width = self.x - piece.x;
+        }
+        self.finish_line();
+
+        let open: Vec<(usize, usize)> = std::mem::take(&mut self.open);
+        for (id, _) in open {
+            self.pieces.push(LinePiece {
+                id,
+                x: 0.0,
+                width: 0.0,
+                text: None,
+                has_start: false,
+                has_end: false,
+            });
+            self.open.push((id, self.pieces.len() - 1));
+        }
+    }
+
+    fn finish_line(&mut self) {
+        let pieces: Vec<LinePiece> = std::mem::take(&mut self.pieces);
+        let line_width: f32 = self.x;
+        self.x = 0.0;
+        self.last_text = None;
+        if pieces.is_empty() {
+            return;
+        }
+
+        // 高さはこの行に出てくるboxの中で一番大きいものに合わせる
+        // 包含ブロック自体のfont-sizeとline-height(strut)も最低限の高さとして入れる
+        let strut_size: f32 = self.context.font_size;
+        let strut: (f32, f32) = (strut_size, self.context.line_height.used(strut_size));
+        let (above, below): (f32, f32) = pieces
+            .iter()
+            .map(|piece: &LinePiece| {
+                (
+                    self.boxes[piece.id].font_size,
+                    self.boxes[piece.id].line_height,
+                )
+            })
+            .chain(std::iter::once(strut))
+            .map(|(size, line_height): (f32, f32)| {
+                let above: f32 = (line_height - size) / 2.0 + size * font::ASCENT;
+                (above, line_height - above)
+            })
+            .fold(
+                (0.0, 0.0),
+                |(a, b): (f32, f32), (above, below): (f32, f32)| (a.max(above), b.max(below)),
+            );
+        let line: LineBox = LineBox {
+            rect: Rect {
+                x: self.area.x,
+                y: self.y,
+                width: self.area.width,
+                height: above + below,
+            },
+            baseline: above,
+        };
+
+        // text-alignで行の中身を横にずらす。はみ出してるなら左に寄せる
+        let free: f32 = (self.area.width - line_width).max(0.0);
+        let offset: f32 = match self.context.text_align {
+            TextAlign::Left => 0.0,
+            TextAlign::Center => free / 2.0,
+            TextAlign::Right => free,
+        };
+
+        for piece in pieces {
+            let info: &InlineBoxInfo = &self.boxes[piece.id];
+            let mut d: Dimensions = Dimensions {
+                margin: info.margin,
+                border: info.border,
+                padding: info.padding,
+                ..Default::default()
+            };
+            if !piece.has_start {
+                d.margin.left = 0.0;
+                d.border.left = 0.0;
+                d.padding.left = 0.0;
+            }
+            if !piece.has_end {
+                d.margin.right = 0.0;
+                d.border.right = 0.0;
+                d.padding.right = 0.0;
+            }
+            let left: f32 = d.margin.left + d.border.left + d.padding.left;
+            let right: f32 = d.margin.right + d.border.right + d.padding.right;
+            d.content = Rect {
+                x: self.area.x + offset + piece.x + left,
+                y: line.rect.y + line.baseline - info.font_size * font::ASCENT,
+                width: (piece.width - left - right).max(0.0),
+                height: info.font_size,
+            };
+            self.fragments[piece.id].push(Fragment {
+                dimensions: d,
+                text: piece.text,
+                font_size: info.font_size,
+            });
+        }
+
+        self.y += line.rect.height;
+        self.lines.push(line);
+    }
+}
+
+// boxesを包含ブロックのareaの中に行として並べて、並べた行を返す
+// 各boxには行ごとの断片がfragmentsとして入る
+fn layout_inline_boxes(
+    boxes: &mut [LayoutBox],
+    area: Rect,
+    context: &InlineContext,
+) -> Vec<LineBox> {
+    let mut collector: InlineCollector = InlineCollector::default();
+    for layout_box in boxes.iter() {
+        collector.collect(layout_box, context.font_size, context.line_height);
+    }
+
+    let mut builder: LineBuilder = LineBuilder::new(&collector.boxes, context, area);
+    let mut run: Vec<InlineItem> = Vec::new();
+    let mut space: Option<usize> = None;
+    for item in collector.items {
+        match item {
+            InlineItem::Space(id) => {
+                builder.place_run(&run, space);
+                run.clear();
+                space = Some(id);
+            }
+            InlineItem::Word {
+                break_before: true, ..
+            } if !run.is_empty() => {
+                builder.place_run(&run, space);
+                run.clear();
+                space = None;
+                run.push(item);
+            }
+            _ => run.push(item),
+        }
+    }
+    builder.place_run(&run, space);
+    builder.break_line();
+
+    let lines: Vec<LineBox> = std::mem::take(&mut builder.lines);
+    let mut fragments: std::vec::IntoIter<Vec<Fragment>> = builder.fragments.into_iter();
+    for layout_box in boxes.iter_mut() {
+        assign_fragments(layout_box, &mut fragments);
+    }
+    lines
+}
+
+// collectと同じ行きがけ順で断片を配る
+fn assign_fragments(layout_box: &mut LayoutBox, fragments: &mut std::vec::IntoIter<Vec<Fragment>>) {
+    layout_box.fragments = fragments.next().unwrap_or_default();
+    if let (Some(first), Some(last)) = (layout_box.fragments.first(), layout_box.fragments.last()) {
+        // box全体としては全部の断片を囲む矩形にしておく
+        let mut d: Dimensions = first.dimensions;
+        d.margin.right = last.dimensions.margin.right;
+        d.border.right = last.dimensions.border.right;
+        d.padding.right = last.dimensions.padding.right;
+        d.content = layout_box
+            .fragments
+            .iter()
+            .fold(first.dimensions.content, |rect: Rect, f: &Fragment| {
+                rect.union(f.dimensions.content)
+            });
+        layout_box.dimensions = d;
+    }
+
+    for child in &mut layout_box.children {
+        assign_fragments(child, fragments);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dom;
+    use crate::{html, tree_builder};
+
+    fn viewport(width: f32) -> Dimensions {
+        let mut viewport: Dimensions = Default::default();
+        viewport.content.width = width;
+        viewport.content.height = 600.0;
+        viewport
+    }
+
+    fn div(children: Vec<dom::Node>) -> dom::Node {
+        dom::elem("div".to_string(), dom::AttrMap::new(), children)
+    }
+
+    fn span(text: &str) -> dom::Node {
+        dom::elem(
+            "span".to_string(),
+            dom::AttrMap::new(),
+            vec![dom::text(text.to_string())],
+        )
+    }
+
+    fn text(text: &str) -> dom::Node {
+        dom::text(text.to_string())
+    }
+
+    fn stylesheet(css: &str) -> css::Stylesheet {
+        css::parse(format!("div {{ display: block; }} {}", css)).unwrap()
+    }
+
+    // 800pxのdivの中のpのmarginの左右とwidth
+    fn block_width(css: &str) -> (f32, f32, f32) {
+        let root: dom::Node = div(vec![dom::elem(
+            "p".to_string(),
+            dom::AttrMap::new(),
+            Vec::new(),
+        )]);
+        let sheet: css::Stylesheet = css::parse(format!(
+            "div {{ display: block; }} p {{ display: block; }} {}",
+            css
+        ))
+        .unwrap();
+        let styled: StyledNode = style::style_tree(&root, std::slice::from_ref(&sheet));
+        let mut layout_root: LayoutBox = build_layout_tree(&styled);
+        layout_root.layout(viewport(800.0));
+        let d: Dimensions = layout_root.children[0].dimensions;
+        (d.margin.left, d.content.width, d.margin.right)
+    }
+
+    // https://www.w3.org/TR/CSS2/visudet.html#blockwidth
+    #[test]
+    fn auto_width_fills_containing_block() {
+        assert_eq!(block_width(""), (0.0, 800.0, 0.0));
+        assert_eq!(
+            block_width(
+                "p { margin-left: 10px; margin-right: 20px; padding-left: 5px; padding-right: 5px;
+                     border-left-width: 1px; border-right-width: 1px; }"
+            ),
+            (10.0, 758.0, 20.0)
+        );
+        // widthがautoならautoのmarginは0
+        assert_eq!(
+            block_width("p { margin-left: auto; margin-right: auto; }"),
+            (0.0, 800.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn auto_margins_center_fixed_width() {
+        assert_eq!(
+            block_width("p { width: 200px; margin-left: auto; margin-right: auto; }"),
+            (300.0, 200.0, 300.0)
+        );
+    }
+
+    #[test]
+    fn single_auto_margin_takes_remaining_space() {
+        assert_eq!(
+            block_width("p { width: 200px; margin-left: auto; margin-right: 100px; }"),
+            (500.0, 200.0, 100.0)
+        );
+        assert_eq!(
+            block_width("p { width: 200px; margin-left: 100px; margin-right: auto; }"),
+            (100.0, 200.0, 500.0)
+        );
+    }
+
+    // 全部決まっていたらmargin-rightで合わせる
+    #[test]
+    fn over_constrained_adjusts_margin_right() {
+        assert_eq!(
+            block_width("p { width: 200px; margin-left: 10px; margin-right: 10px; }"),
+            (10.0, 200.0, 590.0)
+        );
+    }
+
+    #[test]
+    fn wider_than_containing_block() {
+        // autoのmarginは0にしてmargin-rightがマイナスになる
+        assert_eq!(
+            block_width("p { width: 900px; margin-left: auto; margin-right: auto; }"),
+            (0.0, 900.0, -100.0)
+        );
+        // widthはマイナスにできない
+        assert_eq!(
+            block_width("p { padding-left: 900px; }"),
+            (0.0, 0.0, -100.0)
+        );
+    }
+
+    // 匿名ブロックの中のboxを行きがけ順に並べて、それぞれの断片を(x, width, text)にする
+    fn fragments(layout_root: &LayoutBox) -> Vec<Vec<(f32, f32, Option<String>)>> {
+        fn walk(layout_box: &LayoutBox, out: &mut Vec<Vec<(f32, f32, Option<String>)>>) {
+            out.push(
+                layout_box
+                    .fragments
+                    .iter()
+                    .map(|f: &Fragment| {
+                        (
+                            f.dimensions.content.x,
+                            f.dimensions.content.width,
+                            f.text.clone(),
+                        )
+                    })
+                    .collect(),
+            );
+            for child in &layout_box.children {
+                walk(child, out);
+            }
+        }
+        let mut out: Vec<Vec<(f32, f32, Option<String>)>> = Vec::new();
+        for child in &layout_root.children[0].children {
+            walk(child, &mut out);
+        }
+        out
+    }
+
+    fn line_count(layout_root: &LayoutBox) -> usize {
+        layout_root.children[0].line_boxes.len()
+    }
+
+    fn piece(x: f32, width: f32, text: Option<&str>) -> (f32, f32, Option<String>) {
+        (x, width, text.map(str::to_string))
+    }
+
+    // 16pxなので半角1文字は8px
+    #[test]
+    fn text_and_span_wrap_across_lines() {
+        let root: dom::Node = div(vec![text("aa bb "), span("cc dd"), text(" ee")]);
+        let sheet: css::Stylesheet =
+            stylesheet("span { margin-left: 2px; padding-left: 4px; padding-right: 4px; }");
+        let styled: StyledNode = style::style_tree(&root, std::slice::from_ref(&sheet));
+        let layout_root: LayoutBox = layout_tree(&styled, viewport(48.0));
+        assert_eq!(line_count(&layout_root), 3);
+        assert_eq!(
+            fragments(&layout_root),
+            vec![
+                // 行末の空白は捨てる
+                vec![piece(0.0, 40.0, Some("aa bb"))],
+                // spanの左のmarginとpaddingは最初の断片、右のpaddingは最後の断片だけ
+                vec![piece(6.0, 16.0, None), piece(0.0, 16.0, None)],
+                vec![piece(6.0, 16.0, Some("cc")), piece(0.0, 16.0, Some("dd"))],
+                vec![piece(20.0, 24.0, Some(" ee"))],
+            ]
+        );
+    }
+
+    #[test]
+    fn whitespace_collapses() {
+        let root: dom::Node = div(vec![text("  aa   bb\n\t cc ")]);
+        let sheet: css::Stylesheet = stylesheet("");
+        let styled: StyledNode = style::style_tree(&root, std::slice::from_ref(&sheet));
+        let layout_root: LayoutBox = layout_tree(&styled, viewport(48.0));
+        assert_eq!(line_count(&layout_root), 2);
+        assert_eq!(
+            fragments(&layout_root),
+            vec![vec![
+                piece(0.0, 40.0, Some("aa bb")),
+                piece(0.0, 16.0, Some("cc")),
+            ]]
+        );
+    }
+
+    // 空白のないところでは、inline boxの境目でも改行しない
+    #[test]
+    fn no_break_between_boxes_without_space() {
+        let root: dom::Node = div(vec![text("ab"), span("cd"), text("ef gh")]);
+        let sheet: css::Stylesheet = stylesheet("span { padding-left: 4px; padding-right: 4px; }");
+        let styled: StyledNode = style::style_tree(&root, std::slice::from_ref(&sheet));
+        let layout_root: LayoutBox = layout_tree(&styled, viewport(30.0));
+        assert_eq!(line_count(&layout_root), 2);
+        assert_eq!(
+            fragments(&layout_root),
+            vec![
+                vec![piece(0.0, 16.0, Some("ab"))],
+                vec![piece(20.0, 16.0, None)],
+                vec![piece(20.0, 16.0, Some("cd"))],
+                vec![piece(40.0, 16.0, Some("ef")), piece(0.0, 16.0, Some("gh"))],
+            ]
+        );
+    }
+
+    #[test]
+    fn text_align_center() {
+        let root: dom::Node = div(vec![text("ab")]);
+        let sheet: css::Stylesheet = stylesheet("div { text-align: center; }");
+        let styled: StyledNode = style::style_tree(&root, std::slice::from_ref(&sheet));
+        let layout_root: LayoutBox = layout_tree(&styled, viewport(100.0));
+        assert_eq!(
+            fragments(&layout_root),
+            vec![vec![piece(42.0, 16.0, Some("ab"))]]
+        );
+    }
+
+    // 匿名ブロックの高さの分だけ後ろのblockが下にずれる
+    #[test]
+    fn anonymous_block_stacks_with_siblings() {
+        let root: dom::Node = div(vec![
+            text("ab"),
+            dom::elem("section".to_string(), dom::AttrMap::new(), Vec::new()),
+        ]);
+        let sheet: css::Stylesheet = stylesheet("section { display: block; height: 10px; }");
+        let styled: StyledNode = style::style_tree(&root, std::slice::from_ref(&sheet));
+        let layout_root: LayoutBox = layout_tree(&styled, viewport(100.0));
+        let anonymous: Dimensions = layout_root.children[0].dimensions;
+        assert_eq!(anonymous.content.width, 100.0);
+        assert!(anonymous.content.height > 0.0);
+        assert_eq!(
+            layout_root.children[1].dimensions.content.y,
+            anonymous.content.height
+        );
+        assert_eq!(
+            layout_root.dimensions.content.height,
+            anonymous.content.height + 10.0
+        );
+    }
+
+    #[test]
+    fn display_none_root_is_empty() {
+        let root: dom::Node = html::Parser::parse("<span>x</span>".to_string()).unwrap();
+        let sheet: css::Stylesheet = css::parse("span { display: none; }".to_string()).unwrap();
+        let styled: StyledNode = style::style_tree(&root, std::slice::from_ref(&sheet));
+        let layout_root: LayoutBox = layout_tree(&styled, viewport(800.0));
+        assert!(matches!(layout_root.box_type, BoxType::AnonymousBlock(_)));
+        assert!(layout_root.children.is_empty());
+        assert_eq!(layout_root.dimensions.content.height, 0.0);
+    }
+
+    // UA stylesheetでheadやscriptはdisplay: noneになる
+    #[test]
+    fn hidden_root_from_user_agent_sheet() {
+        for source in ["<head><title>t</title></head>", "<script>x</script>"] {
+            let root: dom::Node = html::Parser::parse(source.to_string()).unwrap();
+            let styled: StyledNode = style::style_tree(&root, &[]);
+            let layout_root: LayoutBox = layout_tree(&styled, viewport(800.0));
+            assert!(layout_root.children.is_empty(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn frameset_after_script_does_not_panic() {
+        let source: String = "<!DOCTYPE html><script> <frameset>".to_string();
+        let root: dom::Node = tree_builder::parse_document(source).root;
+        let styled: StyledNode = style::style_tree(&root, &[]);
+        layout_tree(&styled, viewport(800.0));
+    }
 }
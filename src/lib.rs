@@ -0,0 +1,12 @@
+pub mod css;
+pub mod dom;
+pub mod entities;
+pub mod font;
+pub mod html;
+pub mod image;
+pub mod layout;
+pub mod painting;
+pub mod shorthand;
+pub mod style;
+pub mod tokenizer;
+pub mod tree_builder;
@@ -1,34 +1,599 @@
-use std::println;
+use engine::{
+    css, dom, html, image,
+    layout::{self, BoxType, Dimensions, LayoutBox, Rect},
+    painting::{self, DisplayCommand},
+    style::{self, StyledNode},
+    tree_builder,
+};
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    process,
+};
 
-use crate::html::Parser;
+const USAGE: &str = "\
+usage: engine [--html FILE] [--css FILE]... [--user-css FILE]... [--viewport WxH]
+              [-o FILE] [--dump KIND] [--strict] [--html5]
+
+  --html FILE        read HTML from FILE, or from stdin if omitted or -
+  --css FILE         read a stylesheet from FILE (repeatable), - for stdin
+  --user-css FILE    read a user stylesheet from FILE (repeatable), - for stdin
+  --viewport WxH     viewport size in px (default: 800x600)
+  -o, --output FILE  render to FILE as .png or .ppm, - writes PNG to stdout
+  --dump KIND        print dom, style, layout or display-list to stdout
+  --strict           fail on invalid CSS files instead of warning
+  --html5            parse HTML like a browser, accepting malformed markup
+  -h, --help         show this help";
+
+// 終了コードはsysexits.hに合わせてる
+const EXIT_USAGE: i32 = 64;
+const EXIT_DATAERR: i32 = 65;
+const EXIT_NOINPUT: i32 = 66;
+const EXIT_IOERR: i32 = 74;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Dump {
+    Dom,
+    Style,
+    Layout,
+    DisplayList,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Png,
+    Ppm,
+}
+
+struct Options {
+    html: Option<String>,
+    css: Vec<String>,
+    // ユーザーのstylesheet。!importantじゃなければページのものより弱い
+    user_css: Vec<String>,
+    viewport: (f32, f32),
+    output: Option<(String, Format)>,
+    dump: Option<Dump>,
+    // trueならCSSのエラーを警告じゃなくてエラーにする
+    strict: bool,
+    // trueならHTMLをtree_builderでブラウザと同じようにparseする
+    html5: bool,
+}
+
+struct CliError {
+    code: i32,
+    message: String,
+}
+
+impl CliError {
+    fn new(code: i32, message: String) -> CliError {
+        CliError { code, message }
+    }
+}
 
-pub mod css;
-pub mod dom;
-pub mod html;
-pub mod layout;
-pub mod style;
 fn main() {
-    // Node 動作確認
-    // let sample_node: dom::Node = dom::Node {
-    //     children: vec![],
-    //     node_type: dom::NodeType::Text("<h1>sample-h1</h1>".to_string()),
-    // };
-    // println!("{:?}", sample_node.node_type);
-
-    println!("----------------------------------------------------------------------");
-    // HTML動作確認
-    let dom: dom::Node = Parser::parse(
-        "<h1 class='sample' style='font-bold'>コンテンツ<p class='sample2'>Aです</p><p>Bです</p></h1>".to_string(),
-    );
-    // println!("{:?}", dom);
-
-    println!("\n");
-    let css: css::Stylesheet = crate::css::parse(
-        "h1{ margin: auto; backgroundcolor: red; } .sample{ padding: 10px;} .sample2{ text-align: center; }".to_string(),
-    );
-    // CSS動作確認
-    // println!("{:?}", css);
-
-    let styletree: style::StyledNode = crate::style::style_tree(&dom, &css);
-    println!("{:?}", styletree);
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args
+        .iter()
+        .any(|arg: &String| arg == "-h" || arg == "--help")
+    {
+        println!("{}", USAGE);
+        return;
+    }
+
+    if let Err(err) = parse_args(&args).and_then(|options: Options| run(&options)) {
+        eprintln!("engine: {}", err.message);
+        if err.code == EXIT_USAGE {
+            eprintln!("{}", USAGE);
+        }
+        process::exit(err.code);
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, CliError> {
+    let mut options: Options = Options {
+        html: None,
+        css: Vec::new(),
+        user_css: Vec::new(),
+        viewport: (800.0, 600.0),
+        output: None,
+        dump: None,
+        strict: false,
+        html5: false,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        // --name=value の形でも受け付ける
+        let (name, inline_value): (&str, Option<&str>) = match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => (name, Some(value)),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || -> Result<String, CliError> {
+            inline_value
+                .map(str::to_string)
+                .or_else(|| iter.next().cloned())
+                .ok_or_else(|| usage(format!("{} requires a value", name)))
+        };
+
+        match name {
+            "--strict" => options.strict = true,
+            "--html5" => options.html5 = true,
+            "--html" => options.html = Some(value()?),
+            "--css" => options.css.push(value()?),
+            "--user-css" => options.user_css.push(value()?),
+            "--viewport" => options.viewport = parse_viewport(&value()?)?,
+            "--output" | "-o" => {
+                let path: String = value()?;
+                let format: Format = output_format(&path)?;
+                options.output = Some((path, format));
+            }
+            "--dump" => {
+                options.dump = Some(match &*value()? {
+                    "dom" => Dump::Dom,
+                    "style" => Dump::Style,
+                    "layout" => Dump::Layout,
+                    "display-list" => Dump::DisplayList,
+                    other => return Err(usage(format!("unknown dump kind '{}'", other))),
+                })
+            }
+            _ => return Err(usage(format!("unknown argument '{}'", arg))),
+        }
+    }
+
+    if options.output.is_none() && options.dump.is_none() {
+        return Err(usage("nothing to do: pass --output or --dump".to_string()));
+    }
+
+    // stdinは一回しか読めないので、htmlとcssの両方をstdinからは読めない
+    let stdin_inputs: usize = options
+        .css
+        .iter()
+        .chain(&options.user_css)
+        .filter(|path| *path == "-")
+        .count()
+        + match options.html.as_deref() {
+            None | Some("-") => 1,
+            Some(_) => 0,
+        };
+    if stdin_inputs > 1 {
+        return Err(usage("only one input can be read from stdin".to_string()));
+    }
+
+    Ok(options)
+}
+
+fn usage(message: String) -> CliError {
+    CliError::new(EXIT_USAGE, message)
+}
+
+// 800x600 みたいな文字列をパースする
+fn parse_viewport(s: &str) -> Result<(f32, f32), CliError> {
+    let invalid = || usage(format!("invalid viewport '{}', expected WIDTHxHEIGHT", s));
+    let (width, height) = s.split_once('x').ok_or_else(invalid)?;
+    let width: u32 = width.parse().map_err(|_| invalid())?;
+    let height: u32 = height.parse().map_err(|_| invalid())?;
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+    Ok((width as f32, height as f32))
+}
+
+// 出力の形式は拡張子で決める
+fn output_format(path: &str) -> Result<Format, CliError> {
+    if path == "-" {
+        return Ok(Format::Png);
+    }
+    match path
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+    {
+        Some(ext) if ext == "png" => Ok(Format::Png),
+        Some(ext) if ext == "ppm" => Ok(Format::Ppm),
+        _ => Err(usage(format!(
+            "cannot tell output format of '{}', use .png or .ppm",
+            path
+        ))),
+    }
+}
+
+// strictなら最初のエラーで止めて、そうでなければ警告を出して読めたところを使う
+fn parse_css(name: &str, source: String, strict: bool) -> Result<css::Stylesheet, CliError> {
+    if strict {
+        return css::parse(source).map_err(|err: css::CssError| {
+            CliError::new(EXIT_DATAERR, format!("{}:{}", name, err))
+        });
+    }
+    let (sheet, errors) = css::parse_lenient(source);
+    for err in errors {
+        eprintln!("engine: warning: {}:{}", name, err);
+    }
+    Ok(sheet)
+}
+
+fn run(options: &Options) -> Result<(), CliError> {
+    let html_source: String = read_input(options.html.as_deref().unwrap_or("-"))?;
+    let mut css_sources: Vec<(&str, String, css::Origin)> = Vec::new();
+    for path in &options.user_css {
+        css_sources.push((path, read_input(path)?, css::Origin::User));
+    }
+    for path in &options.css {
+        css_sources.push((path, read_input(path)?, css::Origin::Author));
+    }
+
+    let html_path: &str = options.html.as_deref().unwrap_or("-");
+    let document: dom::Document = if options.html5 {
+        tree_builder::parse_document(html_source)
+    } else {
+        html::Parser::parse_document(html_source).map_err(|err: html::ParseError| {
+            CliError::new(EXIT_DATAERR, format!("{}:{}", input_name(html_path), err))
+        })?
+    };
+    let root_node: &dom::Node = &document.root;
+    if options.dump == Some(Dump::Dom) {
+        let mut out: String = String::new();
+        dump_doctype(&mut out, &document);
+        dump_dom(&mut out, root_node, 0);
+        write_stdout(out.as_bytes())?;
+        if options.output.is_none() {
+            return Ok(());
+        }
+    }
+
+    // stylesheetは指定された順に並べる。同じ強さのdeclarationなら後ろが勝つ
+    // <style>の中身はstyle_treeがファイルの後ろに文書の順で足す
+    let mut stylesheets: Vec<css::Stylesheet> = Vec::new();
+    for (path, source, origin) in css_sources {
+        let mut sheet: css::Stylesheet = parse_css(input_name(path), source, options.strict)?;
+        sheet.origin = origin;
+        stylesheets.push(sheet);
+    }
+    // <style>は文書の一部なので、ブラウザと同じで--strictでも止めないで警告だけ出す
+    let style_name: String = format!("{} <style>", input_name(html_path));
+    let mut style_elements: Vec<css::Stylesheet> = Vec::new();
+    for (sheet, errors) in style::style_element_sheets(root_node) {
+        for err in errors {
+            eprintln!("engine: warning: {}:{}", style_name, err);
+        }
+        style_elements.push(sheet);
+    }
+    let style_root: StyledNode =
+        style::style_tree_with_style_elements(root_node, &stylesheets, &style_elements);
+    if options.dump == Some(Dump::Style) {
+        let mut out: String = String::new();
+        dump_style(&mut out, &style_root, 0);
+        write_stdout(out.as_bytes())?;
+        if options.output.is_none() {
+            return Ok(());
+        }
+    }
+
+    let (width, height): (f32, f32) = options.viewport;
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = width;
+    viewport.content.height = height;
+    let layout_root: LayoutBox = layout::layout_tree(&style_root, viewport);
+    match options.dump {
+        Some(Dump::Layout) => {
+            let mut out: String = String::new();
+            dump_layout(&mut out, &layout_root, 0);
+            write_stdout(out.as_bytes())?;
+        }
+        Some(Dump::DisplayList) => {
+            let mut out: String = String::new();
+            for item in painting::build_display_list(&layout_root) {
+                dump_display_command(&mut out, &item);
+            }
+            write_stdout(out.as_bytes())?;
+        }
+        _ => {}
+    }
+
+    if let Some((ref path, format)) = options.output {
+        let canvas: painting::Canvas = painting::paint(
+            &layout_root,
+            Rect {
+                x: 0.0,
+                y: 0.0,
+                width,
+                height,
+            },
+        );
+        let mut data: Vec<u8> = Vec::new();
+        match format {
+            Format::Png => image::write_png(&canvas, &mut data),
+            Format::Ppm => image::write_ppm(&canvas, &mut data),
+        }
+        .map_err(|err: io::Error| CliError::new(EXIT_IOERR, err.to_string()))?;
+
+        if path == "-" {
+            write_stdout(&data)?;
+        } else {
+            fs::write(path, &data).map_err(|err: io::Error| {
+                CliError::new(EXIT_IOERR, format!("cannot write '{}': {}", path, err))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+// - ならstdin、それ以外はファイルから読む
+fn read_input(path: &str) -> Result<String, CliError> {
+    let mut source: String = String::new();
+    if path == "-" {
+        io::stdin()
+            .read_to_string(&mut source)
+            .map_err(|err: io::Error| CliError::new(EXIT_DATAERR, format!("stdin: {}", err)))?;
+    } else {
+        let bytes: Vec<u8> = fs::read(path).map_err(|err: io::Error| {
+            CliError::new(EXIT_NOINPUT, format!("cannot read '{}': {}", path, err))
+        })?;
+        source = String::from_utf8(bytes)
+            .map_err(|_| CliError::new(EXIT_DATAERR, format!("'{}' is not valid UTF-8", path)))?;
+    }
+    Ok(source)
+}
+
+// エラーメッセージに出す入力の名前
+fn input_name(path: &str) -> &str {
+    if path == "-" {
+        "<stdin>"
+    } else {
+        path
+    }
+}
+
+fn write_stdout(data: &[u8]) -> Result<(), CliError> {
+    let mut stdout = io::stdout().lock();
+    stdout
+        .write_all(data)
+        .and_then(|_| stdout.flush())
+        .map_err(|err: io::Error| CliError::new(EXIT_IOERR, format!("stdout: {}", err)))
+}
+
+// ---- dump ----
+// golden fileと比べやすいように、HashMapの中身は名前順に並べて出す
+
+fn indent(out: &mut String, depth: usize) {
+    out.push_str(&"  ".repeat(depth));
+}
+
+// 先頭にdoctypeとquirks modeを出す
+fn dump_doctype(out: &mut String, document: &dom::Document) {
+    if let Some(ref doctype) = document.doctype {
+        out.push_str(&format!("<!DOCTYPE {}", doctype.name));
+        if let Some(ref public_id) = doctype.public_id {
+            out.push_str(&format!(" PUBLIC {:?}", public_id));
+        }
+        if let Some(ref system_id) = doctype.system_id {
+            if doctype.public_id.is_none() {
+                out.push_str(" SYSTEM");
+            }
+            out.push_str(&format!(" {:?}", system_id));
+        }
+        out.push_str(">\n");
+    }
+    out.push_str(&format!("mode: {:?}\n", document.mode));
+}
+
+fn dump_dom(out: &mut String, node: &dom::Node, depth: usize) {
+    indent(out, depth);
+    match node.node_type {
+        dom::NodeType::Text(ref text) => out.push_str(&format!("{:?}\n", text)),
+        dom::NodeType::Comment(ref data) => out.push_str(&format!("<!--{}-->\n", data)),
+        dom::NodeType::Element(ref elem) => {
+            out.push('<');
+            out.push_str(&elem.tag_name);
+            let mut attrs: Vec<(&String, &String)> = elem.attributes.iter().collect();
+            attrs.sort();
+            for (name, value) in attrs {
+                out.push_str(&format!(" {}={:?}", name, value));
+            }
+            out.push_str(">\n");
+        }
+    }
+    for child in &node.children {
+        dump_dom(out, child, depth + 1);
+    }
+}
+
+fn dump_style(out: &mut String, styled: &StyledNode, depth: usize) {
+    indent(out, depth);
+    match styled.node.node_type {
+        dom::NodeType::Text(ref text) => out.push_str(&format!("{:?}\n", text)),
+        dom::NodeType::Comment(ref data) => out.push_str(&format!("<!--{}-->\n", data)),
+        dom::NodeType::Element(ref elem) => {
+            out.push_str(&format!("<{}>", elem.tag_name));
+            // 継承した値や初期値も入ったcomputed valueを出す
+            let mut values: Vec<(&String, &css::Value)> = styled.computed_values.iter().collect();
+            values.sort_by(|a, b| a.0.cmp(b.0));
+            for (name, value) in values {
+                out.push_str(&format!(" {}: {};", name, value));
+            }
+            out.push('\n');
+        }
+    }
+    for child in &styled.children {
+        dump_style(out, child, depth + 1);
+    }
+}
+
+fn dump_layout(out: &mut String, layout_box: &LayoutBox, depth: usize) {
+    indent(out, depth);
+    let label: String = match layout_box.box_type {
+        BoxType::BlockNode(styled) => format!("block {}", node_label(styled)),
+        BoxType::InlineNode(styled) => format!("inline {}", node_label(styled)),
+        BoxType::AnonymousBlock(_) => "anonymous".to_string(),
+    };
+    out.push_str(&format!(
+        "{} {}\n",
+        label,
+        rect_label(layout_box.dimensions.content)
+    ));
+    for line in &layout_box.line_boxes {
+        indent(out, depth + 1);
+        out.push_str(&format!("line {}\n", rect_label(line.rect)));
+    }
+    for fragment in &layout_box.fragments {
+        indent(out, depth + 1);
+        match fragment.text {
+            Some(ref text) => out.push_str(&format!(
+                "fragment {} {:?}\n",
+                rect_label(fragment.dimensions.content),
+                text
+            )),
+            None => out.push_str(&format!(
+                "fragment {}\n",
+                rect_label(fragment.dimensions.content)
+            )),
+        }
+    }
+    for child in &layout_box.children {
+        dump_layout(out, child, depth + 1);
+    }
+}
+
+fn node_label(styled: &StyledNode) -> String {
+    match styled.node.node_type {
+        dom::NodeType::Text(_) => "#text".to_string(),
+        dom::NodeType::Comment(_) => "#comment".to_string(),
+        dom::NodeType::Element(ref elem) => format!("<{}>", elem.tag_name),
+    }
+}
+
+fn rect_label(rect: Rect) -> String {
+    format!("({}, {}) {}x{}", rect.x, rect.y, rect.width, rect.height)
+}
+
+fn dump_display_command(out: &mut String, item: &DisplayCommand) {
+    match *item {
+        DisplayCommand::SolidColor(color, rect) => {
+            out.push_str(&format!("rect {} {}\n", color, rect_label(rect)))
+        }
+        DisplayCommand::Text {
+            ref text,
+            rect,
+            color,
+            font_size,
+        } => out.push_str(&format!(
+            "text {} {} {}px {:?}\n",
+            color,
+            rect_label(rect),
+            font_size,
+            text
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg: &&str| arg.to_string()).collect()
+    }
+
+    fn exit_code(list: &[&str]) -> Option<i32> {
+        parse_args(&args(list))
+            .and_then(|options: Options| run(&options))
+            .err()
+            .map(|err: CliError| err.code)
+    }
+
+    // テストごとに別のファイル名にしておく
+    fn temp_file(name: &str, data: &[u8]) -> String {
+        let path: std::path::PathBuf =
+            env::temp_dir().join(format!("engine-test-{}-{}", process::id(), name));
+        fs::write(&path, data).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert_eq!(exit_code(&["--bogus"]), Some(EXIT_USAGE));
+        assert_eq!(exit_code(&["--viewport", "800"]), Some(EXIT_USAGE));
+        assert_eq!(exit_code(&["--dump", "tree"]), Some(EXIT_USAGE));
+        assert_eq!(exit_code(&["--output", "out.gif"]), Some(EXIT_USAGE));
+        assert_eq!(exit_code(&["--html"]), Some(EXIT_USAGE));
+        // --outputも--dumpもないとやることがない
+        assert_eq!(exit_code(&["--html", "a.html"]), Some(EXIT_USAGE));
+    }
+
+    #[test]
+    fn only_one_input_from_stdin() {
+        assert_eq!(
+            exit_code(&["--css", "-", "--dump", "dom"]),
+            Some(EXIT_USAGE)
+        );
+        assert_eq!(
+            exit_code(&["--html=-", "--css=-", "--dump=dom"]),
+            Some(EXIT_USAGE)
+        );
+        assert!(parse_args(&args(&["--html", "a.html", "--css", "-", "--dump", "dom"])).is_ok());
+    }
+
+    #[test]
+    fn missing_input_file() {
+        assert_eq!(
+            exit_code(&["--html", "/nonexistent/a.html", "--dump", "dom"]),
+            Some(EXIT_NOINPUT)
+        );
+    }
+
+    #[test]
+    fn input_is_not_utf8() {
+        let path: String = temp_file("latin1.html", b"<p>\xE9</p>");
+        assert_eq!(
+            exit_code(&["--html", &path, "--dump", "dom"]),
+            Some(EXIT_DATAERR)
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn cannot_write_output() {
+        let path: String = temp_file("ok.html", b"<p>x</p>");
+        assert_eq!(
+            exit_code(&["--html", &path, "-o", "/nonexistent/out.png"]),
+            Some(EXIT_IOERR)
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn malformed_html() {
+        let path: String = temp_file("mismatched.html", b"<div><p>x</b></div>");
+        assert_eq!(
+            exit_code(&["--html", &path, "--dump", "dom"]),
+            Some(EXIT_DATAERR)
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    // --strictのときだけおかしいCSSで止まる
+    #[test]
+    fn invalid_css_only_fails_with_strict() {
+        let html: String = temp_file("strict.html", b"<p>x</p>");
+        let css: String = temp_file("strict.css", b"p { width: 10em; }");
+        assert_eq!(
+            exit_code(&["--html", &html, "--css", &css, "--strict", "--dump", "style"]),
+            Some(EXIT_DATAERR)
+        );
+        assert_eq!(
+            exit_code(&["--html", &html, "--css", &css, "--dump", "style"]),
+            None
+        );
+        fs::remove_file(html).unwrap();
+        fs::remove_file(css).unwrap();
+    }
+
+    // <style>は--strictでも警告だけで止まらない
+    #[test]
+    fn invalid_style_element_does_not_fail_with_strict() {
+        let html: String = temp_file("style.html", b"<style>p { width: 10em; }</style><p>x</p>");
+        assert_eq!(
+            exit_code(&["--html", &html, "--strict", "--dump", "style"]),
+            None
+        );
+        fs::remove_file(html).unwrap();
+    }
 }
@@ -0,0 +1,472 @@
+use crate::{
+    css::{Color, Value},
+    dom, font,
+    layout::{BoxType, Dimensions, Fragment, LayoutBox, Rect},
+    style::StyledNode,
+};
+
+// painting.rsはlayoutで位置が決まったboxを、何をどこに描くかの命令の列(display list)に変換するファイル
+
+pub type DisplayList = Vec<DisplayCommand>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisplayCommand {
+    // 矩形を一色で塗る
+    SolidColor(Color, Rect),
+    // rectはテキストのcontent領域
+    Text {
+        text: String,
+        rect: Rect,
+        color: Color,
+        font_size: f32,
+    },
+}
+
+// colorが何も指定されてないときの文字の色
+const DEFAULT_TEXT_COLOR: Color = Color {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 255,
+};
+
+// layout treeを上から順に見て、後ろにあるものから先に描くようにdisplay listを作る
+pub fn build_display_list(layout_root: &LayoutBox) -> DisplayList {
+    let mut list: DisplayList = Vec::new();
+    render_layout_box(&mut list, layout_root, DEFAULT_TEXT_COLOR);
+    list
+}
+
+// colorは継承されるので親の文字色を受け取っておく
+fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox, parent_color: Color) {
+    let color: Color = match layout_box.box_type {
+        BoxType::BlockNode(style) | BoxType::InlineNode(style) => {
+            get_color(style, "color").unwrap_or(parent_color)
+        }
+        BoxType::AnonymousBlock(_) => parent_color,
+    };
+
+    match layout_box.box_type {
+        BoxType::BlockNode(style) => {
+            render_background(list, style, &layout_box.dimensions);
+            render_borders(list, style, &layout_box.dimensions);
+        }
+        // inline要素は行ごとの断片をそれぞれ描く
+        BoxType::InlineNode(style) => {
+            for fragment in &layout_box.fragments {
+                render_fragment(list, style, fragment, color);
+            }
+        }
+        BoxType::AnonymousBlock(_) => {}
+    }
+
+    for child in &layout_box.children {
+        render_layout_box(list, child, color);
+    }
+}
+
+fn render_fragment(list: &mut DisplayList, style: &StyledNode, fragment: &Fragment, color: Color) {
+    match style.node.node_type {
+        dom::NodeType::Text(_) => {
+            if let Some(ref text) = fragment.text {
+                list.push(DisplayCommand::Text {
+                    text: text.clone(),
+                    rect: fragment.dimensions.content,
+                    color,
+                    font_size: fragment.font_size,
+                });
+            }
+        }
+        dom::NodeType::Element(_) => {
+            render_background(list, style, &fragment.dimensions);
+            render_borders(list, style, &fragment.dimensions);
+        }
+        dom::NodeType::Comment(_) => {}
+    }
+}
+
+// 背景はborderの内側まで塗る
+fn render_background(list: &mut DisplayList, style: &StyledNode, d: &Dimensions) {
+    if let Some(color) = get_color(style, "background-color") {
+        list.push(DisplayCommand::SolidColor(color, d.border_box()));
+    }
+}
+
+// borderは上下左右それぞれ別の矩形として塗る
+fn render_borders(list: &mut DisplayList, style: &StyledNode, d: &Dimensions) {
+    let border_box: Rect = d.border_box();
+
+    // left
+    if let Some(color) = get_border_color(style, "left") {
+        list.push(DisplayCommand::SolidColor(
+            color,
+            Rect {
+                x: border_box.x,
+                y: border_box.y,
+                width: d.border.left,
+                height: border_box.height,
+            },
+        ));
+    }
+
+    // right
+    if let Some(color) = get_border_color(style, "right") {
+        list.push(DisplayCommand::SolidColor(
+            color,
+            Rect {
+                x: border_box.x + border_box.width - d.border.right,
+                y: border_box.y,
+                width: d.border.right,
+                height: border_box.height,
+            },
+        ));
+    }
+
+    // top
+    if let Some(color) = get_border_color(style, "top") {
+        list.push(DisplayCommand::SolidColor(
+            color,
+            Rect {
+                x: border_box.x,
+                y: border_box.y,
+                width: border_box.width,
+                height: d.border.top,
+            },
+        ));
+    }
+
+    // bottom
+    if let Some(color) = get_border_color(style, "bottom") {
+        list.push(DisplayCommand::SolidColor(
+            color,
+            Rect {
+                x: border_box.x,
+                y: border_box.y + border_box.height - d.border.bottom,
+                width: border_box.width,
+                height: d.border.bottom,
+            },
+        ));
+    }
+}
+
+// border-colorはparserでborder-left-colorとかに分けてある
+fn get_border_color(style: &StyledNode, side: &str) -> Option<Color> {
+    get_color(style, &format!("border-{}-color", side))
+}
+
+// 色が指定されていればそれを返す
+fn get_color(style: &StyledNode, name: &str) -> Option<Color> {
+    match style.value(name) {
+        Some(Value::ColorValue(color)) => Some(color),
+        _ => None,
+    }
+}
+
+// ---- rasterize ----
+// GPUは使わずにdisplay listをCPUでピクセルの配列に塗っていく
+
+pub struct Canvas {
+    pub pixels: Vec<Color>,
+    pub width: usize,
+    pub height: usize,
+}
+
+const WHITE: Color = Color {
+    r: 255,
+    g: 255,
+    b: 255,
+    a: 255,
+};
+
+/// layout treeをboundsの大きさのcanvasに描く
+pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
+    let display_list: DisplayList = build_display_list(layout_root);
+    let mut canvas: Canvas = Canvas::new(bounds.width as usize, bounds.height as usize);
+    for item in &display_list {
+        canvas.paint_item(item);
+    }
+    canvas
+}
+
+impl Canvas {
+    // 白で塗りつぶしたcanvasを作る
+    pub fn new(width: usize, height: usize) -> Canvas {
+        Canvas {
+            pixels: vec![WHITE; width * height],
+            width,
+            height,
+        }
+    }
+
+    pub fn paint_item(&mut self, item: &DisplayCommand) {
+        match *item {
+            DisplayCommand::SolidColor(color, rect) => self.fill_rect(color, rect),
+            DisplayCommand::Text {
+                ref text,
+                rect,
+                color,
+                font_size,
+            } => self.draw_text(text, rect, color, font_size),
+        }
+    }
+
+    // canvasからはみ出した部分は切り捨てて矩形を塗る
+    fn fill_rect(&mut self, color: Color, rect: Rect) {
+        if color.a == 0 {
+            return;
+        }
+        let x0: usize = rect.x.clamp(0.0, self.width as f32).round() as usize;
+        let y0: usize = rect.y.clamp(0.0, self.height as f32).round() as usize;
+        let x1: usize = (rect.x + rect.width).clamp(0.0, self.width as f32).round() as usize;
+        let y1: usize = (rect.y + rect.height)
+            .clamp(0.0, self.height as f32)
+            .round() as usize;
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let pixel: &mut Color = &mut self.pixels[y * self.width + x];
+                *pixel = blend(color, *pixel);
+            }
+        }
+    }
+
+    // 1文字ずつビットマップフォントを拡大して描く
+    // 文字の横幅はlayoutと同じfont::char_widthで進める
+    fn draw_text(&mut self, text: &str, rect: Rect, color: Color, font_size: f32) {
+        // em boxを縦10等分して、上から1..8の部分にグリフの7行を置く
+        let unit_y: f32 = font_size / 10.0;
+        let mut x: f32 = rect.x;
+        for c in text.chars() {
+            let advance: f32 = font::char_width(c, font_size);
+            match font::glyph(c) {
+                Some(rows) => {
+                    // グリフの5列と隙間の1列で文字の幅を6等分する
+                    let unit_x: f32 = advance / (font::GLYPH_WIDTH + 1) as f32;
+                    for (row, bits) in rows.iter().enumerate() {
+                        for col in 0..font::GLYPH_WIDTH {
+                            if bits & (1 << (font::GLYPH_WIDTH - 1 - col)) != 0 {
+                                self.fill_rect(
+                                    color,
+                                    Rect {
+                                        x: x + col as f32 * unit_x,
+                                        y: rect.y + (row + 1) as f32 * unit_y,
+                                        width: unit_x,
+                                        height: unit_y,
+                                    },
+                                );
+                            }
+                        }
+                    }
+                }
+                // フォントにない文字は枠だけの四角にする
+                None if !c.is_whitespace() => {
+                    let inset: f32 = advance / 10.0;
+                    let outer: Rect = Rect {
+                        x: x + inset,
+                        y: rect.y + unit_y,
+                        width: advance - inset * 2.0,
+                        height: unit_y * 8.0,
+                    };
+                    let line: f32 = unit_y.max(1.0);
+                    self.fill_rect(
+                        color,
+                        Rect {
+                            height: line,
+                            ..outer
+                        },
+                    );
+                    self.fill_rect(
+                        color,
+                        Rect {
+                            y: outer.y + outer.height - line,
+                            height: line,
+                            ..outer
+                        },
+                    );
+                    self.fill_rect(
+                        color,
+                        Rect {
+                            width: line,
+                            ..outer
+                        },
+                    );
+                    self.fill_rect(
+                        color,
+                        Rect {
+                            x: outer.x + outer.width - line,
+                            width: line,
+                            ..outer
+                        },
+                    );
+                }
+                None => {}
+            }
+            x += advance;
+        }
+    }
+}
+
+// aを使って上の色(src)を下の色(dst)に重ねる(source-over)
+fn blend(src: Color, dst: Color) -> Color {
+    if src.a == 255 {
+        return src;
+    }
+    let src_a: f32 = src.a as f32 / 255.0;
+    let dst_a: f32 = dst.a as f32 / 255.0;
+    let out_a: f32 = src_a + dst_a * (1.0 - src_a);
+    if out_a == 0.0 {
+        return Color::default();
+    }
+    let channel = |s: u8, d: u8| -> u8 {
+        ((s as f32 * src_a + d as f32 * dst_a * (1.0 - src_a)) / out_a).round() as u8
+    };
+    Color {
+        r: channel(src.r, dst.r),
+        g: channel(src.g, dst.g),
+        b: channel(src.b, dst.b),
+        a: (out_a * 255.0).round() as u8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{css, layout, style};
+
+    fn display_list(root: &dom::Node, css: &str) -> DisplayList {
+        let sheet: css::Stylesheet = css::parse(css.to_string()).unwrap();
+        let styled: StyledNode = style::style_tree(root, std::slice::from_ref(&sheet));
+        let mut viewport: Dimensions = Default::default();
+        viewport.content.width = 100.0;
+        let layout_root: LayoutBox = layout::layout_tree(&styled, viewport);
+        build_display_list(&layout_root)
+    }
+
+    fn color(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b, a: 255 }
+    }
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    // 背景、border(左右上下)、子の順に並ぶ
+    #[test]
+    fn background_then_borders_then_children() {
+        let root: dom::Node = dom::elem(
+            "div".to_string(),
+            dom::AttrMap::new(),
+            vec![dom::elem(
+                "section".to_string(),
+                dom::AttrMap::new(),
+                Vec::new(),
+            )],
+        );
+        let list: DisplayList = display_list(
+            &root,
+            "div { display: block; background-color: #ff0000; border-color: #0000ff;
+                   border-left-width: 1px; border-right-width: 2px;
+                   border-top-width: 3px; border-bottom-width: 4px; }
+             section { display: block; height: 10px; background-color: #00ff00; }",
+        );
+        assert_eq!(
+            list,
+            vec![
+                DisplayCommand::SolidColor(color(255, 0, 0), rect(0.0, 0.0, 100.0, 17.0)),
+                DisplayCommand::SolidColor(color(0, 0, 255), rect(0.0, 0.0, 1.0, 17.0)),
+                DisplayCommand::SolidColor(color(0, 0, 255), rect(98.0, 0.0, 2.0, 17.0)),
+                DisplayCommand::SolidColor(color(0, 0, 255), rect(0.0, 0.0, 100.0, 3.0)),
+                DisplayCommand::SolidColor(color(0, 0, 255), rect(0.0, 13.0, 100.0, 4.0)),
+                DisplayCommand::SolidColor(color(0, 255, 0), rect(1.0, 3.0, 97.0, 10.0)),
+            ]
+        );
+    }
+
+    // 文字色は親から受け継いで、inline要素は断片ごとに背景を塗る
+    #[test]
+    fn text_inherits_color() {
+        let root: dom::Node = dom::elem(
+            "div".to_string(),
+            dom::AttrMap::new(),
+            vec![dom::elem(
+                "span".to_string(),
+                dom::AttrMap::new(),
+                vec![dom::text("ab".to_string())],
+            )],
+        );
+        let list: DisplayList = display_list(
+            &root,
+            "div { display: block; color: #ff0000; } span { background-color: #00ff00; }",
+        );
+        assert_eq!(list.len(), 2);
+        assert!(matches!(
+            list[0],
+            DisplayCommand::SolidColor(c, r) if c == color(0, 255, 0) && r.width == 16.0
+        ));
+        match &list[1] {
+            DisplayCommand::Text { text, color: c, .. } => {
+                assert_eq!(text, "ab");
+                assert_eq!(*c, color(255, 0, 0));
+            }
+            other => panic!("expected text, got {:?}", other),
+        }
+    }
+
+    // 色の名前もhexと同じように塗れる。longhandでもshorthandの中でも同じ
+    #[test]
+    fn named_colors_are_painted() {
+        let root: dom::Node = dom::elem("div".to_string(), dom::AttrMap::new(), Vec::new());
+        let list: DisplayList = display_list(
+            &root,
+            "div { display: block; height: 10px; background-color: Green; border-left: 1px solid navy; }",
+        );
+        assert_eq!(
+            list,
+            vec![
+                DisplayCommand::SolidColor(color(0, 128, 0), rect(0.0, 0.0, 100.0, 10.0)),
+                DisplayCommand::SolidColor(color(0, 0, 128), rect(0.0, 0.0, 1.0, 10.0)),
+            ]
+        );
+    }
+
+    // canvasの外にはみ出した部分は塗らない
+    #[test]
+    fn fill_rect_clips_to_canvas() {
+        let mut canvas: Canvas = Canvas::new(4, 3);
+        canvas.fill_rect(color(255, 0, 0), rect(-2.0, 1.0, 4.0, 10.0));
+        let red: Vec<bool> = canvas
+            .pixels
+            .iter()
+            .map(|p: &Color| *p == color(255, 0, 0))
+            .collect();
+        assert_eq!(
+            red,
+            vec![
+                false, false, false, false, //
+                true, true, false, false, //
+                true, true, false, false,
+            ]
+        );
+    }
+
+    // 半透明の赤を白に重ねると薄い赤になる
+    #[test]
+    fn blend_partially_transparent_over_white() {
+        let half_red: Color = Color {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 128,
+        };
+        assert_eq!(blend(half_red, WHITE), color(255, 127, 127));
+        // 透明なら何も変わらない
+        let mut canvas: Canvas = Canvas::new(1, 1);
+        canvas.fill_rect(Color::default(), rect(0.0, 0.0, 1.0, 1.0));
+        assert_eq!(canvas.pixels[0], WHITE);
+    }
+}
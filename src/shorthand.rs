@@ -0,0 +1,1000 @@
+// margin: 10px autoとかborder: 1px solid #000みたいなshorthandをlonghandに分けるファイル
+// css.rsのparserが値を読んで、ここでどのlonghandに入れるか決める
+// https://drafts.csswg.org/css-cascade/#shorthand
+
+use crate::css::{self, Unit, Value};
+
+// shorthandの値を空白で区切ったもの。/と,も区切りとして入れておく
+#[derive(Debug, Clone, PartialEq)]
+pub enum Component {
+    Value(Value),
+    // "Times New Roman"みたいにクォートしたもの
+    String(String),
+    Slash,
+    Comma,
+}
+
+const SIDES: [&str; 4] = ["top", "right", "bottom", "left"];
+
+const BORDER_STYLES: [&str; 10] = [
+    "none", "hidden", "dotted", "dashed", "solid", "double", "groove", "ridge", "inset", "outset",
+];
+
+/// nameがshorthandならlonghandの名前を全部返す
+/// font-familyは分けないけど、,で並べて書けるのでここで読む
+pub fn longhands(name: &str) -> Option<Vec<String>> {
+    let sides = |prefix: &str, suffix: &str| -> Vec<String> {
+        SIDES
+            .iter()
+            .map(|side: &&str| format!("{}-{}{}", prefix, side, suffix))
+            .collect()
+    };
+    let names = |names: &[&str]| -> Vec<String> {
+        names.iter().map(|name: &&str| name.to_string()).collect()
+    };
+    Some(match name {
+        "margin" => sides("margin", ""),
+        "padding" => sides("padding", ""),
+        "border-width" => sides("border", "-width"),
+        "border-style" => sides("border", "-style"),
+        "border-color" => sides("border", "-color"),
+        "border" => ["-width", "-style", "-color"]
+            .iter()
+            .flat_map(|suffix: &&str| sides("border", suffix))
+            .collect(),
+        "border-top" | "border-right" | "border-bottom" | "border-left" => {
+            ["-width", "-style", "-color"]
+                .iter()
+                .map(|suffix: &&str| format!("{}{}", name, suffix))
+                .collect()
+        }
+        "font" => names(&[
+            "font-style",
+            "font-variant",
+            "font-weight",
+            "font-size",
+            "line-height",
+            "font-family",
+        ]),
+        "font-family" => names(&["font-family"]),
+        "background" => names(&[
+            "background-color",
+            "background-image",
+            "background-repeat",
+            "background-attachment",
+            "background-position",
+        ]),
+        "flex" => names(&["flex-grow", "flex-shrink", "flex-basis"]),
+        "flex-flow" => names(&["flex-direction", "flex-wrap"]),
+        "grid-row" => names(&["grid-row-start", "grid-row-end"]),
+        "grid-column" => names(&["grid-column-start", "grid-column-end"]),
+        "grid-area" => names(&[
+            "grid-row-start",
+            "grid-column-start",
+            "grid-row-end",
+            "grid-column-end",
+        ]),
+        "gap" | "grid-gap" => names(&["row-gap", "column-gap"]),
+        "grid-template" => names(&[
+            "grid-template-rows",
+            "grid-template-columns",
+            "grid-template-areas",
+        ]),
+        "grid" => names(&[
+            "grid-template-rows",
+            "grid-template-columns",
+            "grid-template-areas",
+            "grid-auto-rows",
+            "grid-auto-columns",
+            "grid-auto-flow",
+        ]),
+        _ => return None,
+    })
+}
+
+/// shorthandの値をlonghandに分ける
+/// 書いてないlonghandは初期値にする。おかしい値なら何を期待してたかを返す
+pub fn expand(name: &str, components: &[Component]) -> Result<Vec<(String, Value)>, String> {
+    let names: Vec<String> = longhands(name).ok_or_else(|| "a shorthand".to_string())?;
+    // inheritとかは1つだけで書いて、全部のlonghandに入れる
+    if let [Component::Value(Value::Keyword(keyword))] = components {
+        if ["inherit", "initial", "unset", "revert"].contains(&&*keyword.to_ascii_lowercase()) {
+            return Ok(names
+                .into_iter()
+                .map(|name: String| (name, Value::Keyword(keyword.to_ascii_lowercase())))
+                .collect());
+        }
+    }
+    let values: Vec<Value> = match name {
+        "margin" => expand_sides(components, "lengths or 'auto'", |value: &Value| {
+            length(value).or_else(|| keyword_in(value, &["auto"]))
+        })?,
+        "padding" => expand_sides(components, "lengths", length)?,
+        "border-width" => expand_sides(components, "border widths", border_width)?,
+        "border-style" => expand_sides(components, "border styles", |value: &Value| {
+            keyword_in(value, &BORDER_STYLES)
+        })?,
+        "border-color" => expand_sides(components, "colors", color)?,
+        "border" => {
+            let (width, style, color): (Value, Value, Value) = expand_border(components)?;
+            [width, style, color]
+                .iter()
+                .flat_map(|value: &Value| std::iter::repeat_n(value.clone(), 4))
+                .collect()
+        }
+        "border-top" | "border-right" | "border-bottom" | "border-left" => {
+            let (width, style, color): (Value, Value, Value) = expand_border(components)?;
+            vec![width, style, color]
+        }
+        "font" => expand_font(components)?,
+        "font-family" => vec![font_family(components)?],
+        "background" => expand_background(components)?,
+        "flex" => expand_flex(components)?,
+        "flex-flow" => expand_flex_flow(components)?,
+        "grid-row" | "grid-column" => expand_grid_lines(components, 2)?,
+        "grid-area" => expand_grid_lines(components, 4)?,
+        "gap" | "grid-gap" => {
+            let values: Vec<Value> = plain_values(components, "one or two lengths")?;
+            let gaps: Option<Vec<Value>> = values
+                .iter()
+                .map(|value: &Value| length(value).or_else(|| keyword_in(value, &["normal"])))
+                .collect();
+            match gaps {
+                Some(gaps) if gaps.len() == 1 => vec![gaps[0].clone(), gaps[0].clone()],
+                Some(gaps) if gaps.len() == 2 => gaps,
+                _ => return Err("one or two lengths".to_string()),
+            }
+        }
+        "grid-template" => expand_grid_template(components)?,
+        "grid" => expand_grid(components)?,
+        _ => return Err("a shorthand".to_string()),
+    };
+    Ok(names.into_iter().zip(values).collect())
+}
+
+// /とか,とか文字列がない、空白で区切っただけの値
+fn plain_values(components: &[Component], expected: &str) -> Result<Vec<Value>, String> {
+    let values: Vec<Value> = components
+        .iter()
+        .map_while(|component: &Component| match *component {
+            Component::Value(ref value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
+    if values.is_empty() || values.len() != components.len() {
+        return Err(expected.to_string());
+    }
+    Ok(values)
+}
+
+// 上右下左の順に1〜4個
+// 1つなら全部、2つなら上下と左右、3つなら上、左右、下
+fn expand_sides(
+    components: &[Component],
+    expected: &str,
+    convert: impl Fn(&Value) -> Option<Value>,
+) -> Result<Vec<Value>, String> {
+    let expected: String = format!("1 to 4 {}", expected);
+    let values: Option<Vec<Value>> = plain_values(components, &expected)?
+        .iter()
+        .map(convert)
+        .collect();
+    let values: Vec<Value> = values.ok_or_else(|| expected.clone())?;
+    let indexes: [usize; 4] = match values.len() {
+        1 => [0, 0, 0, 0],
+        2 => [0, 1, 0, 1],
+        3 => [0, 1, 2, 1],
+        4 => [0, 1, 2, 3],
+        _ => return Err(expected),
+    };
+    Ok(indexes
+        .iter()
+        .map(|index: &usize| values[*index].clone())
+        .collect())
+}
+
+// width、style、colorをどの順番でも1つずつ書ける
+fn expand_border(components: &[Component]) -> Result<(Value, Value, Value), String> {
+    const EXPECTED: &str = "a border width, style or color";
+    let mut width: Option<Value> = None;
+    let mut style: Option<Value> = None;
+    let mut color: Option<Value> = None;
+    for value in plain_values(components, EXPECTED)? {
+        let slot: (&mut Option<Value>, Value) = if let Some(w) = border_width(&value) {
+            (&mut width, w)
+        } else if let Some(s) = keyword_in(&value, &BORDER_STYLES) {
+            (&mut style, s)
+        } else if let Some(c) = self::color(&value) {
+            (&mut color, c)
+        } else {
+            return Err(EXPECTED.to_string());
+        };
+        // 同じものを2回は書けない
+        if slot.0.replace(slot.1).is_some() {
+            return Err(EXPECTED.to_string());
+        }
+    }
+    Ok((
+        width.unwrap_or_else(|| keyword("medium")),
+        style.unwrap_or_else(|| keyword("none")),
+        color.unwrap_or_else(|| keyword("currentcolor")),
+    ))
+}
+
+// font: [style || variant || weight] size[/line-height] family
+// https://drafts.csswg.org/css-fonts/#font-prop
+fn expand_font(components: &[Component]) -> Result<Vec<Value>, String> {
+    let mut style: Option<Value> = None;
+    let mut variant: Option<Value> = None;
+    let mut weight: Option<Value> = None;
+    let mut index: usize = 0;
+    // normalはどれのことかわからないので、3つまでなら何個あってもいい
+    let mut normals: usize = 0;
+    while let Some(Component::Value(ref value)) = components.get(index) {
+        let slot: &mut Option<Value> = if keyword_in(value, &["normal"]).is_some() {
+            normals += 1;
+            index += 1;
+            continue;
+        } else if keyword_in(value, &["italic", "oblique"]).is_some() {
+            &mut style
+        } else if keyword_in(value, &["small-caps"]).is_some() {
+            &mut variant
+        } else if font_weight(value).is_some() {
+            &mut weight
+        } else {
+            break;
+        };
+        if slot.replace(value.clone()).is_some() {
+            return Err("a font size".to_string());
+        }
+        index += 1;
+    }
+    if normals + style.iter().count() + variant.iter().count() + weight.iter().count() > 3 {
+        return Err("a font size".to_string());
+    }
+
+    let size: Value = match components.get(index) {
+        Some(Component::Value(ref value)) => font_size(value),
+        _ => None,
+    }
+    .ok_or_else(|| "a font size".to_string())?;
+    index += 1;
+
+    let mut line_height: Value = keyword("normal");
+    if components.get(index) == Some(&Component::Slash) {
+        line_height = match components.get(index + 1) {
+            Some(Component::Value(ref value)) => match *value {
+                Value::Length(..) | Value::Number(_) => Some(value.clone()),
+                _ => keyword_in(value, &["normal"]),
+            },
+            _ => None,
+        }
+        .ok_or_else(|| "a line height".to_string())?;
+        index += 2;
+    }
+
+    Ok(vec![
+        style.unwrap_or_else(|| keyword("normal")),
+        variant.unwrap_or_else(|| keyword("normal")),
+        weight.unwrap_or_else(|| keyword("normal")),
+        size,
+        line_height,
+        font_family(&components[index..])?,
+    ])
+}
+
+// "Helvetica Neue", Arial, sans-serifを1つのkeywordにまとめる
+// クォートしてない名前は空白でつなぐ
+fn font_family(components: &[Component]) -> Result<Value, String> {
+    const EXPECTED: &str = "a font family";
+    let mut families: Vec<String> = Vec::new();
+    let mut words: Vec<String> = Vec::new();
+    for component in components {
+        match *component {
+            // クォートした名前の後ろには続けられない
+            Component::Value(Value::Keyword(ref word))
+                if !words
+                    .first()
+                    .is_some_and(|first: &String| first.starts_with('"')) =>
+            {
+                words.push(word.clone())
+            }
+            // クォートした名前は1つで1つのfamily
+            Component::String(ref name) if words.is_empty() => {
+                words.push(format!("\"{}\"", name));
+            }
+            Component::Comma if !words.is_empty() => {
+                families.push(words.join(" "));
+                words.clear();
+            }
+            _ => return Err(EXPECTED.to_string()),
+        }
+    }
+    if words.is_empty() {
+        return Err(EXPECTED.to_string());
+    }
+    families.push(words.join(" "));
+    Ok(Value::Keyword(families.join(", ")))
+}
+
+// background: color || image || repeat || attachment || position
+// url()はまだ読めないので、imageはnoneだけ
+fn expand_background(components: &[Component]) -> Result<Vec<Value>, String> {
+    const EXPECTED: &str = "a background color, image, repeat, attachment or position";
+    let mut color: Option<Value> = None;
+    let mut image: Option<Value> = None;
+    let mut repeat: Vec<Value> = Vec::new();
+    let mut attachment: Option<Value> = None;
+    let mut position: Vec<Value> = Vec::new();
+    for value in plain_values(components, EXPECTED)? {
+        let duplicated: bool = if let Some(v) = keyword_in(&value, &["none"]) {
+            image.replace(v).is_some()
+        } else if let Some(v) = keyword_in(
+            &value,
+            &[
+                "repeat",
+                "repeat-x",
+                "repeat-y",
+                "no-repeat",
+                "space",
+                "round",
+            ],
+        ) {
+            repeat.push(v);
+            repeat.len() > 2
+        } else if let Some(v) = keyword_in(&value, &["scroll", "fixed", "local"]) {
+            attachment.replace(v).is_some()
+        } else if let Some(v) = length(&value)
+            .or_else(|| keyword_in(&value, &["left", "right", "top", "bottom", "center"]))
+        {
+            position.push(v);
+            position.len() > 4
+        } else if let Some(v) = self::color(&value) {
+            color.replace(v).is_some()
+        } else {
+            return Err(EXPECTED.to_string());
+        };
+        if duplicated {
+            return Err(EXPECTED.to_string());
+        }
+    }
+    Ok(vec![
+        color.unwrap_or(Value::ColorValue(css::TRANSPARENT)),
+        image.unwrap_or_else(|| keyword("none")),
+        list_or_single(repeat).unwrap_or_else(|| keyword("repeat")),
+        attachment.unwrap_or_else(|| keyword("scroll")),
+        list_or_single(position).unwrap_or_else(|| {
+            Value::List(vec![
+                Value::Length(0.0, Unit::Px),
+                Value::Length(0.0, Unit::Px),
+            ])
+        }),
+    ])
+}
+
+// flex: none | auto | <grow> <shrink>? || <basis>
+// https://drafts.csswg.org/css-flexbox/#flex-property
+fn expand_flex(components: &[Component]) -> Result<Vec<Value>, String> {
+    const EXPECTED: &str = "'none', 'auto' or flex factors and a basis";
+    let values: Vec<Value> = plain_values(components, EXPECTED)?;
+    if let [ref value] = values[..] {
+        if keyword_in(value, &["none"]).is_some() {
+            return Ok(vec![
+                Value::Number(0.0),
+                Value::Number(0.0),
+                keyword("auto"),
+            ]);
+        }
+        if keyword_in(value, &["auto"]).is_some() {
+            return Ok(vec![
+                Value::Number(1.0),
+                Value::Number(1.0),
+                keyword("auto"),
+            ]);
+        }
+    }
+    let mut factors: Vec<Value> = Vec::new();
+    let mut basis: Option<Value> = None;
+    // growとshrinkは続けて書く。basisはその前でも後ろでもいい
+    let mut previous_was_factor: bool = false;
+    for value in &values {
+        let factor: bool = matches!(*value, Value::Number(n) if n >= 0.0)
+            && (factors.is_empty() || factors.len() == 1 && previous_was_factor);
+        if factor {
+            factors.push(value.clone());
+        } else {
+            let b: Value = length(value)
+                .or_else(|| keyword_in(value, &["auto", "content"]))
+                .ok_or_else(|| EXPECTED.to_string())?;
+            if basis.replace(b).is_some() {
+                return Err(EXPECTED.to_string());
+            }
+        }
+        previous_was_factor = factor;
+    }
+    let mut factors: std::vec::IntoIter<Value> = factors.into_iter();
+    Ok(vec![
+        factors.next().unwrap_or(Value::Number(1.0)),
+        factors.next().unwrap_or(Value::Number(1.0)),
+        // factorだけなら0
+        basis.unwrap_or(Value::Length(0.0, Unit::Px)),
+    ])
+}
+
+// flex-flow: direction || wrap
+fn expand_flex_flow(components: &[Component]) -> Result<Vec<Value>, String> {
+    const EXPECTED: &str = "a flex direction or wrap";
+    let mut direction: Option<Value> = None;
+    let mut wrap: Option<Value> = None;
+    for value in plain_values(components, EXPECTED)? {
+        let duplicated: bool = if let Some(v) =
+            keyword_in(&value, &["row", "row-reverse", "column", "column-reverse"])
+        {
+            direction.replace(v).is_some()
+        } else if let Some(v) = keyword_in(&value, &["nowrap", "wrap", "wrap-reverse"]) {
+            wrap.replace(v).is_some()
+        } else {
+            return Err(EXPECTED.to_string());
+        };
+        if duplicated {
+            return Err(EXPECTED.to_string());
+        }
+    }
+    Ok(vec![
+        direction.unwrap_or_else(|| keyword("row")),
+        wrap.unwrap_or_else(|| keyword("nowrap")),
+    ])
+}
+
+// grid-row: 1 / 3とかgrid-area: a / b / c / d。/で区切って最大count個
+// 書いてないものは、対応する前の値が名前ならそれ、そうでなければauto
+// https://drafts.csswg.org/css-grid/#placement-shorthands
+fn expand_grid_lines(components: &[Component], count: usize) -> Result<Vec<Value>, String> {
+    let lines: Vec<Value> = components
+        .split(|component: &Component| *component == Component::Slash)
+        .map(grid_line)
+        .collect::<Result<Vec<Value>, String>>()?;
+    if lines.len() > count {
+        return Err(format!("at most {} grid lines", count));
+    }
+    let fallback = |line: &Value| -> Value {
+        match *line {
+            Value::Keyword(ref name) if !name.eq_ignore_ascii_case("auto") => line.clone(),
+            _ => keyword("auto"),
+        }
+    };
+    let mut result: Vec<Value> = lines;
+    while result.len() < count {
+        // grid-areaは(row-start, column-start, row-end, column-end)の順なので
+        // column-endはcolumn-startから、それ以外はrow-startから決める
+        let from: usize = if result.len() == 3 { 1 } else { 0 };
+        let line: Value = fallback(&result[from]);
+        result.push(line);
+    }
+    Ok(result)
+}
+
+// grid-template: none、rows / columns、"a a" 10px "b c" / columnsのどれか
+// 返すのは(rows, columns, areas)
+// https://drafts.csswg.org/css-grid/#explicit-grid-shorthand
+fn expand_grid_template(components: &[Component]) -> Result<Vec<Value>, String> {
+    const EXPECTED: &str = "'none' or grid tracks";
+    if let [Component::Value(value)] = components {
+        if let Some(none) = keyword_in(value, &["none"]) {
+            return Ok(vec![none.clone(), none.clone(), none]);
+        }
+    }
+    let parts: Vec<&[Component]> = components
+        .split(|component: &Component| *component == Component::Slash)
+        .collect();
+    let (rows, columns): (&[Component], &[Component]) = match parts[..] {
+        [rows, columns] => (rows, columns),
+        // 文字列で書くときはcolumnsを省略できる
+        [rows] if matches!(rows.first(), Some(Component::String(_))) => (rows, &[]),
+        _ => return Err(EXPECTED.to_string()),
+    };
+    let columns: Value = if columns.is_empty() {
+        keyword("none")
+    } else {
+        track_list(columns).ok_or_else(|| EXPECTED.to_string())?
+    };
+    if !matches!(rows.first(), Some(Component::String(_))) {
+        let rows: Value = track_list(rows).ok_or_else(|| EXPECTED.to_string())?;
+        return Ok(vec![rows, columns, keyword("none")]);
+    }
+    // 文字列のあとにその行の大きさを書ける。書かなければauto
+    let mut areas: Vec<String> = Vec::new();
+    let mut sizes: Vec<Value> = Vec::new();
+    // 1つの行に大きさは1つだけ
+    let mut sized: bool = false;
+    for component in rows {
+        match *component {
+            Component::String(ref row) => {
+                areas.push(format!("\"{}\"", row));
+                sizes.push(keyword("auto"));
+                sized = false;
+            }
+            Component::Value(ref value) if !sized => {
+                let size: Value = track_size(value).ok_or_else(|| EXPECTED.to_string())?;
+                *sizes.last_mut().unwrap() = size;
+                sized = true;
+            }
+            _ => return Err(EXPECTED.to_string()),
+        }
+    }
+    Ok(vec![
+        list_or_single(sizes).unwrap(),
+        columns,
+        Value::Keyword(areas.join(" ")),
+    ])
+}
+
+// grid: grid-templateか、rows / auto-flow dense? columnsか、auto-flow dense? rows / columns
+// 返すのは(template-rows, template-columns, areas, auto-rows, auto-columns, auto-flow)
+fn expand_grid(components: &[Component]) -> Result<Vec<Value>, String> {
+    const EXPECTED: &str = "a grid template or 'auto-flow'";
+    let parts: Vec<&[Component]> = components
+        .split(|component: &Component| *component == Component::Slash)
+        .collect();
+    let is_auto_flow = |part: &[Component]| -> bool {
+        part.iter().any(|component: &Component| {
+            matches!(component, Component::Value(value) if keyword_in(value, &["auto-flow"]).is_some())
+        })
+    };
+    let auto: Value = keyword("auto");
+    match parts[..] {
+        [rows, columns] if is_auto_flow(columns) => {
+            let rows: Value = track_list(rows).ok_or_else(|| EXPECTED.to_string())?;
+            let (flow, tracks): (Value, Value) =
+                auto_flow(columns, "column").ok_or_else(|| EXPECTED.to_string())?;
+            Ok(vec![
+                rows,
+                keyword("none"),
+                keyword("none"),
+                auto.clone(),
+                tracks,
+                flow,
+            ])
+        }
+        [rows, columns] if is_auto_flow(rows) => {
+            let columns: Value = track_list(columns).ok_or_else(|| EXPECTED.to_string())?;
+            let (flow, tracks): (Value, Value) =
+                auto_flow(rows, "row").ok_or_else(|| EXPECTED.to_string())?;
+            Ok(vec![
+                keyword("none"),
+                columns,
+                keyword("none"),
+                tracks,
+                auto,
+                flow,
+            ])
+        }
+        // grid-templateと同じ書き方なら、auto系は初期値に戻す
+        _ => {
+            let mut values: Vec<Value> =
+                expand_grid_template(components).map_err(|_| EXPECTED.to_string())?;
+            values.extend([auto.clone(), auto, keyword("row")]);
+            Ok(values)
+        }
+    }
+}
+
+// auto-flowとdenseはどっちが先でもいい。そのあとにauto-rowsかauto-columnsのtrackを書ける
+fn auto_flow(components: &[Component], direction: &str) -> Option<(Value, Value)> {
+    let (mut auto_flow, mut dense): (bool, bool) = (false, false);
+    let mut index: usize = 0;
+    while let Some(Component::Value(value)) = components.get(index) {
+        match keyword_in(value, &["auto-flow", "dense"]) {
+            Some(Value::Keyword(ref name)) if name == "auto-flow" && !auto_flow => auto_flow = true,
+            Some(Value::Keyword(ref name)) if name == "dense" && !dense => dense = true,
+            _ => break,
+        }
+        index += 1;
+    }
+    if !auto_flow {
+        return None;
+    }
+    let tracks: Value = if index == components.len() {
+        keyword("auto")
+    } else {
+        track_list(&components[index..])?
+    };
+    let flow: Value = if dense {
+        Value::List(vec![keyword(direction), keyword("dense")])
+    } else {
+        keyword(direction)
+    };
+    Some((flow, tracks))
+}
+
+// 10px autoみたいなtrackの並び。frとrepeat()はまだ読めない
+fn track_list(components: &[Component]) -> Option<Value> {
+    if let [Component::Value(value)] = components {
+        if let Some(none) = keyword_in(value, &["none"]) {
+            return Some(none);
+        }
+    }
+    let tracks: Option<Vec<Value>> = components
+        .iter()
+        .map(|component: &Component| match *component {
+            Component::Value(ref value) => track_size(value),
+            _ => None,
+        })
+        .collect();
+    list_or_single(tracks?)
+}
+
+fn track_size(value: &Value) -> Option<Value> {
+    length(value).or_else(|| keyword_in(value, &["auto", "min-content", "max-content"]))
+}
+
+// auto、3、name、span 2、span nameとか
+fn grid_line(components: &[Component]) -> Result<Value, String> {
+    const EXPECTED: &str = "a grid line";
+    let values: Vec<Value> = plain_values(components, EXPECTED)?;
+    let (mut integers, mut spans, mut names, mut autos): (usize, usize, usize, usize) =
+        (0, 0, 0, 0);
+    for value in &values {
+        match *value {
+            Value::Number(n) if n.fract() == 0.0 && n != 0.0 => integers += 1,
+            Value::Keyword(ref name) if name.eq_ignore_ascii_case("span") => spans += 1,
+            Value::Keyword(ref name) if name.eq_ignore_ascii_case("auto") => autos += 1,
+            Value::Keyword(_) => names += 1,
+            _ => return Err(EXPECTED.to_string()),
+        }
+    }
+    // autoは1つだけで書く。spanだけもだめ
+    let valid: bool = if autos > 0 {
+        values.len() == 1
+    } else {
+        integers <= 1 && spans <= 1 && names <= 1 && integers + names >= 1
+    };
+    if !valid {
+        return Err(EXPECTED.to_string());
+    }
+    Ok(list_or_single(values).unwrap())
+}
+
+fn keyword(name: &str) -> Value {
+    Value::Keyword(name.to_string())
+}
+
+// valueがkeywordsのどれかなら小文字にして返す
+fn keyword_in(value: &Value, keywords: &[&str]) -> Option<Value> {
+    match *value {
+        Value::Keyword(ref name) => {
+            let name: String = name.to_ascii_lowercase();
+            keywords
+                .contains(&name.as_str())
+                .then_some(Value::Keyword(name))
+        }
+        _ => None,
+    }
+}
+
+// 単位のない0は長さの0として扱う
+fn length(value: &Value) -> Option<Value> {
+    match *value {
+        Value::Length(..) => Some(value.clone()),
+        Value::Number(0.0) => Some(Value::Length(0.0, Unit::Px)),
+        _ => None,
+    }
+}
+
+fn border_width(value: &Value) -> Option<Value> {
+    length(value).or_else(|| keyword_in(value, &["thin", "medium", "thick"]))
+}
+
+// #fffか、redとかの色の名前かcurrentcolorかtransparent
+// 色の名前はcss.rsと同じでColorValueにする。それ以外のkeywordは色じゃないのでdeclarationごと捨てる
+fn color(value: &Value) -> Option<Value> {
+    match *value {
+        Value::ColorValue(_) => Some(value.clone()),
+        Value::Keyword(ref name) if name.eq_ignore_ascii_case("currentcolor") => {
+            Some(keyword("currentcolor"))
+        }
+        Value::Keyword(ref name) => css::named_color(name).map(Value::ColorValue),
+        _ => None,
+    }
+}
+
+fn font_weight(value: &Value) -> Option<Value> {
+    match *value {
+        Value::Number(n) if (1.0..=1000.0).contains(&n) => Some(value.clone()),
+        _ => keyword_in(value, &["bold", "bolder", "lighter"]),
+    }
+}
+
+fn font_size(value: &Value) -> Option<Value> {
+    length(value).or_else(|| {
+        keyword_in(
+            value,
+            &[
+                "xx-small", "x-small", "small", "medium", "large", "x-large", "xx-large", "larger",
+                "smaller",
+            ],
+        )
+    })
+}
+
+// 1つならそのまま、2つ以上ならList
+fn list_or_single(mut values: Vec<Value>) -> Option<Value> {
+    match values.len() {
+        0 => None,
+        1 => values.pop(),
+        _ => Some(Value::List(values)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn components(values: &[Value]) -> Vec<Component> {
+        values.iter().cloned().map(Component::Value).collect()
+    }
+
+    fn px(n: f32) -> Value {
+        Value::Length(n, Unit::Px)
+    }
+
+    // longhandの値だけを順に並べる
+    fn expanded(name: &str, components: &[Component]) -> Vec<Value> {
+        expand(name, components)
+            .unwrap()
+            .into_iter()
+            .map(|(_, value): (String, Value)| value)
+            .collect()
+    }
+
+    #[test]
+    fn sides_from_one_to_four_values() {
+        assert_eq!(
+            expanded("margin", &components(&[px(1.0)])),
+            vec![px(1.0), px(1.0), px(1.0), px(1.0)]
+        );
+        assert_eq!(
+            expanded("margin", &components(&[px(1.0), keyword("AUTO")])),
+            vec![px(1.0), keyword("auto"), px(1.0), keyword("auto")]
+        );
+        assert_eq!(
+            expanded("padding", &components(&[px(1.0), px(2.0), px(3.0)])),
+            vec![px(1.0), px(2.0), px(3.0), px(2.0)]
+        );
+        assert_eq!(
+            expand(
+                "border-width",
+                &components(&[px(1.0), keyword("thin"), Value::Number(0.0), px(4.0)])
+            )
+            .unwrap(),
+            vec![
+                ("border-top-width".to_string(), px(1.0)),
+                ("border-right-width".to_string(), keyword("thin")),
+                ("border-bottom-width".to_string(), px(0.0)),
+                ("border-left-width".to_string(), px(4.0)),
+            ]
+        );
+        assert!(expand("padding", &components(&[keyword("auto")])).is_err());
+        assert!(expand("margin", &components(&vec![px(1.0); 5])).is_err());
+    }
+
+    // 書いてないものは初期値、順番は自由
+    #[test]
+    fn border_in_any_order() {
+        let color: Value = Value::ColorValue(crate::css::Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        });
+        let values: Vec<Value> = expanded(
+            "border-left",
+            &components(&[keyword("dashed"), color.clone(), px(2.0)]),
+        );
+        assert_eq!(values, vec![px(2.0), keyword("dashed"), color]);
+        let values: Vec<Value> = expanded("border", &components(&[keyword("solid")]));
+        assert_eq!(values.len(), 12);
+        assert_eq!(values[0], keyword("medium"));
+        assert_eq!(values[4], keyword("solid"));
+        assert_eq!(values[8], keyword("currentcolor"));
+        assert!(expand("border", &components(&[px(1.0), px(2.0)])).is_err());
+    }
+
+    #[test]
+    fn inherit_goes_to_every_longhand() {
+        assert_eq!(
+            expanded("flex-flow", &components(&[keyword("Inherit")])),
+            vec![keyword("inherit"), keyword("inherit")]
+        );
+        assert!(expand("margin", &components(&[keyword("inherit"), px(1.0)])).is_err());
+    }
+
+    #[test]
+    fn font_shorthand() {
+        let mut font: Vec<Component> = components(&[keyword("italic"), keyword("bold"), px(12.0)]);
+        font.extend([
+            Component::Slash,
+            Component::Value(Value::Number(1.5)),
+            Component::String("Helvetica Neue".to_string()),
+            Component::Comma,
+            Component::Value(keyword("Times")),
+            Component::Value(keyword("New")),
+            Component::Value(keyword("Roman")),
+            Component::Comma,
+            Component::Value(keyword("serif")),
+        ]);
+        assert_eq!(
+            expanded("font", &font),
+            vec![
+                keyword("italic"),
+                keyword("normal"),
+                keyword("bold"),
+                px(12.0),
+                Value::Number(1.5),
+                keyword("\"Helvetica Neue\", Times New Roman, serif"),
+            ]
+        );
+        // sizeとfamilyは省略できない
+        assert!(expand("font", &components(&[keyword("bold"), keyword("serif")])).is_err());
+        assert!(expand("font", &components(&[px(12.0)])).is_err());
+    }
+
+    #[test]
+    fn background_shorthand() {
+        let values: Vec<Value> = expanded(
+            "background",
+            &components(&[keyword("no-repeat"), keyword("center"), keyword("fixed")]),
+        );
+        assert_eq!(
+            values,
+            vec![
+                Value::ColorValue(css::TRANSPARENT),
+                keyword("none"),
+                keyword("no-repeat"),
+                keyword("fixed"),
+                keyword("center"),
+            ]
+        );
+    }
+
+    #[test]
+    fn flex_shorthand() {
+        assert_eq!(
+            expanded("flex", &components(&[keyword("none")])),
+            vec![Value::Number(0.0), Value::Number(0.0), keyword("auto")]
+        );
+        assert_eq!(
+            expanded("flex", &components(&[Value::Number(2.0)])),
+            vec![Value::Number(2.0), Value::Number(1.0), px(0.0)]
+        );
+        assert_eq!(
+            expanded(
+                "flex",
+                &components(&[px(30.0), Value::Number(2.0), Value::Number(3.0)])
+            ),
+            vec![Value::Number(2.0), Value::Number(3.0), px(30.0)]
+        );
+        // growとshrinkの間にbasisは入れられない
+        assert!(expand(
+            "flex",
+            &components(&[Value::Number(2.0), px(30.0), Value::Number(3.0)])
+        )
+        .is_err());
+        assert_eq!(
+            expanded("flex-flow", &components(&[keyword("wrap")])),
+            vec![keyword("row"), keyword("wrap")]
+        );
+    }
+
+    #[test]
+    fn grid_lines_fall_back_to_names() {
+        let mut area: Vec<Component> = components(&[keyword("a")]);
+        area.extend([Component::Slash, Component::Value(Value::Number(2.0))]);
+        assert_eq!(
+            expanded("grid-area", &area),
+            vec![
+                keyword("a"),
+                Value::Number(2.0),
+                keyword("a"),
+                keyword("auto")
+            ]
+        );
+        assert_eq!(
+            expanded(
+                "grid-row",
+                &components(&[keyword("span"), Value::Number(2.0)])
+            ),
+            vec![
+                Value::List(vec![keyword("span"), Value::Number(2.0)]),
+                keyword("auto")
+            ]
+        );
+        assert!(expand("grid-column", &components(&[keyword("span")])).is_err());
+        assert_eq!(
+            expanded("gap", &components(&[px(4.0)])),
+            vec![px(4.0), px(4.0)]
+        );
+    }
+
+    #[test]
+    fn border_accepts_named_colors() {
+        let values: Vec<Value> = vec![
+            Value::Length(1.0, Unit::Px),
+            keyword("solid"),
+            keyword("Red"),
+        ];
+        let longhands: Vec<(String, Value)> = expand("border", &components(&values)).unwrap();
+        let red: Value = Value::ColorValue(css::Color {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 255,
+        });
+        assert!(longhands.contains(&("border-top-color".to_string(), red)));
+    }
+
+    #[test]
+    fn border_rejects_unknown_color() {
+        let values: Vec<Value> = vec![
+            Value::Length(1.0, Unit::Px),
+            keyword("solid"),
+            keyword("bogus"),
+        ];
+        assert!(expand("border", &components(&values)).is_err());
+        assert!(expand("border-color", &components(&[keyword("bogus")])).is_err());
+    }
+
+    #[test]
+    fn grid_rows_and_columns() {
+        let mut values: Vec<Component> = components(&[keyword("auto")]);
+        values.push(Component::Slash);
+        values.push(Component::Value(Value::Length(1.0, Unit::Px)));
+        let longhands: Vec<(String, Value)> = expand("grid", &values).unwrap();
+        assert_eq!(longhands.len(), 6);
+        assert!(longhands.contains(&("grid-template-rows".to_string(), keyword("auto"))));
+        assert!(longhands.contains(&(
+            "grid-template-columns".to_string(),
+            Value::Length(1.0, Unit::Px)
+        )));
+        assert!(longhands.contains(&("grid-auto-flow".to_string(), keyword("row"))));
+    }
+
+    #[test]
+    fn grid_template_areas() {
+        let values: Vec<Component> = vec![
+            Component::String("a a".to_string()),
+            Component::Value(Value::Length(40.0, Unit::Px)),
+            Component::String("b c".to_string()),
+        ];
+        let longhands: Vec<(String, Value)> = expand("grid-template", &values).unwrap();
+        assert_eq!(
+            longhands,
+            vec![
+                (
+                    "grid-template-rows".to_string(),
+                    Value::List(vec![Value::Length(40.0, Unit::Px), keyword("auto")])
+                ),
+                ("grid-template-columns".to_string(), keyword("none")),
+                (
+                    "grid-template-areas".to_string(),
+                    keyword("\"a a\" \"b c\"")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn grid_auto_flow() {
+        let mut values: Vec<Component> = components(&[keyword("dense"), keyword("auto-flow")]);
+        values.push(Component::Slash);
+        values.push(Component::Value(Value::Length(5.0, Unit::Px)));
+        let longhands: Vec<(String, Value)> = expand("grid", &values).unwrap();
+        assert!(longhands.contains(&(
+            "grid-auto-flow".to_string(),
+            Value::List(vec![keyword("row"), keyword("dense")])
+        )));
+
+        let twice: Vec<Component> = components(&[keyword("auto-flow"), keyword("auto-flow")]);
+        assert!(expand("grid", &twice).is_err());
+    }
+}
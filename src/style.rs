@@ -1,8 +1,13 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap, sync::OnceLock};
 
 use crate::{
-    css::{Rule, Selector, SimpleSelector, Specificity, Stylesheet, Value},
-    dom::{ElementData, Node},
+    css::{
+        self, AttributeOperator, AttributeSelector, CaseFlag, Combinator, ComplexSelector,
+        ContentItem, Declaration, Origin, PseudoClass, PseudoElement, Rule, Selector,
+        SimpleSelector, Specificity, Stylesheet, Unit, Value,
+    },
+    dom::{self, ElementData, ElementState, Node, NodeType},
+    font,
 };
 
 // styleを表す型
@@ -12,8 +17,12 @@ type PropertyMap = HashMap<String, Value>;
 // どのNodeに何のstyleがついてるかをまとめてる型
 #[derive(Debug)]
 pub struct StyledNode<'a> {
-    pub node: &'a Node, // pointer to a DOM node
+    // ::beforeとかの作ったnodeはDOMにないので自分で持つ
+    pub node: Cow<'a, Node>,
+    // cascadeで決まった、ruleとstyle属性に書いてある値
     pub specified_values: PropertyMap,
+    // specified_valuesに親から継承した値と初期値を足して、inheritとかを解決したもの
+    pub computed_values: PropertyMap,
     pub children: Vec<StyledNode<'a>>,
 }
 
@@ -26,10 +35,26 @@ pub enum Display {
 }
 
 impl<'a> StyledNode<'a> {
-    // Return the specified value of a property if it exists, otherwise `None`.
+    // parent_valuesは親のcomputed_values。rootならNone
+    fn new(
+        node: Cow<'a, Node>,
+        specified_values: PropertyMap,
+        parent_values: Option<&PropertyMap>,
+    ) -> StyledNode<'a> {
+        let computed_values: PropertyMap = computed_values(&specified_values, parent_values);
+        StyledNode {
+            node,
+            specified_values,
+            computed_values,
+            children: Vec::new(),
+        }
+    }
+
+    // Return the computed value of a property: inherited or initial when it is not declared,
+    // with inherit/initial/unset resolved. `None` only if nothing gives it a value.
     // .style{ margin: auto; padding: auto}の部分。これなんて言うのかなstyle?のautoとかの部分全部とってきて配列で返してる
     pub fn value(&self, name: &str) -> Option<Value> {
-        self.specified_values.get(name).map(|v: &Value| v.clone())
+        self.computed_values.get(name).cloned()
     }
 
     // displayのデフォルトをinlineにしてる
@@ -38,7 +63,8 @@ impl<'a> StyledNode<'a> {
         match self.value("display") {
             // Someでそもそもkeywordなんか？みたいなの見てる
             Some(Value::Keyword(s)) => match &*s {
-                "block" => Display::Block,
+                // markerはまだ描かないのでlist-itemはblockと同じ
+                "block" | "list-item" => Display::Block,
                 "none" => Display::None,
                 _ => Display::Inline,
             },
@@ -46,87 +72,827 @@ impl<'a> StyledNode<'a> {
             _ => Display::Inline,
         }
     }
-    pub fn lookup(&self, name: &str, fallback_name: &str, default: &Value) -> Value {
-        self.value(name)
-            .unwrap_or_else(|| self.value(fallback_name).unwrap_or_else(|| default.clone()))
+    // shorthandはparserでlonghandに分けてあるので、longhandだけ見ればいい
+    pub fn lookup(&self, name: &str, default: &Value) -> Value {
+        self.value(name).unwrap_or_else(|| default.clone())
+    }
+}
+
+// 継承するかどうかと初期値がわかっているproperty
+// https://www.w3.org/TR/CSS22/propidx.html
+// borderの幅はborder-styleがないと0になってしまうので、まだここには入れていない
+struct Property {
+    name: &'static str,
+    inherited: bool,
+    initial: fn() -> Value,
+}
+
+const PROPERTIES: [Property; 28] = [
+    Property {
+        name: "color",
+        inherited: true,
+        initial: || {
+            Value::ColorValue(css::Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255,
+            })
+        },
+    },
+    Property {
+        name: "font-size",
+        inherited: true,
+        initial: || Value::Length(font::DEFAULT_FONT_SIZE, Unit::Px),
+    },
+    Property {
+        name: "font-family",
+        inherited: true,
+        initial: || Value::Keyword("sans-serif".to_string()),
+    },
+    Property {
+        name: "font-style",
+        inherited: true,
+        initial: || Value::Keyword("normal".to_string()),
+    },
+    Property {
+        name: "font-weight",
+        inherited: true,
+        initial: || Value::Keyword("normal".to_string()),
+    },
+    Property {
+        name: "font-variant",
+        inherited: true,
+        initial: || Value::Keyword("normal".to_string()),
+    },
+    Property {
+        name: "line-height",
+        inherited: true,
+        initial: || Value::Keyword("normal".to_string()),
+    },
+    Property {
+        name: "text-align",
+        inherited: true,
+        initial: || Value::Keyword("start".to_string()),
+    },
+    Property {
+        name: "text-transform",
+        inherited: true,
+        initial: || Value::Keyword("none".to_string()),
+    },
+    Property {
+        name: "white-space",
+        inherited: true,
+        initial: || Value::Keyword("normal".to_string()),
+    },
+    Property {
+        name: "visibility",
+        inherited: true,
+        initial: || Value::Keyword("visible".to_string()),
+    },
+    Property {
+        name: "list-style-type",
+        inherited: true,
+        initial: || Value::Keyword("disc".to_string()),
+    },
+    Property {
+        name: "direction",
+        inherited: true,
+        initial: || Value::Keyword("ltr".to_string()),
+    },
+    Property {
+        name: "display",
+        inherited: false,
+        initial: || Value::Keyword("inline".to_string()),
+    },
+    Property {
+        name: "width",
+        inherited: false,
+        initial: || Value::Keyword("auto".to_string()),
+    },
+    Property {
+        name: "height",
+        inherited: false,
+        initial: || Value::Keyword("auto".to_string()),
+    },
+    Property {
+        name: "margin-top",
+        inherited: false,
+        initial: || Value::Length(0.0, Unit::Px),
+    },
+    Property {
+        name: "margin-right",
+        inherited: false,
+        initial: || Value::Length(0.0, Unit::Px),
+    },
+    Property {
+        name: "margin-bottom",
+        inherited: false,
+        initial: || Value::Length(0.0, Unit::Px),
+    },
+    Property {
+        name: "margin-left",
+        inherited: false,
+        initial: || Value::Length(0.0, Unit::Px),
+    },
+    Property {
+        name: "padding-top",
+        inherited: false,
+        initial: || Value::Length(0.0, Unit::Px),
+    },
+    Property {
+        name: "padding-right",
+        inherited: false,
+        initial: || Value::Length(0.0, Unit::Px),
+    },
+    Property {
+        name: "padding-bottom",
+        inherited: false,
+        initial: || Value::Length(0.0, Unit::Px),
+    },
+    Property {
+        name: "padding-left",
+        inherited: false,
+        initial: || Value::Length(0.0, Unit::Px),
+    },
+    Property {
+        name: "content",
+        inherited: false,
+        initial: || Value::Keyword("normal".to_string()),
+    },
+    Property {
+        name: "counter-reset",
+        inherited: false,
+        initial: || Value::Keyword("none".to_string()),
+    },
+    Property {
+        name: "counter-increment",
+        inherited: false,
+        initial: || Value::Keyword("none".to_string()),
+    },
+    Property {
+        name: "counter-set",
+        inherited: false,
+        initial: || Value::Keyword("none".to_string()),
+    },
+];
+
+fn property(name: &str) -> Option<&'static Property> {
+    PROPERTIES
+        .iter()
+        .find(|property: &&Property| property.name == name)
+}
+
+// specifiedにある値のinheritとかを解決して、書いてないpropertyは継承するか初期値にする
+// https://drafts.csswg.org/css-cascade/#defaulting
+fn computed_values(specified: &PropertyMap, parent: Option<&PropertyMap>) -> PropertyMap {
+    let mut values: PropertyMap = HashMap::new();
+    for property in &PROPERTIES {
+        if !specified.contains_key(property.name) {
+            let value: Option<Value> = default_value(property.name, parent);
+            values.extend(value.map(|value: Value| (property.name.to_string(), value)));
+        }
+    }
+    for (name, value) in specified {
+        let value: Option<Value> = match *value {
+            Value::Keyword(ref keyword) => match &*keyword.to_ascii_lowercase() {
+                // rootには親がいないので初期値になる
+                "inherit" => parent
+                    .and_then(|parent: &PropertyMap| parent.get(name).cloned())
+                    .or_else(|| property(name).map(|property: &Property| (property.initial)())),
+                "initial" => property(name).map(|property: &Property| (property.initial)()),
+                // revertはcascadeで解決してあるのでここには来ない
+                "unset" => default_value(name, parent),
+                _ => Some(value.clone()),
+            },
+            _ => Some(value.clone()),
+        };
+        values.extend(value.map(|value: Value| (name.clone(), value)));
+    }
+    // font-sizeは子が継承するので、larger、smallerとかはここでpxにしておく
+    let parent_font_size: f32 = parent
+        .and_then(|parent: &PropertyMap| parent.get("font-size"))
+        .map_or(font::DEFAULT_FONT_SIZE, Value::to_px);
+    if let Some(Value::Keyword(keyword)) = values.get("font-size") {
+        let size: f32 = font_size_keyword(keyword, parent_font_size);
+        values.insert("font-size".to_string(), Value::Length(size, Unit::Px));
+    }
+    // borderの幅はthinとかをpxにして、styleがnoneかhiddenなら0にする
+    for side in ["top", "right", "bottom", "left"] {
+        let width_name: String = format!("border-{}-width", side);
+        if let Some(Value::Keyword(keyword)) = values.get(&width_name) {
+            let width: f32 = match &**keyword {
+                "thin" => 1.0,
+                "thick" => 5.0,
+                _ => 3.0,
+            };
+            values.insert(width_name.clone(), Value::Length(width, Unit::Px));
+        }
+        if let Some(Value::Keyword(style)) = values.get(&format!("border-{}-style", side)) {
+            if style == "none" || style == "hidden" {
+                values.insert(width_name, Value::Length(0.0, Unit::Px));
+            }
+        }
+    }
+    // currentcolorはcolorの値。colorに書いてあったら親のcolor
+    let current_color: Option<Value> = match values.get("color") {
+        Some(Value::Keyword(keyword)) if keyword == "currentcolor" => {
+            parent.and_then(|parent: &PropertyMap| parent.get("color").cloned())
+        }
+        color => color.cloned(),
+    };
+    values.extend(
+        current_color
+            .clone()
+            .map(|color: Value| ("color".to_string(), color)),
+    );
+    for (name, value) in values.iter_mut() {
+        if name.ends_with("color") && *value == Value::Keyword("currentcolor".to_string()) {
+            if let Some(ref color) = current_color {
+                *value = color.clone();
+            }
+        }
+    }
+    values
+}
+
+// 書いてないときの値。継承するものは親の値、しないものは初期値
+// どっちもわからないpropertyはなし
+fn default_value(name: &str, parent: Option<&PropertyMap>) -> Option<Value> {
+    let property: &Property = property(name)?;
+    match parent {
+        Some(parent) if property.inherited => parent.get(name).cloned(),
+        _ => Some((property.initial)()),
+    }
+}
+
+// https://drafts.csswg.org/css-fonts/#absolute-size-mapping
+fn font_size_keyword(keyword: &str, parent_size: f32) -> f32 {
+    match keyword {
+        "xx-small" => 9.0,
+        "x-small" => 10.0,
+        "small" => 13.0,
+        "medium" => 16.0,
+        "large" => 18.0,
+        "x-large" => 24.0,
+        "xx-large" => 32.0,
+        "larger" => parent_size * 1.2,
+        "smaller" => parent_size / 1.2,
+        _ => parent_size,
     }
 }
 
 type MatchedRule<'a> = (Specificity, &'a Rule);
 
+// selectorのmatchで使う、treeの中での要素の位置
+// div pとかh1 + pとか:nth-child()のために親と兄弟をたどれるようにしてある
+#[derive(Clone, Copy)]
+struct ElementRef<'a> {
+    elem: &'a ElementData,
+    // rootならNone
+    parent: Option<&'a ElementRef<'a>>,
+    // 自分も含めた兄弟全部で、自分はsiblings[index]。textとかも入ってる
+    siblings: &'a [Node],
+    index: usize,
+}
+
+impl<'a> ElementRef<'a> {
+    // 親、その親...の順
+    fn ancestors(&self) -> impl Iterator<Item = &'a ElementRef<'a>> {
+        std::iter::successors(self.parent, |element: &&ElementRef| element.parent)
+    }
+
+    // 前にある兄弟の要素を近い順に
+    fn previous_elements(&self) -> impl Iterator<Item = ElementRef<'a>> {
+        let parent: Option<&'a ElementRef<'a>> = self.parent;
+        let siblings: &'a [Node] = self.siblings;
+        (0..self.index)
+            .rev()
+            .filter_map(move |index: usize| match siblings[index].node_type {
+                NodeType::Element(ref elem) => Some(ElementRef {
+                    elem,
+                    parent,
+                    siblings,
+                    index,
+                }),
+                _ => None,
+            })
+    }
+
+    // 兄弟の要素の中で何番目か。1から数える
+    fn position(&self) -> usize {
+        self.previous_elements().count() + 1
+    }
+
+    // 後ろに兄弟の要素がなければtrue
+    fn is_last(&self) -> bool {
+        !self.siblings[self.index + 1..]
+            .iter()
+            .any(|sibling: &Node| matches!(sibling.node_type, NodeType::Element(_)))
+    }
+}
+
 //　stylesheetを全てのdomに適用してStyleNodeを返す
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
-    StyledNode {
-        node: root,
-        specified_values: match root.node_type {
-            crate::dom::NodeType::Element(ref elem) => specified_values(elem, stylesheet),
-            crate::dom::NodeType::Text(_) => HashMap::new(),
+// stylesheetsは書かれた順。同じ強さのdeclarationなら後ろのstylesheetのものが勝つ
+// UA stylesheetは一番前に、文書の中の<style>は一番後ろに勝手に足す
+pub fn style_tree<'a>(root: &'a Node, stylesheets: &[Stylesheet]) -> StyledNode<'a> {
+    let style_elements: Vec<Stylesheet> = style_element_sheets(root)
+        .into_iter()
+        .map(|(sheet, _): (Stylesheet, Vec<css::CssError>)| sheet)
+        .collect();
+    style_tree_with_style_elements(root, stylesheets, &style_elements)
+}
+
+/// style_treeと同じだけど、<style>はstyle_element_sheetsでparseしたものを渡す
+/// <style>のエラーを報告したいときに同じものを2回parseしなくて済む
+pub fn style_tree_with_style_elements<'a>(
+    root: &'a Node,
+    stylesheets: &[Stylesheet],
+    style_elements: &[Stylesheet],
+) -> StyledNode<'a> {
+    let stylesheets: Vec<&Stylesheet> = std::iter::once(user_agent_stylesheet())
+        .chain(stylesheets)
+        .chain(style_elements)
+        .collect();
+    let mut counters: Counters = Counters::default();
+    // rootは兄弟のいない一人っ子として扱う
+    style_node(
+        std::slice::from_ref(root),
+        0,
+        None,
+        None,
+        &stylesheets,
+        &mut counters,
+        &mut Vec::new(),
+    )
+}
+
+// siblings[index]のstyleを決める
+// scopeにはこのnodeで作ったcounterの名前を入れる。親の子を全部見終わったら消す
+fn style_node<'a>(
+    siblings: &'a [Node],
+    index: usize,
+    parent: Option<&ElementRef>,
+    parent_values: Option<&PropertyMap>,
+    stylesheets: &[&Stylesheet],
+    counters: &mut Counters,
+    scope: &mut Vec<String>,
+) -> StyledNode<'a> {
+    let node: &'a Node = &siblings[index];
+    let element: ElementRef = match node.node_type {
+        NodeType::Element(ref elem) => ElementRef {
+            elem,
+            parent,
+            siblings,
+            index,
         },
-        // styletreeを再帰的に行なっている
-        children: root
-            .children
-            .iter()
-            .map(|child: &Node| style_tree(child, stylesheet))
-            .collect(),
+        // textは親から継承した値だけ持つ
+        NodeType::Text(_) | NodeType::Comment(_) => {
+            return StyledNode::new(Cow::Borrowed(node), HashMap::new(), parent_values);
+        }
+    };
+    let mut styled: StyledNode = StyledNode::new(
+        Cow::Borrowed(node),
+        specified_values(&element, stylesheets, None),
+        parent_values,
+    );
+    // display: noneの要素は描画されないのでcounterも変えない
+    if styled.display() == Display::None {
+        return styled;
+    }
+    counters.apply(&styled.computed_values, scope);
+
+    // ::beforeと::afterとその中で作ったcounterは子と同じ扱い
+    let mut child_scope: Vec<String> = Vec::new();
+    if let Some(before) = pseudo_element_node(
+        &element,
+        PseudoElement::Before,
+        &styled.computed_values,
+        stylesheets,
+        counters,
+        &mut child_scope,
+    ) {
+        styled.children.push(before);
+    }
+    // styletreeを再帰的に行なっている
+    for (index, child) in node.children.iter().enumerate() {
+        // コメントは描画しないのでstyle treeには入れない
+        if matches!(child.node_type, NodeType::Comment(_)) {
+            continue;
+        }
+        styled.children.push(style_node(
+            &node.children,
+            index,
+            Some(&element),
+            Some(&styled.computed_values),
+            stylesheets,
+            counters,
+            &mut child_scope,
+        ));
+    }
+    if let Some(after) = pseudo_element_node(
+        &element,
+        PseudoElement::After,
+        &styled.computed_values,
+        stylesheets,
+        counters,
+        &mut child_scope,
+    ) {
+        styled.children.push(after);
+    }
+    counters.leave(&child_scope);
+    styled
+}
+
+// ::beforeか::afterのnodeを作る。contentがなければ作らない
+// 中身はcontentから作ったtextを1つだけ持つ
+fn pseudo_element_node<'a>(
+    element: &ElementRef,
+    pseudo: PseudoElement,
+    element_values: &PropertyMap,
+    stylesheets: &[&Stylesheet],
+    counters: &mut Counters,
+    scope: &mut Vec<String>,
+) -> Option<StyledNode<'a>> {
+    let mut styled: StyledNode = StyledNode::new(
+        Cow::Owned(dom::elem(pseudo.to_string(), HashMap::new(), Vec::new())),
+        specified_values(element, stylesheets, Some(pseudo)),
+        Some(element_values),
+    );
+    let items: Vec<ContentItem> = match styled.computed_values.get("content") {
+        Some(Value::Content(items)) => items.clone(),
+        _ => return None,
+    };
+    if styled.display() == Display::None {
+        return None;
+    }
+    counters.apply(&styled.computed_values, scope);
+
+    let mut text: String = String::new();
+    for item in &items {
+        match *item {
+            ContentItem::String(ref s) => text.push_str(s),
+            ContentItem::Attr(ref name) => {
+                if let Some(value) = element.elem.attributes.get(name) {
+                    text.push_str(value);
+                }
+            }
+            ContentItem::Counter(ref name, ref style) => {
+                let value: i32 = *counters.instances(name, scope).last().unwrap();
+                text.push_str(&format_counter(value, style));
+            }
+            ContentItem::Counters(ref name, ref separator, ref style) => {
+                let values: Vec<String> = counters
+                    .instances(name, scope)
+                    .iter()
+                    .map(|value: &i32| format_counter(*value, style))
+                    .collect();
+                text.push_str(&values.join(separator));
+            }
+        }
+    }
+    let text_node: StyledNode = StyledNode::new(
+        Cow::Owned(dom::text(text)),
+        HashMap::new(),
+        Some(&styled.computed_values),
+    );
+    styled.children.push(text_node);
+    Some(styled)
+}
+
+// CSSのcounter。同じ名前のcounterは入れ子になるので名前ごとに外側から順に並べる
+// https://drafts.csswg.org/css-lists/#auto-numbering
+#[derive(Default)]
+struct Counters {
+    instances: HashMap<String, Vec<i32>>,
+}
+
+impl Counters {
+    // counter-reset、counter-increment、counter-setの順に適用する
+    fn apply(&mut self, values: &PropertyMap, scope: &mut Vec<String>) {
+        if let Some(Value::Counters(resets)) = values.get("counter-reset") {
+            for (name, n) in resets {
+                self.instantiate(name, *n, scope);
+            }
+        }
+        if let Some(Value::Counters(increments)) = values.get("counter-increment") {
+            for (name, n) in increments {
+                let value: &mut i32 = self.instances(name, scope).last_mut().unwrap();
+                *value = value.wrapping_add(*n);
+            }
+        }
+        if let Some(Value::Counters(sets)) = values.get("counter-set") {
+            for (name, n) in sets {
+                *self.instances(name, scope).last_mut().unwrap() = *n;
+            }
+        }
+    }
+
+    // nameのcounterを外側から全部。まだなければ0で作る
+    fn instances(&mut self, name: &str, scope: &mut Vec<String>) -> &mut Vec<i32> {
+        if self.instances.get(name).is_none_or(Vec::is_empty) {
+            self.instantiate(name, 0, scope);
+        }
+        self.instances.get_mut(name).unwrap()
+    }
+
+    fn instantiate(&mut self, name: &str, value: i32, scope: &mut Vec<String>) {
+        self.instances
+            .entry(name.to_string())
+            .or_default()
+            .push(value);
+        scope.push(name.to_string());
+    }
+
+    // scopeで作ったcounterを消す
+    fn leave(&mut self, scope: &[String]) {
+        for name in scope {
+            if let Some(instances) = self.instances.get_mut(name) {
+                instances.pop();
+            }
+        }
     }
 }
 
+// counter(name, style)のstyleで数字を文字にする
+// 知らないstyleとか、その書き方で表せない数はdecimalにする
+fn format_counter(value: i32, style: &str) -> String {
+    match style {
+        "none" => String::new(),
+        "lower-alpha" | "lower-latin" if value > 0 => alphabetic(value, b'a'),
+        "upper-alpha" | "upper-latin" if value > 0 => alphabetic(value, b'A'),
+        "lower-roman" if (1..4000).contains(&value) => roman(value).to_ascii_lowercase(),
+        "upper-roman" if (1..4000).contains(&value) => roman(value),
+        _ => value.to_string(),
+    }
+}
+
+// 1 -> a, 26 -> z, 27 -> aa
+fn alphabetic(mut value: i32, first: u8) -> String {
+    let mut letters: Vec<u8> = Vec::new();
+    while value > 0 {
+        value -= 1;
+        letters.push(first + (value % 26) as u8);
+        value /= 26;
+    }
+    letters.reverse();
+    String::from_utf8(letters).unwrap()
+}
+
+fn roman(mut value: i32) -> String {
+    const NUMERALS: [(i32, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    let mut result: String = String::new();
+    for (n, numeral) in NUMERALS {
+        while value >= n {
+            result.push_str(numeral);
+            value -= n;
+        }
+    }
+    result
+}
+
 // elementにstyleを適用させている？
 // ElementDataはただのElementでstylesheetはrule(margin: auto;)とかのvec
-fn specified_values(elem: &ElementData, stylesheet: &Stylesheet) -> PropertyMap {
-    // valuesはdeclarationが追加されていく
-    let mut values: HashMap<String, Value> = HashMap::new();
-    let mut rules: Vec<((usize, usize, usize), &Rule)> = matching_rules(elem, stylesheet);
+// pseudoがSomeなら::beforeとかのstyle
+fn specified_values(
+    element: &ElementRef,
+    stylesheets: &[&Stylesheet],
+    pseudo: Option<PseudoElement>,
+) -> PropertyMap {
+    let mut declarations: Vec<(CascadeKey, Origin, &Declaration)> = Vec::new();
+    for (index, stylesheet) in stylesheets.iter().enumerate() {
+        for (specificity, rule) in matching_rules(element, stylesheet, pseudo) {
+            for declaration in &rule.declarations {
+                let key: CascadeKey = (
+                    precedence(stylesheet.origin, declaration.important),
+                    false,
+                    specificity,
+                    index,
+                    declaration.position,
+                );
+                declarations.push((key, stylesheet.origin, declaration));
+            }
+        }
+    }
+    // style属性は要素自身にしか効かない。どのstylesheetよりも後ろに書いてあることにする
+    let inline: Vec<Declaration> = match pseudo {
+        Some(_) => Vec::new(),
+        None => inline_declarations(element.elem),
+    };
+    for declaration in &inline {
+        let key: CascadeKey = (
+            precedence(Origin::Author, declaration.important),
+            true,
+            (0, 0, 0),
+            stylesheets.len(),
+            declaration.position,
+        );
+        declarations.push((key, Origin::Author, declaration));
+    }
+
+    // propertyごとに弱い順に並べる
+    declarations.sort_by_key(|&(key, _, _): &(CascadeKey, Origin, &Declaration)| key);
+    let mut cascade: HashMap<&str, Vec<(Origin, &Value)>> = HashMap::new();
+    for (_, origin, declaration) in declarations {
+        cascade
+            .entry(&declaration.name)
+            .or_default()
+            .push((origin, &declaration.value));
+    }
+    cascade
+        .into_iter()
+        .filter_map(|(name, candidates): (&str, Vec<(Origin, &Value)>)| {
+            cascaded_value(candidates).map(|value: &Value| (name.to_string(), value.clone()))
+        })
+        .collect()
+}
 
-    // 何かsortしてる。css.rsでもidとかclassでこれやった気がする
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
-    for (_, rule) in rules {
-        for declaration in &rule.declarations {
-            values.insert(declaration.name.clone(), declaration.value.clone());
+// cascadeで並べるときの強さ。小さいほうが弱い
+// https://drafts.csswg.org/css-cascade/#cascade-sort
+// originと!important、style属性かどうか、specificity、書かれた順(何番目のstylesheetの何番目か)の順に比べる
+// CSS 2.1 §6.4.3: style属性はspecificityの一番上の桁が1なので、#idを含めたどのselectorよりも強い
+type CascadeKey = (u8, bool, Specificity, usize, usize);
+
+// !importantのときはoriginの強さが逆になる
+fn precedence(origin: Origin, important: bool) -> u8 {
+    match (important, origin) {
+        (false, Origin::UserAgent) => 0,
+        (false, Origin::User) => 1,
+        (false, Origin::Author) => 2,
+        (true, Origin::Author) => 3,
+        (true, Origin::User) => 4,
+        (true, Origin::UserAgent) => 5,
+    }
+}
+
+// candidatesは弱い順。一番強いものがrevertなら、そのoriginのものは全部なかったことにして次を見る
+// revertしたoriginが!importantかどうかは関係ない
+// https://drafts.csswg.org/css-cascade/#default
+fn cascaded_value(mut candidates: Vec<(Origin, &Value)>) -> Option<&Value> {
+    while let Some((origin, value)) = candidates.pop() {
+        match *value {
+            Value::Keyword(ref keyword) if keyword.eq_ignore_ascii_case("revert") => {
+                candidates.retain(|&(other, _): &(Origin, &Value)| other != origin);
+            }
+            _ => return Some(value),
+        }
+    }
+    None
+}
+
+// style="color: red"の中身。おかしいdeclarationは読み飛ばす
+fn inline_declarations(elem: &ElementData) -> Vec<Declaration> {
+    match elem.attributes.get("style") {
+        Some(style) => css::parse_declaration_block(style.clone()).0,
+        None => Vec::new(),
+    }
+}
+
+/// ブラウザが最初から持ってるstylesheet。最初に呼ばれたときに一回だけparseする
+pub fn user_agent_stylesheet() -> &'static Stylesheet {
+    static STYLESHEET: OnceLock<Stylesheet> = OnceLock::new();
+    STYLESHEET.get_or_init(|| {
+        let mut stylesheet: Stylesheet = css::parse(include_str!("user_agent.css").to_string())
+            .expect("user_agent.css should be valid CSS");
+        stylesheet.origin = Origin::UserAgent;
+        stylesheet
+    })
+}
+
+/// <style>要素をparseしたauthor stylesheetと、読み飛ばしたところのエラー
+/// ブラウザと同じで、おかしいdeclarationは飛ばして残りを使う
+pub fn style_element_sheets(root: &Node) -> Vec<(Stylesheet, Vec<css::CssError>)> {
+    style_element_sources(root)
+        .into_iter()
+        .map(css::parse_lenient)
+        .collect()
+}
+
+/// <style>要素の中身を文書の順に集める
+/// type="text/css"以外のものは無視する
+pub fn style_element_sources(root: &Node) -> Vec<String> {
+    let mut sources: Vec<String> = Vec::new();
+    collect_style_elements(root, &mut sources);
+    sources
+}
+
+fn collect_style_elements(node: &Node, sources: &mut Vec<String>) {
+    if let NodeType::Element(ref elem) = node.node_type {
+        if elem.tag_name.eq_ignore_ascii_case("style")
+            && elem.attributes.get("type").is_none_or(|kind: &String| {
+                kind.is_empty() || kind.eq_ignore_ascii_case("text/css")
+            })
+        {
+            let source: String = node
+                .children
+                .iter()
+                .filter_map(|child: &Node| match child.node_type {
+                    NodeType::Text(ref text) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect();
+            sources.push(source);
+            return;
         }
     }
-    return values;
+    for child in &node.children {
+        collect_style_elements(child, sources);
+    }
 }
 
 //　全てのCSSのruleからそれを持つelementを抽出する
 // stylesheetはruleのvec
-fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a Stylesheet) -> Vec<MatchedRule<'a>> {
+fn matching_rules<'a>(
+    element: &ElementRef,
+    stylesheet: &'a Stylesheet,
+    pseudo: Option<PseudoElement>,
+) -> Vec<MatchedRule<'a>> {
     // match_ruleが通ったものだけ返す
     // match_ruleはNoneを返す場合があるので
     stylesheet
         .rules
         .iter()
-        .filter_map(|rule: &Rule| match_rule(elem, rule))
+        .filter_map(|rule: &Rule| match_rule(element, rule, pseudo))
         .collect()
 }
 
 // rule（例：.style{margin: auto;}）がelem(例：<h1 class="style">)とmatchしたらMatchRuleを返す。そうでなければ何も返さない。
-fn match_rule<'a>(elem: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
+// p::beforeみたいなselectorはpseudoが同じときだけ見る
+fn match_rule<'a>(
+    element: &ElementRef,
+    rule: &'a Rule,
+    pseudo: Option<PseudoElement>,
+) -> Option<MatchedRule<'a>> {
     //　selectorsはstyleを当てる対象
     // findは条件が合っているならselectorを返す
     rule.selectors
         .iter()
-        .find(|selector: &&Selector| matches(elem, *selector))
+        .find(|selector: &&Selector| {
+            selector.pseudo_element() == pseudo && matches(element, selector)
+        })
         .map(|selector: &Selector| (selector.specificity(), rule))
 }
 
 //
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+fn matches(element: &ElementRef, selector: &Selector) -> bool {
     match *selector {
-        crate::css::Selector::Simple(ref simple_selector) => {
-            matches_simple_selector(elem, simple_selector)
+        Selector::Simple(ref simple_selector) => matches_simple_selector(element, simple_selector),
+        Selector::Complex(ref complex) => {
+            matches_complex(element, complex, complex.compounds.len() - 1)
         }
     }
 }
 
+// compounds[index]がelementにmatchして、それより左もmatchするか
+// 右から左にたどる。div pならpを見てから祖先のdivを探す
+fn matches_complex(element: &ElementRef, selector: &ComplexSelector, index: usize) -> bool {
+    if !matches_simple_selector(element, &selector.compounds[index]) {
+        return false;
+    }
+    if index == 0 {
+        return true;
+    }
+    let next: usize = index - 1;
+    match selector.combinators[next] {
+        Combinator::Descendant => element
+            .ancestors()
+            .any(|ancestor: &ElementRef| matches_complex(ancestor, selector, next)),
+        Combinator::Child => element
+            .parent
+            .is_some_and(|parent: &ElementRef| matches_complex(parent, selector, next)),
+        Combinator::NextSibling => element
+            .previous_elements()
+            .next()
+            .is_some_and(|sibling: ElementRef| matches_complex(&sibling, selector, next)),
+        Combinator::SubsequentSibling => element
+            .previous_elements()
+            .any(|sibling: ElementRef| matches_complex(&sibling, selector, next)),
+    }
+}
+
 //　SimpleSelectorはtag_name
-fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> bool {
-    // タグ(h1..)の名前が合ってなかったらそもそもfalse
+fn matches_simple_selector(element: &ElementRef, selector: &SimpleSelector) -> bool {
+    let elem: &ElementData = element.elem;
+    // タグ(h1..)の名前が合ってなかったらそもそもfalse。DIVとdivは同じ
     // selectorってvecじゃないけどうーん、わからん。Ruleならselectorsがvecだけど
     if selector
         .tag_name
         .iter()
-        .any(|name: &String| elem.tag_name != *name)
+        .any(|name: &String| !elem.tag_name.eq_ignore_ascii_case(name))
     {
         return false;
     }
@@ -146,6 +912,755 @@ fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> boo
         return false;
     }
 
+    // Check attribute selectors
+    if !selector
+        .attributes
+        .iter()
+        .all(|attribute: &AttributeSelector| matches_attribute(elem, attribute))
+    {
+        return false;
+    }
+
+    // Check pseudo-classes
+    if !selector
+        .pseudo_classes
+        .iter()
+        .all(|pseudo: &PseudoClass| matches_pseudo_class(element, pseudo))
+    {
+        return false;
+    }
+
     // We didn't find any non-matching selector components.
-    return true;
+    true
+}
+
+// https://drafts.csswg.org/selectors/#structural-pseudos
+fn matches_pseudo_class(element: &ElementRef, pseudo: &PseudoClass) -> bool {
+    match *pseudo {
+        PseudoClass::FirstChild => element.position() == 1,
+        PseudoClass::LastChild => element.is_last(),
+        PseudoClass::NthChild(a, b) => matches_nth(a, b, element.position() as i32),
+        PseudoClass::Not(ref selectors) => !selectors
+            .iter()
+            .any(|selector: &Selector| matches(element, selector)),
+        PseudoClass::Is(ref selectors) | PseudoClass::Where(ref selectors) => selectors
+            .iter()
+            .any(|selector: &Selector| matches(element, selector)),
+        // https://drafts.csswg.org/selectors/#useraction-pseudos
+        PseudoClass::Hover => element.elem.state.contains(ElementState::HOVER),
+        PseudoClass::Focus => element.elem.state.contains(ElementState::FOCUS),
+        PseudoClass::Active => element.elem.state.contains(ElementState::ACTIVE),
+        PseudoClass::Checked => element.elem.state.contains(ElementState::CHECKED),
+        PseudoClass::Disabled => is_disabled(element),
+        PseudoClass::Enabled => {
+            dom::is_form_control(&element.elem.tag_name.to_ascii_lowercase())
+                && !is_disabled(element)
+        }
+        PseudoClass::Link => {
+            is_link(element.elem) && !element.elem.state.contains(ElementState::VISITED)
+        }
+        PseudoClass::Visited => {
+            is_link(element.elem) && element.elem.state.contains(ElementState::VISITED)
+        }
+        PseudoClass::AnyLink => is_link(element.elem),
+    }
+}
+
+// <a href>と<area href>と<link href>
+fn is_link(elem: &ElementData) -> bool {
+    matches!(
+        elem.tag_name.to_ascii_lowercase().as_str(),
+        "a" | "area" | "link"
+    ) && elem.attributes.contains_key("href")
+}
+
+// 自分がdisabledか、disabledな<fieldset>の中にある
+// ただしfieldsetの最初の<legend>の中にあるものはdisabledにならない
+// https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#concept-fe-disabled
+fn is_disabled(element: &ElementRef) -> bool {
+    if !dom::is_form_control(&element.elem.tag_name.to_ascii_lowercase()) {
+        return false;
+    }
+    if element.elem.state.contains(ElementState::DISABLED) {
+        return true;
+    }
+    // childはfieldsetの子で、自分かその祖先
+    let mut child: &ElementRef = element;
+    for ancestor in element.ancestors() {
+        if ancestor.elem.tag_name.eq_ignore_ascii_case("fieldset")
+            && ancestor.elem.state.contains(ElementState::DISABLED)
+            && !is_first_legend(child)
+        {
+            return true;
+        }
+        child = ancestor;
+    }
+    false
+}
+
+fn is_first_legend(element: &ElementRef) -> bool {
+    element.elem.tag_name.eq_ignore_ascii_case("legend")
+        && !element
+            .previous_elements()
+            .any(|sibling: ElementRef| sibling.elem.tag_name.eq_ignore_ascii_case("legend"))
+}
+
+// position = A*n + Bになるn(0以上)があるか
+fn matches_nth(a: i32, b: i32, position: i32) -> bool {
+    let diff: i32 = position - b;
+    if a == 0 {
+        return diff == 0;
+    }
+    diff % a == 0 && diff / a >= 0
+}
+
+// [type=checkbox]とかがelemのattributeとmatchするか
+// https://drafts.csswg.org/selectors/#attribute-selectors
+fn matches_attribute(elem: &ElementData, selector: &AttributeSelector) -> bool {
+    let value: &String = match elem.attributes.get(&selector.name) {
+        Some(value) => value,
+        None => return false,
+    };
+    let (operator, expected): (AttributeOperator, &String) = match selector.matcher {
+        Some((operator, ref expected)) => (operator, expected),
+        None => return true,
+    };
+    // 大文字小文字を区別しないなら両方小文字にして比べる
+    let insensitive: bool = match selector.flag {
+        Some(CaseFlag::Insensitive) => true,
+        Some(CaseFlag::Sensitive) => false,
+        None => CASE_INSENSITIVE_ATTRIBUTES.contains(&selector.name.as_str()),
+    };
+    let (value, expected): (String, String) = if insensitive {
+        (value.to_lowercase(), expected.to_lowercase())
+    } else {
+        (value.clone(), expected.clone())
+    };
+    match operator {
+        AttributeOperator::Equals => value == expected,
+        AttributeOperator::Includes => value
+            .split_ascii_whitespace()
+            .any(|word: &str| word == expected),
+        AttributeOperator::DashMatch => {
+            value == expected || value.starts_with(&format!("{}-", expected))
+        }
+        // ^= $= *=は空文字列だと何にもmatchしない
+        AttributeOperator::Prefix => !expected.is_empty() && value.starts_with(&expected),
+        AttributeOperator::Suffix => !expected.is_empty() && value.ends_with(&expected),
+        AttributeOperator::Substring => !expected.is_empty() && value.contains(&expected),
+    }
+}
+
+// HTMLではこのattributeの値はフラグがなくても大文字小文字を区別しない
+// https://html.spec.whatwg.org/multipage/semantics-other.html#case-sensitivity-of-selectors
+const CASE_INSENSITIVE_ATTRIBUTES: [&str; 46] = [
+    "accept",
+    "accept-charset",
+    "align",
+    "alink",
+    "axis",
+    "bgcolor",
+    "charset",
+    "checked",
+    "clear",
+    "codetype",
+    "color",
+    "compact",
+    "declare",
+    "defer",
+    "dir",
+    "direction",
+    "disabled",
+    "enctype",
+    "face",
+    "frame",
+    "hreflang",
+    "http-equiv",
+    "lang",
+    "language",
+    "link",
+    "media",
+    "method",
+    "multiple",
+    "nohref",
+    "noresize",
+    "noshade",
+    "nowrap",
+    "readonly",
+    "rel",
+    "rev",
+    "rules",
+    "scope",
+    "scrolling",
+    "selected",
+    "shape",
+    "target",
+    "text",
+    "type",
+    "valign",
+    "valuetype",
+    "vlink",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html;
+
+    fn parse_html(source: &str) -> Node {
+        html::Parser::parse(source.to_string()).unwrap()
+    }
+
+    // idで要素を探す
+    fn find<'a, 'b>(styled: &'b StyledNode<'a>, id: &str) -> Option<&'b StyledNode<'a>> {
+        if let NodeType::Element(ref elem) = styled.node.node_type {
+            if elem.id().is_some_and(|value: &String| value == id) {
+                return Some(styled);
+            }
+        }
+        styled
+            .children
+            .iter()
+            .find_map(|child: &StyledNode| find(child, id))
+    }
+
+    // selectorがrootの要素にmatchするか
+    fn selects(html: &str, selector: &str) -> bool {
+        let root: Node = parse_html(html);
+        let sheet: Stylesheet = css::parse(format!("{} {{ width: 1px }}", selector)).unwrap();
+        style_tree(&root, std::slice::from_ref(&sheet))
+            .specified_values
+            .contains_key("width")
+    }
+
+    // style="..."はどのruleよりも強い
+    #[test]
+    fn style_attribute_overrides_rules() {
+        let root: Node =
+            parse_html(r#"<p id="a" style="width: 10px; height: 2em; margin-left: 1px">x</p>"#);
+        let sheet: Stylesheet = css::parse("#a { width: 20px; height: 3px; }".to_string()).unwrap();
+        let styled: StyledNode = style_tree(&root, std::slice::from_ref(&sheet));
+        assert_eq!(
+            styled.value("width"),
+            Some(Value::Length(10.0, css::Unit::Px))
+        );
+        // 読めないdeclarationだけ捨てる
+        assert_eq!(
+            styled.value("height"),
+            Some(Value::Length(3.0, css::Unit::Px))
+        );
+        assert_eq!(
+            styled.value("margin-left"),
+            Some(Value::Length(1.0, css::Unit::Px))
+        );
+    }
+
+    #[test]
+    fn style_elements_in_document_order() {
+        let root: Node = parse_html(
+            r#"<html><head><style>p { width: 1px }</style><style type="text/less">x</style></head>
+               <body><style type="TEXT/CSS">b { width: 2px }</style></body></html>"#,
+        );
+        assert_eq!(
+            style_element_sources(&root),
+            vec![
+                "p { width: 1px }".to_string(),
+                "b { width: 2px }".to_string()
+            ]
+        );
+    }
+
+    // 同じspecificityなら後に書いたほうが勝つ
+    #[test]
+    fn later_rule_wins_on_equal_specificity() {
+        let root: Node = parse_html(r#"<p class="a">x</p>"#);
+        let sheet: Stylesheet =
+            css::parse("p.a { width: 1px } .a { width: 3px } p.a { width: 2px }".to_string())
+                .unwrap();
+        let styled: StyledNode = style_tree(&root, std::slice::from_ref(&sheet));
+        assert_eq!(
+            styled.value("width"),
+            Some(Value::Length(2.0, css::Unit::Px))
+        );
+    }
+
+    #[test]
+    fn combinators_match_siblings_and_ancestors() {
+        let root: Node = parse_html(
+            r#"<div><h1>a</h1><p id="p1">b</p><span>c</span><p id="p2">d</p><section><p id="p3">e</p></section></div>"#,
+        );
+        let sheet: Stylesheet = css::parse(
+            "h1 + p { width: 1px }
+             h1 ~ p { height: 1px }
+             div > p { margin-left: 1px }
+             div p { margin-right: 1px }"
+                .to_string(),
+        )
+        .unwrap();
+        let styled: StyledNode = style_tree(&root, std::slice::from_ref(&sheet));
+        let matched = |id: &str| -> Vec<&str> {
+            let node: &StyledNode = find(&styled, id).unwrap();
+            ["width", "height", "margin-left", "margin-right"]
+                .into_iter()
+                .filter(|name: &&str| node.specified_values.contains_key(*name))
+                .collect()
+        };
+        assert_eq!(
+            matched("p1"),
+            vec!["width", "height", "margin-left", "margin-right"]
+        );
+        assert_eq!(matched("p2"), vec!["height", "margin-left", "margin-right"]);
+        assert_eq!(matched("p3"), vec!["margin-right"]);
+    }
+
+    #[test]
+    fn attribute_operators() {
+        let html: &str = r#"<a class="x  btn-primary y" lang="en-US" href="https://a.example/b.pdf" data-empty="">z</a>"#;
+        assert!(selects(html, "[href]"));
+        assert!(!selects(html, "[title]"));
+        assert!(selects(html, "[data-empty]"));
+        assert!(selects(html, "[lang=en-US]"));
+        assert!(!selects(html, "[lang=en]"));
+        assert!(selects(html, "[class~=btn-primary]"));
+        assert!(!selects(html, "[class~=btn]"));
+        assert!(selects(html, "[lang|=en]"));
+        assert!(!selects(html, "[lang|=en-U]"));
+        assert!(selects(html, r#"[href^="https://"]"#));
+        assert!(selects(html, "[href$='.pdf']"));
+        assert!(selects(html, "[href*=example]"));
+        // 空の値は^= $= *=では何にもmatchしない
+        assert!(!selects(html, r#"[href^=""]"#));
+        assert!(!selects(html, r#"[href*=""]"#));
+        assert!(selects(html, r#"[data-empty=""]"#));
+    }
+
+    #[test]
+    fn attribute_case_flags() {
+        let html: &str = r#"<input type="CheckBox" name="Agree">"#;
+        // typeはHTMLの決まりで大文字小文字を区別しない
+        assert!(selects(html, "[type=checkbox]"));
+        assert!(!selects(html, "[type=checkbox s]"));
+        // nameは区別する
+        assert!(!selects(html, "[name=agree]"));
+        assert!(selects(html, "[name=agree i]"));
+        assert!(selects(html, "[name^=AG I]"));
+        // 属性名は区別しない
+        assert!(selects(html, "[NAME=Agree]"));
+    }
+
+    // widthの指定があるliのidを並べる
+    fn selected_items(selector: &str) -> Vec<String> {
+        let root: Node = parse_html(
+            r#"<ul><li id="1">a</li><li id="2" class="x">b</li><li id="3">c</li><li id="4" class="x">d</li><li id="5">e</li></ul>"#,
+        );
+        let sheet: Stylesheet = css::parse(format!("{} {{ width: 1px }}", selector)).unwrap();
+        let styled: StyledNode = style_tree(&root, std::slice::from_ref(&sheet));
+        (1..=5)
+            .map(|i: i32| i.to_string())
+            .filter(|id: &String| {
+                find(&styled, id)
+                    .unwrap()
+                    .specified_values
+                    .contains_key("width")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn structural_pseudo_classes() {
+        assert_eq!(selected_items("li:first-child"), vec!["1"]);
+        assert_eq!(selected_items("li:last-child"), vec!["5"]);
+        assert_eq!(selected_items("li:nth-child(odd)"), vec!["1", "3", "5"]);
+        assert_eq!(selected_items("li:nth-child(-n+3)"), vec!["1", "2", "3"]);
+        assert_eq!(selected_items("li:nth-child(2n - 1)"), vec!["1", "3", "5"]);
+        assert_eq!(selected_items("li:nth-child(0n+4)"), vec!["4"]);
+    }
+
+    #[test]
+    fn logical_pseudo_classes() {
+        assert_eq!(selected_items("li:not(.x)"), vec!["1", "3", "5"]);
+        assert_eq!(
+            selected_items("li:is(.x, :first-child)"),
+            vec!["1", "2", "4"]
+        );
+        assert_eq!(selected_items("ul > :where(.x)"), vec!["2", "4"]);
+    }
+
+    // :whereはspecificityが0なので、後ろに書いたただのliに負ける
+    #[test]
+    fn where_has_zero_specificity() {
+        let root: Node = parse_html(r#"<ul><li id="a" class="x">a</li></ul>"#);
+        let sheet: Stylesheet = css::parse(
+            "li:where(#a.x) { width: 1px } li { width: 2px } :is(#a) { height: 1px } li { height: 2px }"
+                .to_string(),
+        )
+        .unwrap();
+        let styled: StyledNode = style_tree(&root, std::slice::from_ref(&sheet));
+        let item: &StyledNode = find(&styled, "a").unwrap();
+        assert_eq!(item.value("width"), Some(Value::Length(2.0, css::Unit::Px)));
+        assert_eq!(
+            item.value("height"),
+            Some(Value::Length(1.0, css::Unit::Px))
+        );
+    }
+
+    // style treeのtextを順に|でつなぐ。::beforeとかの中身も入る
+    fn texts(html: &str, css: &str) -> String {
+        fn walk(styled: &StyledNode, out: &mut Vec<String>) {
+            if let NodeType::Text(ref text) = styled.node.node_type {
+                out.push(text.clone());
+            }
+            for child in &styled.children {
+                walk(child, out);
+            }
+        }
+        let root: Node = parse_html(html);
+        let sheet: Stylesheet = css::parse(css.to_string()).unwrap();
+        let mut out: Vec<String> = Vec::new();
+        walk(&style_tree(&root, std::slice::from_ref(&sheet)), &mut out);
+        out.join("|")
+    }
+
+    #[test]
+    fn before_and_after_with_attr() {
+        assert_eq!(
+            texts(
+                r#"<p><a href="/x" title="T">link</a></p>"#,
+                r#"a::before { content: "[" attr(href) "] " } a::after { content: '(' attr(TITLE) attr(rel) ')' }"#,
+            ),
+            "[/x] |link|(T)"
+        );
+        // noneやnormal、display: noneならboxを作らない
+        assert_eq!(
+            texts(
+                "<p>x</p>",
+                "p::before { content: none } p::after { content: 'a'; display: none }",
+            ),
+            "x"
+        );
+    }
+
+    #[test]
+    fn counter_and_counters() {
+        let html: &str = "<ol><li>a</li><li>b<ol><li>c</li><li>d</li></ol></li><li>e</li></ol>";
+        assert_eq!(
+            texts(
+                html,
+                "ol { counter-reset: item } li { counter-increment: item }
+                 li::before { content: counters(item, '.') ' ' }",
+            ),
+            "1 |a|2 |b|2.1 |c|2.2 |d|3 |e"
+        );
+        assert_eq!(
+            texts(
+                html,
+                "ol { counter-reset: item 3 } li { counter-increment: item 2 }
+                 li::before { content: counter(item, upper-roman) ':' counter(item, lower-alpha) }",
+            ),
+            "V:e|a|VII:g|b|V:e|c|VII:g|d|IX:i|e"
+        );
+    }
+
+    // selectorにmatchした要素のidを文書の順に並べる
+    fn matched_ids(root: &Node, selector: &str) -> Vec<String> {
+        fn walk(styled: &StyledNode, out: &mut Vec<String>) {
+            if let NodeType::Element(ref elem) = styled.node.node_type {
+                if styled.specified_values.contains_key("width") {
+                    out.extend(elem.id().cloned());
+                }
+            }
+            for child in &styled.children {
+                walk(child, out);
+            }
+        }
+        let sheet: Stylesheet = css::parse(format!("{} {{ width: 1px }}", selector)).unwrap();
+        let mut out: Vec<String> = Vec::new();
+        walk(&style_tree(root, std::slice::from_ref(&sheet)), &mut out);
+        out
+    }
+
+    #[test]
+    fn user_action_pseudo_classes() {
+        let mut root: Node =
+            parse_html(r#"<div id="a"><p id="b"><span id="c">x</span></p><p id="d">y</p></div>"#);
+        assert!(matched_ids(&root, ":hover").is_empty());
+        root.set_element_state(&[0, 0], ElementState::HOVER, true);
+        root.set_element_state(&[1], ElementState::FOCUS, true);
+        assert_eq!(matched_ids(&root, ":hover"), vec!["a", "b", "c"]);
+        assert_eq!(matched_ids(&root, "p:hover"), vec!["b"]);
+        assert_eq!(matched_ids(&root, ":focus"), vec!["d"]);
+        assert!(matched_ids(&root, ":active").is_empty());
+    }
+
+    #[test]
+    fn form_pseudo_classes() {
+        let root: Node = parse_html(
+            r#"<form><input id="a" type="checkbox" checked><input id="b" disabled>
+               <fieldset id="c" disabled><legend><input id="d"></legend><input id="e"></fieldset>
+               <select><option id="f" selected>x</option><option id="g">y</option></select></form>"#,
+        );
+        assert_eq!(matched_ids(&root, ":checked"), vec!["a", "f"]);
+        assert_eq!(matched_ids(&root, ":disabled"), vec!["b", "c", "e"]);
+        assert_eq!(matched_ids(&root, "input:enabled"), vec!["a", "d"]);
+    }
+
+    #[test]
+    fn link_pseudo_classes() {
+        let mut root: Node = parse_html(
+            r#"<p><a id="a" href="/x">x</a><a id="b">y</a><a id="c" href="/z">z</a></p>"#,
+        );
+        root.set_element_state(&[2], ElementState::VISITED, true);
+        assert_eq!(matched_ids(&root, ":link"), vec!["a"]);
+        assert_eq!(matched_ids(&root, ":visited"), vec!["c"]);
+        assert_eq!(matched_ids(&root, "a:any-link"), vec!["a", "c"]);
+    }
+
+    fn color(r: u8, g: u8, b: u8) -> Value {
+        Value::ColorValue(css::Color { r, g, b, a: 255 })
+    }
+
+    // idの要素のcomputed value
+    fn styled_value(html: &str, css: &str, id: &str, name: &str) -> Option<Value> {
+        let root: Node = parse_html(html);
+        let sheet: Stylesheet = css::parse(css.to_string()).unwrap();
+        let styled: StyledNode = style_tree(&root, std::slice::from_ref(&sheet));
+        find(&styled, id).unwrap().value(name)
+    }
+
+    #[test]
+    fn inherited_properties_come_from_parent() {
+        let html: &str = r#"<div id="d"><p id="p">x</p></div>"#;
+        let css: &str = "div { color: #ff0000; width: 10px; }";
+        assert_eq!(
+            styled_value(html, css, "p", "color"),
+            Some(color(255, 0, 0))
+        );
+        // widthは継承しないので初期値
+        assert_eq!(
+            styled_value(html, css, "p", "width"),
+            Some(Value::Keyword("auto".to_string()))
+        );
+    }
+
+    #[test]
+    fn initial_values_without_declarations() {
+        let html: &str = r#"<div id="d"><span id="s">x</span></div>"#;
+        assert_eq!(styled_value(html, "", "s", "color"), Some(color(0, 0, 0)));
+        assert_eq!(
+            styled_value(html, "", "s", "display"),
+            Some(Value::Keyword("inline".to_string()))
+        );
+    }
+
+    #[test]
+    fn inherit_initial_and_unset_keywords() {
+        let html: &str = r#"<div id="d"><p id="p">x</p></div>"#;
+        let css: &str = "div { width: 10px; color: #ff0000 } p { width: inherit; color: initial }";
+        assert_eq!(
+            styled_value(html, css, "p", "width"),
+            Some(Value::Length(10.0, Unit::Px))
+        );
+        assert_eq!(styled_value(html, css, "p", "color"), Some(color(0, 0, 0)));
+        let css: &str = "div { width: 10px; color: #ff0000 } p { width: unset; color: unset }";
+        assert_eq!(
+            styled_value(html, css, "p", "width"),
+            Some(Value::Keyword("auto".to_string()))
+        );
+        assert_eq!(
+            styled_value(html, css, "p", "color"),
+            Some(color(255, 0, 0))
+        );
+    }
+
+    #[test]
+    fn font_size_keywords_are_resolved() {
+        let html: &str = r#"<div id="d"><p id="p">x</p></div>"#;
+        let css: &str = "div { font-size: 20px } p { font-size: larger }";
+        assert_eq!(
+            styled_value(html, css, "p", "font-size"),
+            Some(Value::Length(24.0, Unit::Px))
+        );
+        assert_eq!(
+            styled_value(html, "", "p", "font-size"),
+            Some(Value::Length(font::DEFAULT_FONT_SIZE, Unit::Px))
+        );
+    }
+
+    // stylesheetを(CSS, origin)の順に並べてpのcolorを決める
+    fn cascaded_color(html: &str, sheets: &[(&str, Origin)]) -> Option<Value> {
+        let root: Node = parse_html(html);
+        let sheets: Vec<Stylesheet> = sheets
+            .iter()
+            .map(|&(source, origin): &(&str, Origin)| {
+                let mut sheet: Stylesheet = css::parse(source.to_string()).unwrap();
+                sheet.origin = origin;
+                sheet
+            })
+            .collect();
+        // htmlはpだけなのでrootがp
+        style_tree(&root, &sheets).value("color")
+    }
+
+    const RED: &str = "#ff0000";
+    const BLUE: &str = "#0000ff";
+
+    fn red() -> Option<Value> {
+        Some(color(255, 0, 0))
+    }
+
+    fn blue() -> Option<Value> {
+        Some(color(0, 0, 255))
+    }
+
+    #[test]
+    fn specificity_beats_source_order() {
+        let css: String = format!("#a {{ color: {} }} p {{ color: {} }}", RED, BLUE);
+        assert_eq!(
+            cascaded_color("<p id=\"a\">x</p>", &[(&css, Origin::Author)]),
+            red()
+        );
+    }
+
+    #[test]
+    fn later_declaration_wins() {
+        let css: String = format!("p {{ color: {}; color: {} }}", RED, BLUE);
+        assert_eq!(
+            cascaded_color("<p>x</p>", &[(&css, Origin::Author)]),
+            blue()
+        );
+        // 別のstylesheetなら後ろのもの
+        let first: String = format!("p {{ color: {} }}", BLUE);
+        let second: String = format!("p {{ color: {} }}", RED);
+        assert_eq!(
+            cascaded_color(
+                "<p>x</p>",
+                &[(&first, Origin::Author), (&second, Origin::Author)]
+            ),
+            red()
+        );
+    }
+
+    #[test]
+    fn inline_style_and_important() {
+        let html: String = format!("<p id=\"a\" style=\"color: {}\">x</p>", BLUE);
+        let css: String = format!("#a {{ color: {} }}", RED);
+        assert_eq!(cascaded_color(&html, &[(&css, Origin::Author)]), blue());
+        let css: String = format!("p {{ color: {} !important }}", RED);
+        assert_eq!(cascaded_color(&html, &[(&css, Origin::Author)]), red());
+    }
+
+    // 普通はUA < User < Authorで、!importantだと逆になる
+    #[test]
+    fn origin_order_reverses_for_important() {
+        let user: String = format!("p {{ color: {} }}", RED);
+        let author: String = format!("p {{ color: {} }}", BLUE);
+        let sheets: [(&str, Origin); 2] = [(&author, Origin::Author), (&user, Origin::User)];
+        assert_eq!(cascaded_color("<p>x</p>", &sheets), blue());
+
+        let user: String = format!("p {{ color: {} !important }}", RED);
+        let author: String = format!("#a {{ color: {} !important }}", BLUE);
+        let sheets: [(&str, Origin); 2] = [(&author, Origin::Author), (&user, Origin::User)];
+        assert_eq!(cascaded_color("<p id=\"a\">x</p>", &sheets), red());
+
+        let user_agent: String = format!("p {{ color: {} !important }}", BLUE);
+        let sheets: [(&str, Origin); 2] = [(&user, Origin::User), (&user_agent, Origin::UserAgent)];
+        assert_eq!(cascaded_color("<p>x</p>", &sheets), blue());
+    }
+
+    // revertはそのoriginのdeclarationを全部なかったことにする
+    #[test]
+    fn revert_rolls_back_origin() {
+        let user: String = format!("p {{ color: {} }}", RED);
+        let author: String = format!("#a {{ color: {} }} p {{ color: revert }}", BLUE);
+        let sheets: [(&str, Origin); 2] = [(&user, Origin::User), (&author, Origin::Author)];
+        assert_eq!(cascaded_color("<p>x</p>", &sheets), red());
+        // Authorのほかのdeclarationも使わない
+        assert_eq!(
+            cascaded_color("<p id=\"a\" style=\"color: revert\">x</p>", &sheets),
+            red()
+        );
+    }
+
+    // stylesheetがなくてもUAのstylesheetでblockやmarginが決まる
+    #[test]
+    fn user_agent_stylesheet_applies() {
+        let html: &str = r#"<div id="d"><p id="p">x</p><ul id="u"><li><ul id="n"><li>y</li></ul></li></ul><h1 id="h">z</h1></div>"#;
+        assert_eq!(
+            styled_value(html, "", "p", "display"),
+            Some(Value::Keyword("block".to_string()))
+        );
+        assert_eq!(
+            styled_value(html, "", "p", "margin-top"),
+            Some(Value::Length(16.0, Unit::Px))
+        );
+        // 入れ子のリストは上下をあけない
+        assert_eq!(
+            styled_value(html, "", "n", "margin-top"),
+            Some(Value::Length(0.0, Unit::Px))
+        );
+        assert_eq!(
+            styled_value(html, "", "h", "font-size"),
+            Some(Value::Length(32.0, Unit::Px))
+        );
+        // authorのstylesheetが勝つ
+        assert_eq!(
+            styled_value(html, "p { display: inline }", "p", "display"),
+            Some(Value::Keyword("inline".to_string()))
+        );
+    }
+
+    #[test]
+    fn user_agent_hides_head_and_scripts() {
+        let html: &str = r#"<html><head id="h"><title>t</title></head><body id="b"><script id="s">x</script><p id="p" hidden>y</p></body></html>"#;
+        for id in ["h", "s", "p"] {
+            assert_eq!(
+                styled_value(html, "", id, "display"),
+                Some(Value::Keyword("none".to_string()))
+            );
+        }
+        assert_eq!(
+            styled_value(html, "", "b", "margin-left"),
+            Some(Value::Length(8.0, Unit::Px))
+        );
+    }
+
+    // タグ名は大文字で書いてもUAのstylesheetにもauthorのselectorにもmatchする
+    #[test]
+    fn tag_names_match_case_insensitively() {
+        let html: &str = r#"<DIV id="d"><P id="p">x</P></DIV>"#;
+        assert_eq!(
+            styled_value(html, "", "d", "display"),
+            Some(Value::Keyword("block".to_string()))
+        );
+        assert_eq!(
+            styled_value(html, "", "p", "margin-top"),
+            Some(Value::Length(16.0, Unit::Px))
+        );
+        assert_eq!(
+            styled_value(html, "P { margin-top: 1px }", "p", "margin-top"),
+            Some(Value::Length(1.0, Unit::Px))
+        );
+    }
+
+    #[test]
+    fn inherit_on_root_is_initial_value() {
+        let html: &str = r#"<div id="d">x</div>"#;
+        let css: &str = "div { color: inherit; font-size: inherit }";
+        assert_eq!(styled_value(html, css, "d", "color"), Some(color(0, 0, 0)));
+        assert_eq!(
+            styled_value(html, css, "d", "font-size"),
+            Some(Value::Length(font::DEFAULT_FONT_SIZE, Unit::Px))
+        );
+    }
+
+    #[test]
+    fn style_elements_apply_without_cli() {
+        let root: Node =
+            parse_html(r#"<div><style>p { color: #ff0000 }</style><p id="p">x</p></div>"#);
+        let styled: StyledNode = style_tree(&root, &[]);
+        assert_eq!(
+            find(&styled, "p").unwrap().value("color"),
+            Some(color(255, 0, 0))
+        );
+    }
 }
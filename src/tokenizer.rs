@@ -0,0 +1,650 @@
+use crate::entities;
+use std::collections::{HashSet, VecDeque};
+
+// tokenizer.rsはHTMLの文字列をtokenに分けるファイル
+// https://html.spec.whatwg.org/multipage/parsing.html#tokenization
+// tree_builder.rsがtokenを1つずつ受け取ってtreeを作る
+// 壊れたHTMLでもエラーにはしないで、ブラウザと同じように読めるところまで読む
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Doctype {
+        name: Option<String>,
+        public_id: Option<String>,
+        system_id: Option<String>,
+        // trueならdoctypeの中身に関係なくquirks modeにする
+        force_quirks: bool,
+    },
+    StartTag(Tag),
+    EndTag(Tag),
+    Comment(String),
+    Character(char),
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Tag {
+    // 小文字にしてある
+    pub name: String,
+    // 同じ名前のattributeは最初のものだけ残してある
+    pub attributes: Vec<(String, String)>,
+    // <br/>みたいに/>で閉じてたらtrue
+    pub self_closing: bool,
+}
+
+// tokenizerの状態。tree builderが<title>とか<script>を見たら切り替える
+// コメントとdoctypeは途中で分岐がほとんどないので状態にしないで一気に読む
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Data,
+    // <title>と<textarea>の中。文字参照はデコードするけどタグは読まない
+    RcData,
+    // <style>とか<xmp>の中。文字参照もデコードしない
+    RawText,
+    ScriptData,
+    // <plaintext>の後ろは全部ただの文字
+    PlainText,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+}
+
+pub struct Tokenizer {
+    pub state: State,
+    // trueならsvgとかmathの中なので<![CDATA[...]]>を文字として読む
+    pub allow_cdata: bool,
+    input: String,
+    pos: usize,
+    // 作っている途中のタグ
+    tag: Tag,
+    is_end_tag: bool,
+    // RcDataとかで</title>みたいな閉じタグかどうか見るのに使う
+    last_start_tag: String,
+    // 一度に何個もtokenができることがあるのでためておく
+    pending: VecDeque<Token>,
+}
+
+impl Tokenizer {
+    pub fn new(input: String) -> Tokenizer {
+        // 改行はCRLFとCRをLFにそろえておく
+        let input: String = input.replace("\r\n", "\n").replace('\r', "\n");
+        Tokenizer {
+            state: State::Data,
+            allow_cdata: false,
+            input,
+            pos: 0,
+            tag: Tag::default(),
+            is_end_tag: false,
+            last_start_tag: String::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// 次のtokenを返す。最後まで読んだらずっとToken::Eofを返す
+    pub fn next_token(&mut self) -> Token {
+        while self.pending.is_empty() {
+            self.step();
+        }
+        self.pending.pop_front().unwrap()
+    }
+
+    // 1文字読んで今の状態に合わせて処理する
+    fn step(&mut self) {
+        let c: Option<char> = self.consume_char();
+        match self.state {
+            State::Data => match c {
+                Some('&') => self.character_reference(),
+                Some('<') => self.state = State::TagOpen,
+                Some(c) => self.emit(Token::Character(c)),
+                None => self.emit(Token::Eof),
+            },
+            State::RcData => match c {
+                Some('&') => self.character_reference(),
+                Some('<') => self.raw_text_less_than(),
+                Some(c) => self.emit(Token::Character(replace_null(c))),
+                None => self.emit(Token::Eof),
+            },
+            State::RawText | State::ScriptData => match c {
+                Some('<') => self.raw_text_less_than(),
+                Some(c) => self.emit(Token::Character(replace_null(c))),
+                None => self.emit(Token::Eof),
+            },
+            State::PlainText => match c {
+                Some(c) => self.emit(Token::Character(replace_null(c))),
+                None => self.emit(Token::Eof),
+            },
+            State::TagOpen => match c {
+                Some('!') => self.markup_declaration_open(),
+                Some('/') => self.state = State::EndTagOpen,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(false);
+                    self.reconsume_in(Some(c), State::TagName);
+                }
+                // <?xml ...>とかはコメントとして読む
+                Some('?') => {
+                    self.reconsume_in(Some('?'), State::Data);
+                    self.bogus_comment("");
+                }
+                // タグじゃなかったので<はただの文字
+                _ => {
+                    self.emit(Token::Character('<'));
+                    self.reconsume_in(c, State::Data);
+                }
+            },
+            State::EndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume_in(Some(c), State::TagName);
+                }
+                // </>は何もなかったことにする
+                Some('>') => self.state = State::Data,
+                None => {
+                    self.emit(Token::Character('<'));
+                    self.emit(Token::Character('/'));
+                    self.state = State::Data;
+                }
+                Some(c) => {
+                    self.reconsume_in(Some(c), State::Data);
+                    self.bogus_comment("");
+                }
+            },
+            State::TagName => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => self.emit_tag(),
+                Some(c) => self.tag.name.push(lowercase(c)),
+                // 閉じてないタグは捨てる
+                None => self.emit(Token::Eof),
+            },
+            State::BeforeAttributeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('/' | '>') | None => self.reconsume_in(c, State::AfterAttributeName),
+                // =から始まる名前はそのまま名前にする
+                Some('=') => {
+                    self.new_attribute();
+                    self.attribute_name().push('=');
+                    self.state = State::AttributeName;
+                }
+                Some(c) => {
+                    self.new_attribute();
+                    self.reconsume_in(Some(c), State::AttributeName);
+                }
+            },
+            State::AttributeName => match c {
+                Some(c) if is_whitespace(c) || c == '/' || c == '>' => {
+                    self.reconsume_in(Some(c), State::AfterAttributeName)
+                }
+                None => self.reconsume_in(None, State::AfterAttributeName),
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some(c) => self.attribute_name().push(lowercase(c)),
+            },
+            State::AfterAttributeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('>') => self.emit_tag(),
+                Some(c) => {
+                    self.new_attribute();
+                    self.reconsume_in(Some(c), State::AttributeName);
+                }
+                None => self.emit(Token::Eof),
+            },
+            State::BeforeAttributeValue => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('"') => self.state = State::AttributeValueDoubleQuoted,
+                Some('\'') => self.state = State::AttributeValueSingleQuoted,
+                // <a href=>みたいに値がないなら空文字列
+                Some('>') => self.emit_tag(),
+                _ => self.reconsume_in(c, State::AttributeValueUnquoted),
+            },
+            State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted => {
+                let quote: char = if self.state == State::AttributeValueDoubleQuoted {
+                    '"'
+                } else {
+                    '\''
+                };
+                match c {
+                    Some(c) if c == quote => self.state = State::AfterAttributeValueQuoted,
+                    Some('&') => self.attribute_character_reference(),
+                    Some(c) => self.attribute_value().push(replace_null(c)),
+                    None => self.emit(Token::Eof),
+                }
+            }
+            State::AttributeValueUnquoted => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('&') => self.attribute_character_reference(),
+                Some('>') => self.emit_tag(),
+                Some(c) => self.attribute_value().push(replace_null(c)),
+                None => self.emit(Token::Eof),
+            },
+            State::AfterAttributeValueQuoted => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => self.emit_tag(),
+                // <a href="x"title="y">みたいに空白がなくても次のattributeにする
+                Some(c) => self.reconsume_in(Some(c), State::BeforeAttributeName),
+                None => self.emit(Token::Eof),
+            },
+            State::SelfClosingStartTag => match c {
+                Some('>') => {
+                    self.tag.self_closing = true;
+                    self.emit_tag();
+                }
+                Some(c) => self.reconsume_in(Some(c), State::BeforeAttributeName),
+                None => self.emit(Token::Eof),
+            },
+        }
+    }
+
+    // <!の後ろ。コメント、doctype、CDATAのどれか
+    fn markup_declaration_open(&mut self) {
+        self.state = State::Data;
+        if self.starts_with("--") {
+            self.pos += "--".len();
+            self.comment();
+        } else if self.starts_with_ignore_case("doctype") {
+            self.pos += "doctype".len();
+            self.doctype();
+        } else if self.starts_with("[CDATA[") {
+            self.pos += "[CDATA[".len();
+            if self.allow_cdata {
+                self.cdata();
+            } else {
+                // HTMLの中のCDATAはコメントになる
+                self.bogus_comment("[CDATA[");
+            }
+        } else {
+            self.bogus_comment("");
+        }
+    }
+
+    // <!--の後ろから-->までを読む
+    fn comment(&mut self) {
+        // <!-->と<!--->は空のコメント
+        for end in [">", "->"] {
+            if self.starts_with(end) {
+                self.pos += end.len();
+                self.emit(Token::Comment(String::new()));
+                return;
+            }
+        }
+        let rest: &str = &self.input[self.pos..];
+        // --!>でも閉じる
+        let end: Option<(usize, usize)> = [
+            rest.find("-->").map(|i: usize| (i, 3)),
+            rest.find("--!>").map(|i: usize| (i, 4)),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let data: String = match end {
+            Some((len, end_len)) => {
+                let data: String = rest[..len].to_string();
+                self.pos += len + end_len;
+                data
+            }
+            // 閉じてないなら最後までコメント
+            None => {
+                let data: String = rest.to_string();
+                self.pos = self.input.len();
+                data
+            }
+        };
+        self.emit(Token::Comment(data.replace('\0', "\u{FFFD}")));
+    }
+
+    // <!や</の後ろがおかしいときは>までをコメントにする
+    fn bogus_comment(&mut self, prefix: &str) {
+        let mut data: String = prefix.to_string();
+        while let Some(c) = self.consume_char() {
+            if c == '>' {
+                break;
+            }
+            data.push(replace_null(c));
+        }
+        self.emit(Token::Comment(data));
+    }
+
+    // <![CDATA[の後ろから]]>までをただの文字として読む
+    fn cdata(&mut self) {
+        let rest: &str = &self.input[self.pos..];
+        let (len, end_len): (usize, usize) = match rest.find("]]>") {
+            Some(len) => (len, 3),
+            None => (rest.len(), 0),
+        };
+        let data: String = rest[..len].to_string();
+        self.pos += len + end_len;
+        for c in data.chars() {
+            self.emit(Token::Character(c));
+        }
+    }
+
+    // <!DOCTYPEの後ろを読む
+    // https://html.spec.whatwg.org/multipage/parsing.html#doctype-state
+    fn doctype(&mut self) {
+        self.consume_whitespace();
+        // <!DOCTYPE>みたいに名前がない
+        if matches!(self.next_char(), Some('>') | None) {
+            self.consume_char();
+            self.emit(Token::Doctype {
+                name: None,
+                public_id: None,
+                system_id: None,
+                force_quirks: true,
+            });
+            return;
+        }
+        let mut name: String = String::new();
+        while let Some(c) = self.next_char() {
+            if is_whitespace(c) || c == '>' {
+                break;
+            }
+            self.consume_char();
+            name.push(lowercase(c));
+        }
+        self.consume_whitespace();
+
+        let mut public_id: Option<String> = None;
+        let mut system_id: Option<String> = None;
+        let mut force_quirks: bool = false;
+        if self.starts_with_ignore_case("public") {
+            self.pos += "public".len();
+            self.consume_whitespace();
+            public_id = self.doctype_identifier();
+            force_quirks = public_id.is_none();
+            self.consume_whitespace();
+            if public_id.is_some() && matches!(self.next_char(), Some('"' | '\'')) {
+                system_id = self.doctype_identifier();
+                force_quirks = system_id.is_none();
+            }
+        } else if self.starts_with_ignore_case("system") {
+            self.pos += "system".len();
+            self.consume_whitespace();
+            system_id = self.doctype_identifier();
+            force_quirks = system_id.is_none();
+        } else if self.next_char() != Some('>') {
+            // 知らないものが書いてある
+            force_quirks = true;
+        }
+
+        // 残りは>まで読み飛ばす
+        loop {
+            match self.consume_char() {
+                Some('>') => break,
+                Some(_) => {}
+                None => {
+                    force_quirks = true;
+                    break;
+                }
+            }
+        }
+        self.emit(Token::Doctype {
+            name: Some(name),
+            public_id,
+            system_id,
+            force_quirks,
+        });
+    }
+
+    // doctypeの"..."か'...'を読む。クォートがないとか途中で>が来たらNone
+    fn doctype_identifier(&mut self) -> Option<String> {
+        let quote: char = match self.next_char() {
+            Some(c) if c == '"' || c == '\'' => c,
+            _ => return None,
+        };
+        self.consume_char();
+        let mut identifier: String = String::new();
+        loop {
+            match self.next_char() {
+                Some(c) if c == quote => {
+                    self.consume_char();
+                    return Some(identifier);
+                }
+                // >は後ろで読み飛ばすので残しておく
+                Some('>') | None => return None,
+                Some(c) => {
+                    self.consume_char();
+                    identifier.push(replace_null(c));
+                }
+            }
+        }
+    }
+
+    // RcDataとかの中の<。直前の開始タグと同じ名前の閉じタグのときだけタグとして読む
+    fn raw_text_less_than(&mut self) {
+        if self.appropriate_end_tag_ahead() {
+            // </の/も読んでおく
+            self.consume_char();
+            self.state = State::EndTagOpen;
+        } else {
+            self.emit(Token::Character('<'));
+        }
+    }
+
+    fn appropriate_end_tag_ahead(&self) -> bool {
+        let name: &str = &self.last_start_tag;
+        let rest: &str = &self.input[self.pos..];
+        if name.is_empty() || !rest.starts_with('/') || rest.len() < name.len() + 1 {
+            return false;
+        }
+        match rest.get(1..name.len() + 1) {
+            Some(candidate) if candidate.eq_ignore_ascii_case(name) => {}
+            _ => return false,
+        }
+        matches!(rest[name.len() + 1..].chars().next(), Some(c) if is_whitespace(c) || c == '/' || c == '>')
+    }
+
+    // 本文の中の&
+    fn character_reference(&mut self) {
+        let mut decoded: String = String::new();
+        let consumed: usize =
+            entities::decode_reference(&self.input[self.pos..], false, &mut decoded);
+        if consumed == 0 {
+            self.emit(Token::Character('&'));
+            return;
+        }
+        self.pos += consumed;
+        for c in decoded.chars() {
+            self.emit(Token::Character(c));
+        }
+    }
+
+    // attributeの値の中の&
+    fn attribute_character_reference(&mut self) {
+        let value: &mut String = &mut self.tag.attributes.last_mut().unwrap().1;
+        let consumed: usize = entities::decode_reference(&self.input[self.pos..], true, value);
+        if consumed == 0 {
+            value.push('&');
+        }
+        self.pos += consumed;
+    }
+
+    fn new_tag(&mut self, is_end_tag: bool) {
+        self.tag = Tag::default();
+        self.is_end_tag = is_end_tag;
+    }
+
+    fn new_attribute(&mut self) {
+        self.tag.attributes.push((String::new(), String::new()));
+    }
+
+    fn attribute_name(&mut self) -> &mut String {
+        &mut self.tag.attributes.last_mut().unwrap().0
+    }
+
+    fn attribute_value(&mut self) -> &mut String {
+        &mut self.tag.attributes.last_mut().unwrap().1
+    }
+
+    fn emit_tag(&mut self) {
+        let mut tag: Tag = std::mem::take(&mut self.tag);
+        // 同じ名前のattributeは最初のものを使う
+        let mut seen: HashSet<String> = HashSet::new();
+        tag.attributes
+            .retain(|(name, _): &(String, String)| seen.insert(name.clone()));
+        self.state = State::Data;
+        if self.is_end_tag {
+            self.emit(Token::EndTag(tag));
+        } else {
+            self.last_start_tag = tag.name.clone();
+            self.emit(Token::StartTag(tag));
+        }
+    }
+
+    fn emit(&mut self, token: Token) {
+        self.pending.push_back(token);
+    }
+
+    // 読んだ文字を戻して、次は別の状態として読む
+    fn reconsume_in(&mut self, c: Option<char>, state: State) {
+        if let Some(c) = c {
+            self.pos -= c.len_utf8();
+        }
+        self.state = state;
+    }
+
+    fn consume_char(&mut self) -> Option<char> {
+        let c: char = self.next_char()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn next_char(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn consume_whitespace(&mut self) {
+        while matches!(self.next_char(), Some(c) if is_whitespace(c)) {
+            self.consume_char();
+        }
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.input[self.pos..].starts_with(s)
+    }
+
+    fn starts_with_ignore_case(&self, s: &str) -> bool {
+        self.input[self.pos..]
+            .get(..s.len())
+            .is_some_and(|head: &str| head.eq_ignore_ascii_case(s))
+    }
+}
+
+/// HTMLの空白(tab, LF, FF, space)
+pub fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | ' ')
+}
+
+// タグとattributeの名前は小文字にする
+fn lowercase(c: char) -> char {
+    replace_null(c.to_ascii_lowercase())
+}
+
+// NULはU+FFFDにする
+fn replace_null(c: char) -> char {
+    if c == '\0' {
+        '\u{FFFD}'
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Eofまで読む。続いたCharacterは1つの文字列にまとめる
+    fn tokens(tokenizer: &mut Tokenizer) -> Vec<Token> {
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut text: String = String::new();
+        loop {
+            let token: Token = tokenizer.next_token();
+            if let Token::Character(c) = token {
+                text.push(c);
+                continue;
+            }
+            if !text.is_empty() {
+                tokens.push(text_token(&std::mem::take(&mut text)));
+            }
+            if token == Token::Eof {
+                return tokens;
+            }
+            tokens.push(token);
+        }
+    }
+
+    // テストで比べやすいように、まとめた文字列はコメントに入れておく
+    fn text_token(value: &str) -> Token {
+        Token::Comment(format!("text:{}", value))
+    }
+
+    fn start_tag(name: &str, attributes: &[(&str, &str)], self_closing: bool) -> Token {
+        Token::StartTag(Tag {
+            name: name.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|&(name, value): &(&str, &str)| (name.to_string(), value.to_string()))
+                .collect(),
+            self_closing,
+        })
+    }
+
+    #[test]
+    fn attributes_are_lowercased_and_decoded() {
+        let mut tokenizer: Tokenizer =
+            Tokenizer::new("<A HREF='x&amp;y' b=c d href=z><br/>".to_string());
+        assert_eq!(
+            tokens(&mut tokenizer),
+            vec![
+                start_tag("a", &[("href", "x&y"), ("b", "c"), ("d", "")], false),
+                start_tag("br", &[], true),
+            ]
+        );
+    }
+
+    #[test]
+    fn rcdata_decodes_references_but_not_tags() {
+        let mut tokenizer: Tokenizer = Tokenizer::new("<title>a<b>&amp;</title>".to_string());
+        assert_eq!(tokenizer.next_token(), start_tag("title", &[], false));
+        // tree builderの代わりに状態を切り替える
+        tokenizer.state = State::RcData;
+        assert_eq!(
+            tokens(&mut tokenizer),
+            vec![
+                text_token("a<b>&"),
+                Token::EndTag(Tag {
+                    name: "title".to_string(),
+                    ..Tag::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_and_doctype() {
+        let mut tokenizer: Tokenizer =
+            Tokenizer::new("<!DOCTYPE html><!-- a -- b -->\r\nx".to_string());
+        assert_eq!(
+            tokens(&mut tokenizer),
+            vec![
+                Token::Doctype {
+                    name: Some("html".to_string()),
+                    public_id: None,
+                    system_id: None,
+                    force_quirks: false,
+                },
+                Token::Comment(" a -- b ".to_string()),
+                text_token("\nx"),
+            ]
+        );
+    }
+}
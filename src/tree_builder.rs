@@ -0,0 +1,2481 @@
+use crate::{
+    dom, html,
+    tokenizer::{self, State, Tag, Token, Tokenizer},
+};
+use std::{collections::HashMap, mem};
+
+// tree_builder.rsはtokenizerから受け取ったtokenでDOM treeを作るファイル
+// https://html.spec.whatwg.org/multipage/parsing.html#tree-construction
+// html.rsのParserと違って閉じタグが抜けてたり入れ子がおかしかったりしてもエラーにしないで、ブラウザと同じtreeを作る
+// scriptは実行しないので<noscript>の中も普通にparseする
+// <template>の中身はdocument fragmentにしないで普通の子要素にする
+
+/// ブラウザと同じ規則でHTMLをparseする。どんな入力でもエラーにはならない
+pub fn parse_document(source: String) -> dom::Document {
+    let mut builder: TreeBuilder = TreeBuilder::new(source);
+    loop {
+        // svgとかmathの中だけ<![CDATA[...]]>が使える
+        builder.tokenizer.allow_cdata = builder
+            .open_elements
+            .last()
+            .is_some_and(|&node: &usize| builder.is_foreign(node));
+        let token: Token = builder.tokenizer.next_token();
+        let eof: bool = token == Token::Eof;
+        builder.process(token);
+        if eof {
+            break;
+        }
+    }
+    builder.finish()
+}
+
+// 作っている途中のtree
+// adoption agency algorithmで要素をつなぎ替えるので、dom::Nodeを直接持たないでnodesの番号で参照する
+struct TreeNode {
+    parent: Option<usize>,
+    children: Vec<usize>,
+    data: NodeData,
+}
+
+enum NodeData {
+    Document,
+    Element {
+        name: String,
+        attributes: dom::AttrMap,
+        // svgとmathの中の要素ならtrue
+        foreign: bool,
+    },
+    Text(String),
+    Comment(String),
+}
+
+// nodes[0]はdocument
+const DOCUMENT: usize = 0;
+
+// https://html.spec.whatwg.org/multipage/parsing.html#the-insertion-mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    InHeadNoscript,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset,
+}
+
+// list of active formatting elementsの要素
+// <b>とかが閉じられないまま別の要素が始まったときに作り直すために覚えておく
+#[derive(Clone, Copy, PartialEq)]
+enum Formatting {
+    // <td>とかに入ったときの区切り。区切りより前の要素は作り直さない
+    Marker,
+    Element(usize),
+}
+
+// 要素を差し込む場所
+#[derive(Clone, Copy)]
+enum InsertionPoint {
+    Append(usize),
+    // (親, この要素の前)
+    Before(usize, usize),
+}
+
+// has an element in scopeのscopeの種類
+#[derive(Clone, Copy, PartialEq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select,
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#special
+const SPECIAL: [&str; 83] = [
+    "address",
+    "applet",
+    "area",
+    "article",
+    "aside",
+    "base",
+    "basefont",
+    "bgsound",
+    "blockquote",
+    "body",
+    "br",
+    "button",
+    "caption",
+    "center",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "embed",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "frame",
+    "frameset",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "iframe",
+    "img",
+    "input",
+    "keygen",
+    "li",
+    "link",
+    "listing",
+    "main",
+    "marquee",
+    "menu",
+    "meta",
+    "nav",
+    "noembed",
+    "noframes",
+    "noscript",
+    "object",
+    "ol",
+    "p",
+    "param",
+    "plaintext",
+    "pre",
+    "script",
+    "search",
+    "section",
+    "select",
+    "source",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "template",
+    "textarea",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "track",
+    "ul",
+    "wbr",
+    "xmp",
+];
+
+// svgとmathの中で特別扱いする要素。名前は小文字にしてある
+const FOREIGN_SPECIAL: [&str; 9] = [
+    "mi",
+    "mo",
+    "mn",
+    "ms",
+    "mtext",
+    "annotation-xml",
+    "foreignobject",
+    "desc",
+    "title",
+];
+
+// 中に普通のHTMLを書けるsvgとmathの要素
+const INTEGRATION_POINTS: [&str; 8] = [
+    "mi",
+    "mo",
+    "mn",
+    "ms",
+    "mtext",
+    "foreignobject",
+    "desc",
+    "title",
+];
+
+// 閉じタグがなくても閉じていい要素
+const IMPLIED_END_TAGS: [&str; 10] = [
+    "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+];
+
+const HEADINGS: [&str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+// svgとmathの中でこれが出てきたらHTMLに戻る
+const BREAKOUT: [&str; 44] = [
+    "b",
+    "big",
+    "blockquote",
+    "body",
+    "br",
+    "center",
+    "code",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "embed",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "hr",
+    "i",
+    "img",
+    "li",
+    "listing",
+    "menu",
+    "meta",
+    "nobr",
+    "ol",
+    "p",
+    "pre",
+    "ruby",
+    "s",
+    "small",
+    "span",
+    "strong",
+    "strike",
+    "sub",
+    "sup",
+    "table",
+    "tt",
+    "u",
+    "ul",
+    "var",
+];
+
+struct TreeBuilder {
+    tokenizer: Tokenizer,
+    nodes: Vec<TreeNode>,
+    mode: InsertionMode,
+    // Text modeとInTableText modeが終わったら戻るmode
+    original_mode: InsertionMode,
+    // 開いている要素。最後が今の要素(current node)
+    open_elements: Vec<usize>,
+    active_formatting: Vec<Formatting>,
+    head: Option<usize>,
+    form: Option<usize>,
+    // falseになったら<frameset>を無視する
+    frameset_ok: bool,
+    // trueならtableの中に置けないものをtableの前に出す
+    foster_parenting: bool,
+    // <pre>とか<textarea>の直後の改行は捨てる
+    ignore_newline: bool,
+    pending_table_text: String,
+    doctype: Option<dom::Doctype>,
+    quirks_mode: dom::QuirksMode,
+}
+
+impl TreeBuilder {
+    fn new(source: String) -> TreeBuilder {
+        TreeBuilder {
+            tokenizer: Tokenizer::new(source),
+            nodes: vec![TreeNode {
+                parent: None,
+                children: Vec::new(),
+                data: NodeData::Document,
+            }],
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            open_elements: Vec::new(),
+            active_formatting: Vec::new(),
+            head: None,
+            form: None,
+            frameset_ok: true,
+            foster_parenting: false,
+            ignore_newline: false,
+            pending_table_text: String::new(),
+            doctype: None,
+            // doctypeがなければquirks mode
+            quirks_mode: dom::QuirksMode::Quirks,
+        }
+    }
+
+    // nodesをdom::Nodeのtreeにする。document直下のコメントは捨てる
+    fn finish(self) -> dom::Document {
+        let html: usize = self.nodes[DOCUMENT]
+            .children
+            .iter()
+            .copied()
+            .find(|&node: &usize| matches!(self.nodes[node].data, NodeData::Element { .. }))
+            .unwrap();
+        dom::Document {
+            root: self.to_dom(html),
+            doctype: self.doctype,
+            mode: self.quirks_mode,
+        }
+    }
+
+    fn to_dom(&self, node: usize) -> dom::Node {
+        let children: Vec<dom::Node> = self.nodes[node]
+            .children
+            .iter()
+            .map(|&child: &usize| self.to_dom(child))
+            .collect();
+        match self.nodes[node].data {
+            NodeData::Element {
+                ref name,
+                ref attributes,
+                ..
+            } => dom::elem(name.clone(), attributes.clone(), children),
+            NodeData::Text(ref text) => dom::text(text.clone()),
+            NodeData::Comment(ref data) => dom::comment(data.clone()),
+            NodeData::Document => unreachable!(),
+        }
+    }
+
+    // tokenizerから来たtokenを処理する
+    fn process(&mut self, token: Token) {
+        if mem::take(&mut self.ignore_newline) && token == Token::Character('\n') {
+            return;
+        }
+        self.dispatch(token);
+    }
+
+    // svgとかの中ならforeign contentの規則、それ以外なら今のmodeの規則で処理する
+    // https://html.spec.whatwg.org/multipage/parsing.html#tree-construction-dispatcher
+    fn dispatch(&mut self, token: Token) {
+        if self.use_foreign_rules(&token) {
+            self.in_foreign_content(token);
+        } else {
+            self.process_in(self.mode, token);
+        }
+    }
+
+    fn process_in(&mut self, mode: InsertionMode, token: Token) {
+        match mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHtml => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::InHeadNoscript => self.in_head_noscript(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::InBody => self.in_body(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::InTable => self.in_table(token),
+            InsertionMode::InTableText => self.in_table_text(token),
+            InsertionMode::InCaption => self.in_caption(token),
+            InsertionMode::InColumnGroup => self.in_column_group(token),
+            InsertionMode::InTableBody => self.in_table_body(token),
+            InsertionMode::InRow => self.in_row(token),
+            InsertionMode::InCell => self.in_cell(token),
+            InsertionMode::InSelect => self.in_select(token),
+            InsertionMode::InSelectInTable => self.in_select_in_table(token),
+            InsertionMode::AfterBody => self.after_body(token),
+            InsertionMode::InFrameset => self.in_frameset(token),
+            InsertionMode::AfterFrameset => self.after_frameset(token),
+            InsertionMode::AfterAfterBody => self.after_after_body(token),
+            InsertionMode::AfterAfterFrameset => self.after_after_frameset(token),
+        }
+    }
+
+    // ---- insertion modes ----
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+    fn initial(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if tokenizer::is_whitespace(c) => {}
+            Token::Comment(data) => self.append_comment(DOCUMENT, data),
+            Token::Doctype {
+                name,
+                public_id,
+                system_id,
+                force_quirks,
+            } => {
+                let doctype: dom::Doctype = dom::Doctype {
+                    name: name.unwrap_or_default(),
+                    public_id,
+                    system_id,
+                };
+                self.quirks_mode = if force_quirks {
+                    dom::QuirksMode::Quirks
+                } else {
+                    html::quirks_mode(Some(&doctype))
+                };
+                self.doctype = Some(doctype);
+                self.mode = InsertionMode::BeforeHtml;
+            }
+            _ => {
+                self.mode = InsertionMode::BeforeHtml;
+                self.dispatch(token);
+            }
+        }
+    }
+
+    fn before_html(&mut self, token: Token) {
+        match token {
+            Token::Doctype { .. } => {}
+            Token::Comment(data) => self.append_comment(DOCUMENT, data),
+            Token::Character(c) if tokenizer::is_whitespace(c) => {}
+            Token::StartTag(ref tag) if tag.name == "html" => {
+                let html: usize = self.create_element(tag, false);
+                self.append(DOCUMENT, html);
+                self.open_elements.push(html);
+                self.mode = InsertionMode::BeforeHead;
+            }
+            Token::EndTag(ref tag)
+                if !matches!(tag.name.as_str(), "head" | "body" | "html" | "br") => {}
+            _ => {
+                let html: usize = self.create_element(&named_tag("html"), false);
+                self.append(DOCUMENT, html);
+                self.open_elements.push(html);
+                self.mode = InsertionMode::BeforeHead;
+                self.dispatch(token);
+            }
+        }
+    }
+
+    fn before_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if tokenizer::is_whitespace(c) => {}
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype { .. } => {}
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "head" => {
+                self.head = Some(self.insert_element(tag));
+                self.mode = InsertionMode::InHead;
+            }
+            Token::EndTag(ref tag)
+                if !matches!(tag.name.as_str(), "head" | "body" | "html" | "br") => {}
+            _ => {
+                self.head = Some(self.insert_element(&named_tag("head")));
+                self.mode = InsertionMode::InHead;
+                self.dispatch(token);
+            }
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inhead
+    fn in_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if tokenizer::is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype { .. } => {}
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "base" | "basefont" | "bgsound" | "link" | "meta"
+                ) =>
+            {
+                self.insert_element(tag);
+                self.open_elements.pop();
+            }
+            Token::StartTag(ref tag) if tag.name == "title" => {
+                self.parse_raw_text(tag, State::RcData)
+            }
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(), "noframes" | "style") => {
+                self.parse_raw_text(tag, State::RawText)
+            }
+            Token::StartTag(ref tag) if tag.name == "noscript" => {
+                self.insert_element(tag);
+                self.mode = InsertionMode::InHeadNoscript;
+            }
+            Token::StartTag(ref tag) if tag.name == "script" => {
+                self.parse_raw_text(tag, State::ScriptData)
+            }
+            Token::StartTag(ref tag) if tag.name == "template" => {
+                self.insert_element(tag);
+                self.active_formatting.push(Formatting::Marker);
+                self.frameset_ok = false;
+            }
+            Token::EndTag(ref tag) if tag.name == "template" => {
+                if !self.has_open_element("template") {
+                    return;
+                }
+                self.generate_all_implied_end_tags();
+                self.pop_until(&["template"]);
+                self.clear_formatting_to_marker();
+                self.reset_insertion_mode();
+            }
+            Token::EndTag(ref tag) if tag.name == "head" => {
+                self.open_elements.pop();
+                self.mode = InsertionMode::AfterHead;
+            }
+            Token::StartTag(ref tag) if tag.name == "head" => {}
+            Token::EndTag(ref tag) if !matches!(tag.name.as_str(), "body" | "html" | "br") => {}
+            _ => {
+                self.open_elements.pop();
+                self.mode = InsertionMode::AfterHead;
+                self.dispatch(token);
+            }
+        }
+    }
+
+    fn in_head_noscript(&mut self, token: Token) {
+        match token {
+            Token::Doctype { .. } => {}
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::EndTag(ref tag) if tag.name == "noscript" => {
+                self.open_elements.pop();
+                self.mode = InsertionMode::InHead;
+            }
+            Token::Character(c) if tokenizer::is_whitespace(c) => self.in_head(token),
+            Token::Comment(_) => self.in_head(token),
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "basefont" | "bgsound" | "link" | "meta" | "noframes" | "style"
+                ) =>
+            {
+                self.in_head(token)
+            }
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(), "head" | "noscript") => {}
+            Token::EndTag(ref tag) if tag.name != "br" => {}
+            _ => {
+                self.open_elements.pop();
+                self.mode = InsertionMode::InHead;
+                self.dispatch(token);
+            }
+        }
+    }
+
+    fn after_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if tokenizer::is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype { .. } => {}
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "body" => {
+                self.insert_element(tag);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InBody;
+            }
+            Token::StartTag(ref tag) if tag.name == "frameset" => {
+                self.insert_element(tag);
+                self.mode = InsertionMode::InFrameset;
+            }
+            // </head>の後ろに書かれたheadの要素もheadに入れる
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "base"
+                        | "basefont"
+                        | "bgsound"
+                        | "link"
+                        | "meta"
+                        | "noframes"
+                        | "script"
+                        | "style"
+                        | "template"
+                        | "title"
+                ) =>
+            {
+                let head: usize = self.head.unwrap();
+                self.open_elements.push(head);
+                self.in_head(token);
+                self.open_elements.retain(|&node: &usize| node != head);
+            }
+            Token::EndTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::StartTag(ref tag) if tag.name == "head" => {}
+            Token::EndTag(ref tag) if !matches!(tag.name.as_str(), "body" | "html" | "br") => {}
+            _ => {
+                self.insert_element(&named_tag("body"));
+                self.mode = InsertionMode::InBody;
+                self.dispatch(token);
+            }
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inbody
+    fn in_body(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => {}
+            Token::Character(c) => {
+                self.reconstruct_formatting();
+                self.insert_character(c);
+                if !tokenizer::is_whitespace(c) {
+                    self.frameset_ok = false;
+                }
+            }
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype { .. } => {}
+            Token::Eof => {}
+            Token::StartTag(tag) => self.in_body_start_tag(tag),
+            Token::EndTag(tag) => self.in_body_end_tag(tag),
+        }
+    }
+
+    fn in_body_start_tag(&mut self, tag: Tag) {
+        match tag.name.as_str() {
+            // 2個目の<html>のattributeは最初の<html>に足す
+            "html" => {
+                let html: usize = self.open_elements[0];
+                self.add_missing_attributes(html, tag);
+            }
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style"
+            | "template" | "title" => self.in_head(Token::StartTag(tag)),
+            "body" => {
+                if self.open_elements.len() < 2 || !self.is_html(self.open_elements[1], "body") {
+                    return;
+                }
+                self.frameset_ok = false;
+                let body: usize = self.open_elements[1];
+                self.add_missing_attributes(body, tag);
+            }
+            "frameset" => {
+                if self.open_elements.len() < 2
+                    || !self.is_html(self.open_elements[1], "body")
+                    || !self.frameset_ok
+                {
+                    return;
+                }
+                let body: usize = self.open_elements[1];
+                self.detach(body);
+                self.open_elements.truncate(1);
+                self.insert_element(&tag);
+                self.mode = InsertionMode::InFrameset;
+            }
+            "address" | "article" | "aside" | "blockquote" | "center" | "details" | "dialog"
+            | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "header"
+            | "hgroup" | "main" | "menu" | "nav" | "ol" | "p" | "search" | "section"
+            | "summary" | "ul" => {
+                self.close_p_in_button_scope();
+                self.insert_element(&tag);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.close_p_in_button_scope();
+                // <h1><h2>みたいに見出しの中に見出しは入れない
+                if self.is_html_one_of(self.current_node(), &HEADINGS) {
+                    self.open_elements.pop();
+                }
+                self.insert_element(&tag);
+            }
+            "pre" | "listing" => {
+                self.close_p_in_button_scope();
+                self.insert_element(&tag);
+                self.ignore_newline = true;
+                self.frameset_ok = false;
+            }
+            "form" => {
+                if self.form.is_some() && !self.has_open_element("template") {
+                    return;
+                }
+                self.close_p_in_button_scope();
+                let form: usize = self.insert_element(&tag);
+                if !self.has_open_element("template") {
+                    self.form = Some(form);
+                }
+            }
+            // <li>の中に<li>が来たら前の<li>を閉じる。dd, dtも同じ
+            "li" | "dd" | "dt" => {
+                self.frameset_ok = false;
+                let siblings: &[&str] = if tag.name == "li" {
+                    &["li"]
+                } else {
+                    &["dd", "dt"]
+                };
+                for index in (0..self.open_elements.len()).rev() {
+                    let node: usize = self.open_elements[index];
+                    if self.is_html_one_of(node, siblings) {
+                        let name: String = self.name(node).to_string();
+                        self.generate_implied_end_tags(Some(&name));
+                        self.pop_until(&[&name]);
+                        break;
+                    }
+                    if self.is_special(node) && !self.is_html_one_of(node, &["address", "div", "p"])
+                    {
+                        break;
+                    }
+                }
+                self.close_p_in_button_scope();
+                self.insert_element(&tag);
+            }
+            "plaintext" => {
+                self.close_p_in_button_scope();
+                self.insert_element(&tag);
+                self.tokenizer.state = State::PlainText;
+            }
+            "button" => {
+                if self.has_in_scope(&["button"], Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["button"]);
+                }
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.frameset_ok = false;
+            }
+            // <a>の中に<a>が来たら前の<a>を閉じる
+            "a" => {
+                if let Some(a) = self.formatting_element_after_marker("a") {
+                    self.adoption_agency("a");
+                    if let Some(position) = self.formatting_position(a) {
+                        self.active_formatting.remove(position);
+                    }
+                    self.open_elements.retain(|&node: &usize| node != a);
+                }
+                self.reconstruct_formatting();
+                let element: usize = self.insert_element(&tag);
+                self.push_formatting(element);
+            }
+            "b" | "big" | "code" | "em" | "font" | "i" | "s" | "small" | "strike" | "strong"
+            | "tt" | "u" => {
+                self.reconstruct_formatting();
+                let element: usize = self.insert_element(&tag);
+                self.push_formatting(element);
+            }
+            "nobr" => {
+                self.reconstruct_formatting();
+                if self.has_in_scope(&["nobr"], Scope::Default) {
+                    self.adoption_agency("nobr");
+                    self.reconstruct_formatting();
+                }
+                let element: usize = self.insert_element(&tag);
+                self.push_formatting(element);
+            }
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.active_formatting.push(Formatting::Marker);
+                self.frameset_ok = false;
+            }
+            "table" => {
+                if self.quirks_mode != dom::QuirksMode::Quirks {
+                    self.close_p_in_button_scope();
+                }
+                self.insert_element(&tag);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTable;
+            }
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" | "input" => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.open_elements.pop();
+                // <input type="hidden">だけはframesetを邪魔しない
+                let hidden: bool = tag.name == "input"
+                    && tag
+                        .attributes
+                        .iter()
+                        .any(|(name, value): &(String, String)| {
+                            name == "type" && value.eq_ignore_ascii_case("hidden")
+                        });
+                if !hidden {
+                    self.frameset_ok = false;
+                }
+            }
+            "param" | "source" | "track" => {
+                self.insert_element(&tag);
+                self.open_elements.pop();
+            }
+            "hr" => {
+                self.close_p_in_button_scope();
+                self.insert_element(&tag);
+                self.open_elements.pop();
+                self.frameset_ok = false;
+            }
+            // <image>は<img>の書き間違いとして扱う
+            "image" => self.in_body_start_tag(Tag {
+                name: "img".to_string(),
+                ..tag
+            }),
+            "textarea" => {
+                self.insert_element(&tag);
+                self.ignore_newline = true;
+                self.tokenizer.state = State::RcData;
+                self.original_mode = self.mode;
+                self.frameset_ok = false;
+                self.mode = InsertionMode::Text;
+            }
+            "xmp" => {
+                self.close_p_in_button_scope();
+                self.reconstruct_formatting();
+                self.frameset_ok = false;
+                self.parse_raw_text(&tag, State::RawText);
+            }
+            "iframe" => {
+                self.frameset_ok = false;
+                self.parse_raw_text(&tag, State::RawText);
+            }
+            "noembed" => self.parse_raw_text(&tag, State::RawText),
+            "select" => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.frameset_ok = false;
+                self.mode = if matches!(
+                    self.mode,
+                    InsertionMode::InTable
+                        | InsertionMode::InCaption
+                        | InsertionMode::InTableBody
+                        | InsertionMode::InRow
+                        | InsertionMode::InCell
+                ) {
+                    InsertionMode::InSelectInTable
+                } else {
+                    InsertionMode::InSelect
+                };
+            }
+            "optgroup" | "option" => {
+                if self.is_html(self.current_node(), "option") {
+                    self.open_elements.pop();
+                }
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+            }
+            "rb" | "rtc" => {
+                if self.has_in_scope(&["ruby"], Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                }
+                self.insert_element(&tag);
+            }
+            "rp" | "rt" => {
+                if self.has_in_scope(&["ruby"], Scope::Default) {
+                    self.generate_implied_end_tags(Some("rtc"));
+                }
+                self.insert_element(&tag);
+            }
+            "math" | "svg" => {
+                self.reconstruct_formatting();
+                self.insert_foreign_element(&tag);
+                if tag.self_closing {
+                    self.open_elements.pop();
+                }
+            }
+            // tableの外にあるtableの部品は無視する
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th"
+            | "thead" | "tr" => {}
+            _ => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+            }
+        }
+    }
+
+    fn in_body_end_tag(&mut self, tag: Tag) {
+        match tag.name.as_str() {
+            "template" => self.in_head(Token::EndTag(tag)),
+            "body" => {
+                if self.has_in_scope(&["body"], Scope::Default) {
+                    self.mode = InsertionMode::AfterBody;
+                }
+            }
+            "html" => {
+                if self.has_in_scope(&["body"], Scope::Default) {
+                    self.mode = InsertionMode::AfterBody;
+                    self.dispatch(Token::EndTag(tag));
+                }
+            }
+            "address" | "article" | "aside" | "blockquote" | "button" | "center" | "details"
+            | "dialog" | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer"
+            | "header" | "hgroup" | "listing" | "main" | "menu" | "nav" | "ol" | "pre"
+            | "search" | "section" | "summary" | "ul" => {
+                if !self.has_in_scope(&[&tag.name], Scope::Default) {
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until(&[&tag.name]);
+            }
+            "form" => {
+                if self.has_open_element("template") {
+                    if !self.has_in_scope(&["form"], Scope::Default) {
+                        return;
+                    }
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["form"]);
+                    return;
+                }
+                let form: usize = match self.form.take() {
+                    Some(form) if self.node_in_scope(form) => form,
+                    _ => return,
+                };
+                self.generate_implied_end_tags(None);
+                // formは途中で閉じても、その下の要素は開いたままにする
+                self.open_elements.retain(|&node: &usize| node != form);
+            }
+            // </p>だけあるときは空の<p></p>にする
+            "p" => {
+                if !self.has_in_scope(&["p"], Scope::Button) {
+                    self.insert_element(&named_tag("p"));
+                }
+                self.close_p();
+            }
+            "li" => {
+                if !self.has_in_scope(&["li"], Scope::ListItem) {
+                    return;
+                }
+                self.generate_implied_end_tags(Some("li"));
+                self.pop_until(&["li"]);
+            }
+            "dd" | "dt" => {
+                if !self.has_in_scope(&[&tag.name], Scope::Default) {
+                    return;
+                }
+                self.generate_implied_end_tags(Some(&tag.name));
+                self.pop_until(&[&tag.name]);
+            }
+            // <h1>...</h2>でも閉じる
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                if !self.has_in_scope(&HEADINGS, Scope::Default) {
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until(&HEADINGS);
+            }
+            "a" | "b" | "big" | "code" | "em" | "font" | "i" | "nobr" | "s" | "small"
+            | "strike" | "strong" | "tt" | "u" => self.adoption_agency(&tag.name),
+            "applet" | "marquee" | "object" => {
+                if !self.has_in_scope(&[&tag.name], Scope::Default) {
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until(&[&tag.name]);
+                self.clear_formatting_to_marker();
+            }
+            // </br>は<br>として扱う
+            "br" => self.in_body_start_tag(named_tag("br")),
+            _ => self.any_other_end_tag(&tag.name),
+        }
+    }
+
+    // in bodyのany other end tag
+    // 同じ名前の要素まで閉じる。途中に<div>とかの特別な要素があったら無視する
+    fn any_other_end_tag(&mut self, name: &str) {
+        for index in (0..self.open_elements.len()).rev() {
+            let node: usize = self.open_elements[index];
+            if self.is_html(node, name) {
+                self.generate_implied_end_tags(Some(name));
+                self.open_elements.truncate(index);
+                return;
+            }
+            if self.is_special(node) {
+                return;
+            }
+        }
+    }
+
+    // <script>とか<textarea>の中身を読む間
+    fn text(&mut self, token: Token) {
+        match token {
+            Token::Character(c) => self.insert_character(c),
+            Token::Eof => {
+                self.open_elements.pop();
+                self.mode = self.original_mode;
+                self.dispatch(token);
+            }
+            _ => {
+                self.open_elements.pop();
+                self.mode = self.original_mode;
+            }
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intable
+    fn in_table(&mut self, token: Token) {
+        match token {
+            Token::Character(_)
+                if self.is_html_one_of(
+                    self.current_node(),
+                    &["table", "tbody", "template", "tfoot", "thead", "tr"],
+                ) =>
+            {
+                self.pending_table_text.clear();
+                self.original_mode = self.mode;
+                self.mode = InsertionMode::InTableText;
+                self.dispatch(token);
+            }
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype { .. } => {}
+            Token::StartTag(ref tag) if tag.name == "caption" => {
+                self.clear_to_context(&["table", "template", "html"]);
+                self.active_formatting.push(Formatting::Marker);
+                self.insert_element(tag);
+                self.mode = InsertionMode::InCaption;
+            }
+            Token::StartTag(ref tag) if tag.name == "colgroup" => {
+                self.clear_to_context(&["table", "template", "html"]);
+                self.insert_element(tag);
+                self.mode = InsertionMode::InColumnGroup;
+            }
+            Token::StartTag(ref tag) if tag.name == "col" => {
+                self.clear_to_context(&["table", "template", "html"]);
+                self.insert_element(&named_tag("colgroup"));
+                self.mode = InsertionMode::InColumnGroup;
+                self.dispatch(token);
+            }
+            Token::StartTag(ref tag)
+                if matches!(tag.name.as_str(), "tbody" | "tfoot" | "thead") =>
+            {
+                self.clear_to_context(&["table", "template", "html"]);
+                self.insert_element(tag);
+                self.mode = InsertionMode::InTableBody;
+            }
+            // <table><tr>みたいに<tbody>がなければ足す
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(), "td" | "th" | "tr") => {
+                self.clear_to_context(&["table", "template", "html"]);
+                self.insert_element(&named_tag("tbody"));
+                self.mode = InsertionMode::InTableBody;
+                self.dispatch(token);
+            }
+            // tableの中の<table>は前のtableを閉じる
+            Token::StartTag(ref tag) if tag.name == "table" => {
+                if !self.has_in_scope(&["table"], Scope::Table) {
+                    return;
+                }
+                self.pop_until(&["table"]);
+                self.reset_insertion_mode();
+                self.dispatch(token);
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => {
+                if !self.has_in_scope(&["table"], Scope::Table) {
+                    return;
+                }
+                self.pop_until(&["table"]);
+                self.reset_insertion_mode();
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "body"
+                        | "caption"
+                        | "col"
+                        | "colgroup"
+                        | "html"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) => {}
+            Token::StartTag(ref tag)
+                if matches!(tag.name.as_str(), "style" | "script" | "template") =>
+            {
+                self.in_head(token)
+            }
+            Token::EndTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::StartTag(ref tag)
+                if tag.name == "input"
+                    && tag
+                        .attributes
+                        .iter()
+                        .any(|(name, value): &(String, String)| {
+                            name == "type" && value.eq_ignore_ascii_case("hidden")
+                        }) =>
+            {
+                self.insert_element(tag);
+                self.open_elements.pop();
+            }
+            Token::StartTag(ref tag) if tag.name == "form" => {
+                if self.form.is_some() || self.has_open_element("template") {
+                    return;
+                }
+                self.form = Some(self.insert_element(tag));
+                self.open_elements.pop();
+            }
+            Token::Eof => self.in_body(token),
+            // tableの中に置けないものはtableの前に出す(foster parenting)
+            _ => {
+                self.foster_parenting = true;
+                self.in_body(token);
+                self.foster_parenting = false;
+            }
+        }
+    }
+
+    // tableの中の文字は、空白だけならそのまま、それ以外が混ざってたらtableの前に出す
+    fn in_table_text(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => {}
+            Token::Character(c) => self.pending_table_text.push(c),
+            _ => {
+                let text: String = mem::take(&mut self.pending_table_text);
+                if text.chars().all(tokenizer::is_whitespace) {
+                    for c in text.chars() {
+                        self.insert_character(c);
+                    }
+                } else {
+                    self.foster_parenting = true;
+                    for c in text.chars() {
+                        self.in_body(Token::Character(c));
+                    }
+                    self.foster_parenting = false;
+                }
+                self.mode = self.original_mode;
+                self.dispatch(token);
+            }
+        }
+    }
+
+    fn in_caption(&mut self, token: Token) {
+        match token {
+            Token::EndTag(ref tag) if tag.name == "caption" => {
+                self.close_caption();
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption"
+                        | "col"
+                        | "colgroup"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                if self.close_caption() {
+                    self.dispatch(token);
+                }
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => {
+                if self.close_caption() {
+                    self.dispatch(token);
+                }
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "body"
+                        | "col"
+                        | "colgroup"
+                        | "html"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) => {}
+            _ => self.in_body(token),
+        }
+    }
+
+    // captionを閉じてInTableに戻る。captionが開いてなければfalse
+    fn close_caption(&mut self) -> bool {
+        if !self.has_in_scope(&["caption"], Scope::Table) {
+            return false;
+        }
+        self.generate_implied_end_tags(None);
+        self.pop_until(&["caption"]);
+        self.clear_formatting_to_marker();
+        self.mode = InsertionMode::InTable;
+        true
+    }
+
+    fn in_column_group(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if tokenizer::is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype { .. } => {}
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "col" => {
+                self.insert_element(tag);
+                self.open_elements.pop();
+            }
+            Token::EndTag(ref tag) if tag.name == "colgroup" => {
+                if self.is_html(self.current_node(), "colgroup") {
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::InTable;
+                }
+            }
+            Token::EndTag(ref tag) if tag.name == "col" => {}
+            Token::StartTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::EndTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::Eof => self.in_body(token),
+            _ => {
+                if self.is_html(self.current_node(), "colgroup") {
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::InTable;
+                    self.dispatch(token);
+                }
+            }
+        }
+    }
+
+    fn in_table_body(&mut self, token: Token) {
+        const CONTEXT: [&str; 5] = ["tbody", "tfoot", "thead", "template", "html"];
+        match token {
+            Token::StartTag(ref tag) if tag.name == "tr" => {
+                self.clear_to_context(&CONTEXT);
+                self.insert_element(tag);
+                self.mode = InsertionMode::InRow;
+            }
+            // <tbody><td>みたいに<tr>がなければ足す
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(), "th" | "td") => {
+                self.clear_to_context(&CONTEXT);
+                self.insert_element(&named_tag("tr"));
+                self.mode = InsertionMode::InRow;
+                self.dispatch(token);
+            }
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if !self.has_in_scope(&[&tag.name], Scope::Table) {
+                    return;
+                }
+                self.clear_to_context(&CONTEXT);
+                self.open_elements.pop();
+                self.mode = InsertionMode::InTable;
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead"
+                ) =>
+            {
+                self.close_table_body(token)
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => self.close_table_body(token),
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr"
+                ) => {}
+            _ => self.in_table(token),
+        }
+    }
+
+    // tbodyとかを閉じてからtokenをもう一度処理する
+    fn close_table_body(&mut self, token: Token) {
+        if !self.has_in_scope(&["tbody", "thead", "tfoot"], Scope::Table) {
+            return;
+        }
+        self.clear_to_context(&["tbody", "tfoot", "thead", "template", "html"]);
+        self.open_elements.pop();
+        self.mode = InsertionMode::InTable;
+        self.dispatch(token);
+    }
+
+    fn in_row(&mut self, token: Token) {
+        match token {
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(), "th" | "td") => {
+                self.clear_to_context(&["tr", "template", "html"]);
+                self.insert_element(tag);
+                self.mode = InsertionMode::InCell;
+                self.active_formatting.push(Formatting::Marker);
+            }
+            Token::EndTag(ref tag) if tag.name == "tr" => {
+                self.close_row();
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                if self.close_row() {
+                    self.dispatch(token);
+                }
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => {
+                if self.close_row() {
+                    self.dispatch(token);
+                }
+            }
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if self.has_in_scope(&[&tag.name], Scope::Table) && self.close_row() {
+                    self.dispatch(token);
+                }
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th"
+                ) => {}
+            _ => self.in_table(token),
+        }
+    }
+
+    // trを閉じてInTableBodyに戻る。trが開いてなければfalse
+    fn close_row(&mut self) -> bool {
+        if !self.has_in_scope(&["tr"], Scope::Table) {
+            return false;
+        }
+        self.clear_to_context(&["tr", "template", "html"]);
+        self.open_elements.pop();
+        self.mode = InsertionMode::InTableBody;
+        true
+    }
+
+    fn in_cell(&mut self, token: Token) {
+        match token {
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(), "td" | "th") => {
+                if !self.has_in_scope(&[&tag.name], Scope::Table) {
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until(&[&tag.name]);
+                self.clear_formatting_to_marker();
+                self.mode = InsertionMode::InRow;
+            }
+            // <td>の中に次の<td>とかが来たら今のセルを閉じる
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption"
+                        | "col"
+                        | "colgroup"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                if self.has_in_scope(&["td", "th"], Scope::Table) {
+                    self.close_cell();
+                    self.dispatch(token);
+                }
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html"
+                ) => {}
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "table" | "tbody" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                if self.has_in_scope(&[&tag.name], Scope::Table) {
+                    self.close_cell();
+                    self.dispatch(token);
+                }
+            }
+            _ => self.in_body(token),
+        }
+    }
+
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags(None);
+        self.pop_until(&["td", "th"]);
+        self.clear_formatting_to_marker();
+        self.mode = InsertionMode::InRow;
+    }
+
+    fn in_select(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => {}
+            Token::Character(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype { .. } => {}
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "option" => {
+                if self.is_html(self.current_node(), "option") {
+                    self.open_elements.pop();
+                }
+                self.insert_element(tag);
+            }
+            Token::StartTag(ref tag) if tag.name == "optgroup" || tag.name == "hr" => {
+                if self.is_html(self.current_node(), "option") {
+                    self.open_elements.pop();
+                }
+                if self.is_html(self.current_node(), "optgroup") {
+                    self.open_elements.pop();
+                }
+                self.insert_element(tag);
+                if tag.name == "hr" {
+                    self.open_elements.pop();
+                }
+            }
+            Token::EndTag(ref tag) if tag.name == "optgroup" => {
+                let len: usize = self.open_elements.len();
+                if self.is_html(self.current_node(), "option")
+                    && len >= 2
+                    && self.is_html(self.open_elements[len - 2], "optgroup")
+                {
+                    self.open_elements.pop();
+                }
+                if self.is_html(self.current_node(), "optgroup") {
+                    self.open_elements.pop();
+                }
+            }
+            Token::EndTag(ref tag)
+                if tag.name == "option" && self.is_html(self.current_node(), "option") =>
+            {
+                self.open_elements.pop();
+            }
+            Token::EndTag(ref tag) if tag.name == "select" => {
+                self.close_select();
+            }
+            // selectの中の<select>は閉じタグとして扱う
+            Token::StartTag(ref tag) if tag.name == "select" => {
+                self.close_select();
+            }
+            Token::StartTag(ref tag)
+                if matches!(tag.name.as_str(), "input" | "keygen" | "textarea")
+                    && self.has_in_scope(&["select"], Scope::Select) =>
+            {
+                self.close_select();
+                self.dispatch(token);
+            }
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(), "script" | "template") => {
+                self.in_head(token)
+            }
+            Token::EndTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::Eof => self.in_body(token),
+            _ => {}
+        }
+    }
+
+    // selectを閉じる。selectが開いてなければfalse
+    fn close_select(&mut self) -> bool {
+        if !self.has_in_scope(&["select"], Scope::Select) {
+            return false;
+        }
+        self.pop_until(&["select"]);
+        self.reset_insertion_mode();
+        true
+    }
+
+    fn in_select_in_table(&mut self, token: Token) {
+        const TABLE_TAGS: [&str; 8] = [
+            "caption", "table", "tbody", "tfoot", "thead", "tr", "td", "th",
+        ];
+        match token {
+            // selectが開いてないときはpopしすぎないように何もしない
+            Token::StartTag(ref tag) if TABLE_TAGS.contains(&tag.name.as_str()) => {
+                if self.close_select() {
+                    self.dispatch(token);
+                }
+            }
+            Token::EndTag(ref tag) if TABLE_TAGS.contains(&tag.name.as_str()) => {
+                if !self.has_in_scope(&[&tag.name], Scope::Table) {
+                    return;
+                }
+                if self.close_select() {
+                    self.dispatch(token);
+                }
+            }
+            _ => self.in_select(token),
+        }
+    }
+
+    fn after_body(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if tokenizer::is_whitespace(c) => self.in_body(token),
+            // </html>の後ろのコメントもhtml要素に入れる
+            Token::Comment(data) => self.append_comment(self.open_elements[0], data),
+            Token::Doctype { .. } => {}
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::EndTag(ref tag) if tag.name == "html" => {
+                self.mode = InsertionMode::AfterAfterBody
+            }
+            Token::Eof => {}
+            // </body>の後ろに何か書いてあったらbodyに戻る
+            _ => {
+                self.mode = InsertionMode::InBody;
+                self.dispatch(token);
+            }
+        }
+    }
+
+    fn in_frameset(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if tokenizer::is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype { .. } => {}
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "frameset" => {
+                self.insert_element(tag);
+            }
+            Token::EndTag(ref tag) if tag.name == "frameset" => {
+                if self.current_node() == self.open_elements[0] {
+                    return;
+                }
+                self.open_elements.pop();
+                if !self.is_html(self.current_node(), "frameset") {
+                    self.mode = InsertionMode::AfterFrameset;
+                }
+            }
+            Token::StartTag(ref tag) if tag.name == "frame" => {
+                self.insert_element(tag);
+                self.open_elements.pop();
+            }
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head(token),
+            _ => {}
+        }
+    }
+
+    fn after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if tokenizer::is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::EndTag(ref tag) if tag.name == "html" => {
+                self.mode = InsertionMode::AfterAfterFrameset
+            }
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head(token),
+            _ => {}
+        }
+    }
+
+    fn after_after_body(&mut self, token: Token) {
+        match token {
+            Token::Comment(data) => self.append_comment(DOCUMENT, data),
+            Token::Doctype { .. } | Token::Eof => self.in_body(token),
+            Token::Character(c) if tokenizer::is_whitespace(c) => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            _ => {
+                self.mode = InsertionMode::InBody;
+                self.dispatch(token);
+            }
+        }
+    }
+
+    fn after_after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Comment(data) => self.append_comment(DOCUMENT, data),
+            Token::Doctype { .. } | Token::Eof => self.in_body(token),
+            Token::Character(c) if tokenizer::is_whitespace(c) => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head(token),
+            _ => {}
+        }
+    }
+
+    // ---- foreign content ----
+
+    fn use_foreign_rules(&self, token: &Token) -> bool {
+        let node: usize = match self.open_elements.last() {
+            Some(&node) => node,
+            None => return false,
+        };
+        if !self.is_foreign(node) {
+            return false;
+        }
+        match token {
+            Token::Eof => false,
+            // <foreignObject>とかの中は普通のHTMLとして読む
+            Token::StartTag(_) | Token::Character(_) => {
+                !INTEGRATION_POINTS.contains(&self.name(node))
+            }
+            _ => true,
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inforeign
+    fn in_foreign_content(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => self.insert_character('\u{FFFD}'),
+            Token::Character(c) => {
+                self.insert_character(c);
+                if !tokenizer::is_whitespace(c) {
+                    self.frameset_ok = false;
+                }
+            }
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype { .. } => {}
+            // svgの中に<div>とかが来たらsvgを閉じてHTMLに戻る
+            Token::StartTag(ref tag)
+                if BREAKOUT.contains(&tag.name.as_str())
+                    || (tag.name == "font"
+                        && tag.attributes.iter().any(|(name, _): &(String, String)| {
+                            matches!(name.as_str(), "color" | "face" | "size")
+                        })) =>
+            {
+                self.leave_foreign_content(token)
+            }
+            Token::EndTag(ref tag) if tag.name == "br" || tag.name == "p" => {
+                self.leave_foreign_content(token)
+            }
+            Token::StartTag(ref tag) => {
+                self.insert_foreign_element(tag);
+                if tag.self_closing {
+                    self.open_elements.pop();
+                }
+            }
+            Token::EndTag(ref tag) => {
+                let mut index: usize = self.open_elements.len() - 1;
+                while index > 0 {
+                    let node: usize = self.open_elements[index];
+                    if self.name(node).eq_ignore_ascii_case(&tag.name) {
+                        self.open_elements.truncate(index);
+                        return;
+                    }
+                    index -= 1;
+                    // HTMLの要素まで来たら普通の閉じタグとして処理する
+                    if !self.is_foreign(self.open_elements[index]) {
+                        self.process_in(self.mode, token);
+                        return;
+                    }
+                }
+            }
+            Token::Eof => self.process_in(self.mode, token),
+        }
+    }
+
+    fn leave_foreign_content(&mut self, token: Token) {
+        while let Some(&node) = self.open_elements.last() {
+            if !self.is_foreign(node) || INTEGRATION_POINTS.contains(&self.name(node)) {
+                break;
+            }
+            self.open_elements.pop();
+        }
+        self.process_in(self.mode, token);
+    }
+
+    // ---- adoption agency algorithm ----
+
+    // <b>1<p>2</b>3</p>みたいに入れ子がおかしいときに、ブラウザと同じように要素を分けてつなぎ替える
+    // https://html.spec.whatwg.org/multipage/parsing.html#adoption-agency-algorithm
+    fn adoption_agency(&mut self, subject: &str) {
+        let current: usize = self.current_node();
+        if self.is_html(current, subject) && self.formatting_position(current).is_none() {
+            self.open_elements.pop();
+            return;
+        }
+
+        for _ in 0..8 {
+            let formatting_element: usize = match self.formatting_element_after_marker(subject) {
+                Some(node) => node,
+                None => {
+                    self.any_other_end_tag(subject);
+                    return;
+                }
+            };
+            let formatting_position: usize = self.formatting_position(formatting_element).unwrap();
+            let formatting_index: usize = match self
+                .open_elements
+                .iter()
+                .position(|&node: &usize| node == formatting_element)
+            {
+                Some(index) => index,
+                None => {
+                    self.active_formatting.remove(formatting_position);
+                    return;
+                }
+            };
+            if !self.node_in_scope(formatting_element) {
+                return;
+            }
+
+            // formatting elementより下にある一番上の特別な要素
+            let furthest_block_index: usize = match self.open_elements[formatting_index + 1..]
+                .iter()
+                .position(|&node: &usize| self.is_special(node))
+            {
+                Some(offset) => formatting_index + 1 + offset,
+                // なければformatting elementまで閉じるだけ
+                None => {
+                    self.open_elements.truncate(formatting_index);
+                    self.active_formatting.remove(formatting_position);
+                    return;
+                }
+            };
+            let furthest_block: usize = self.open_elements[furthest_block_index];
+            let common_ancestor: usize = self.open_elements[formatting_index - 1];
+            let mut bookmark: usize = formatting_position;
+
+            // furthest blockからformatting elementまでの要素を作り直してつなぎ替える
+            let mut node_index: usize = furthest_block_index;
+            let mut last_node: usize = furthest_block;
+            let mut inner: usize = 0;
+            loop {
+                inner += 1;
+                node_index -= 1;
+                let node: usize = self.open_elements[node_index];
+                if node == formatting_element {
+                    break;
+                }
+                let mut position: Option<usize> = self.formatting_position(node);
+                if inner > 3 {
+                    if let Some(position) = position.take() {
+                        self.active_formatting.remove(position);
+                        if position < bookmark {
+                            bookmark -= 1;
+                        }
+                    }
+                }
+                let position: usize = match position {
+                    Some(position) => position,
+                    None => {
+                        self.open_elements.remove(node_index);
+                        continue;
+                    }
+                };
+                let new_node: usize = self.clone_element(node);
+                self.active_formatting[position] = Formatting::Element(new_node);
+                self.open_elements[node_index] = new_node;
+                if last_node == furthest_block {
+                    bookmark = position + 1;
+                }
+                self.detach(last_node);
+                self.append(new_node, last_node);
+                last_node = new_node;
+            }
+
+            self.detach(last_node);
+            let point: InsertionPoint = self.appropriate_place(Some(common_ancestor));
+            self.insert_at(point, last_node);
+
+            // furthest blockの中身を新しいformatting elementで包む
+            let new_element: usize = self.clone_element(formatting_element);
+            for child in mem::take(&mut self.nodes[furthest_block].children) {
+                self.append(new_element, child);
+            }
+            self.append(furthest_block, new_element);
+
+            let formatting_position: usize = self.formatting_position(formatting_element).unwrap();
+            self.active_formatting.remove(formatting_position);
+            if formatting_position < bookmark {
+                bookmark -= 1;
+            }
+            self.active_formatting
+                .insert(bookmark, Formatting::Element(new_element));
+
+            self.open_elements
+                .retain(|&node: &usize| node != formatting_element);
+            let furthest_block_index: usize = self
+                .open_elements
+                .iter()
+                .position(|&node: &usize| node == furthest_block)
+                .unwrap();
+            self.open_elements
+                .insert(furthest_block_index + 1, new_element);
+        }
+    }
+
+    // ---- list of active formatting elements ----
+
+    // 同じ名前とattributeの要素はmarkerの後ろに3個までしか覚えない(Noah's Ark clause)
+    fn push_formatting(&mut self, element: usize) {
+        let mut same: Vec<usize> = Vec::new();
+        for (position, entry) in self.active_formatting.iter().enumerate().rev() {
+            match *entry {
+                Formatting::Marker => break,
+                Formatting::Element(node) if self.same_element(node, element) => {
+                    same.push(position)
+                }
+                Formatting::Element(_) => {}
+            }
+        }
+        if same.len() >= 3 {
+            self.active_formatting.remove(*same.last().unwrap());
+        }
+        self.active_formatting.push(Formatting::Element(element));
+    }
+
+    // 閉じられてしまった<b>とかを作り直す
+    // https://html.spec.whatwg.org/multipage/parsing.html#reconstruct-the-active-formatting-elements
+    fn reconstruct_formatting(&mut self) {
+        let is_settled = |builder: &TreeBuilder, entry: Formatting| -> bool {
+            match entry {
+                Formatting::Marker => true,
+                Formatting::Element(node) => builder.open_elements.contains(&node),
+            }
+        };
+        match self.active_formatting.last() {
+            Some(&entry) if !is_settled(self, entry) => {}
+            _ => return,
+        }
+        let mut start: usize = self.active_formatting.len() - 1;
+        while start > 0 && !is_settled(self, self.active_formatting[start - 1]) {
+            start -= 1;
+        }
+        for position in start..self.active_formatting.len() {
+            if let Formatting::Element(node) = self.active_formatting[position] {
+                let new_node: usize = self.clone_element(node);
+                let point: InsertionPoint = self.appropriate_place(None);
+                self.insert_at(point, new_node);
+                self.open_elements.push(new_node);
+                self.active_formatting[position] = Formatting::Element(new_node);
+            }
+        }
+    }
+
+    fn clear_formatting_to_marker(&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if entry == Formatting::Marker {
+                break;
+            }
+        }
+    }
+
+    // 最後のmarkerより後ろにあるnameの要素
+    fn formatting_element_after_marker(&self, name: &str) -> Option<usize> {
+        for entry in self.active_formatting.iter().rev() {
+            match *entry {
+                Formatting::Marker => return None,
+                Formatting::Element(node) if self.is_html(node, name) => return Some(node),
+                Formatting::Element(_) => {}
+            }
+        }
+        None
+    }
+
+    fn formatting_position(&self, node: usize) -> Option<usize> {
+        self.active_formatting
+            .iter()
+            .position(|&entry: &Formatting| entry == Formatting::Element(node))
+    }
+
+    // ---- stack of open elements ----
+
+    fn current_node(&self) -> usize {
+        *self.open_elements.last().unwrap()
+    }
+
+    fn has_open_element(&self, name: &str) -> bool {
+        self.open_elements
+            .iter()
+            .any(|&node: &usize| self.is_html(node, name))
+    }
+
+    // namesのどれかがscopeの中で開いているか
+    // https://html.spec.whatwg.org/multipage/parsing.html#has-an-element-in-the-specific-scope
+    fn has_in_scope(&self, names: &[&str], scope: Scope) -> bool {
+        for &node in self.open_elements.iter().rev() {
+            if self.is_html_one_of(node, names) {
+                return true;
+            }
+            if self.is_scope_boundary(node, scope) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn node_in_scope(&self, target: usize) -> bool {
+        for &node in self.open_elements.iter().rev() {
+            if node == target {
+                return true;
+            }
+            if self.is_scope_boundary(node, Scope::Default) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn is_scope_boundary(&self, node: usize, scope: Scope) -> bool {
+        match scope {
+            Scope::Select => !self.is_html_one_of(node, &["optgroup", "option"]),
+            Scope::Table => self.is_html_one_of(node, &["html", "table", "template"]),
+            _ => {
+                if self.is_foreign(node) {
+                    return FOREIGN_SPECIAL.contains(&self.name(node));
+                }
+                self.is_html_one_of(
+                    node,
+                    &[
+                        "applet", "caption", "html", "table", "td", "th", "marquee", "object",
+                        "template",
+                    ],
+                ) || (scope == Scope::ListItem && self.is_html_one_of(node, &["ol", "ul"]))
+                    || (scope == Scope::Button && self.is_html(node, "button"))
+            }
+        }
+    }
+
+    // namesのどれかを閉じるまでpopする
+    fn pop_until(&mut self, names: &[&str]) {
+        while let Some(node) = self.open_elements.pop() {
+            if self.is_html_one_of(node, names) {
+                break;
+            }
+        }
+    }
+
+    // </p>とかを書かなくても閉じていい要素を閉じる。exceptは閉じない
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        while let Some(&node) = self.open_elements.last() {
+            if !self.is_html_one_of(node, &IMPLIED_END_TAGS) || Some(self.name(node)) == except {
+                break;
+            }
+            self.open_elements.pop();
+        }
+    }
+
+    // templateを閉じるときはtableの部品も閉じる
+    fn generate_all_implied_end_tags(&mut self) {
+        while let Some(&node) = self.open_elements.last() {
+            if !self.is_html_one_of(node, &IMPLIED_END_TAGS)
+                && !self.is_html_one_of(
+                    node,
+                    &[
+                        "caption", "colgroup", "tbody", "td", "tfoot", "th", "thead", "tr",
+                    ],
+                )
+            {
+                break;
+            }
+            self.open_elements.pop();
+        }
+    }
+
+    fn close_p_in_button_scope(&mut self) {
+        if self.has_in_scope(&["p"], Scope::Button) {
+            self.close_p();
+        }
+    }
+
+    fn close_p(&mut self) {
+        self.generate_implied_end_tags(Some("p"));
+        self.pop_until(&["p"]);
+    }
+
+    // tableの中でnamesのどれかが今の要素になるまで閉じる
+    fn clear_to_context(&mut self, names: &[&str]) {
+        while !self.is_html_one_of(self.current_node(), names) {
+            self.open_elements.pop();
+        }
+    }
+
+    // 開いている要素から今どのmodeにいるべきか決め直す
+    // https://html.spec.whatwg.org/multipage/parsing.html#reset-the-insertion-mode-appropriately
+    fn reset_insertion_mode(&mut self) {
+        for index in (0..self.open_elements.len()).rev() {
+            let node: usize = self.open_elements[index];
+            let last: bool = index == 0;
+            self.mode = match self.name(node) {
+                "select" => {
+                    let in_table: bool = self.open_elements[..index]
+                        .iter()
+                        .rev()
+                        .take_while(|&&ancestor: &&usize| !self.is_html(ancestor, "template"))
+                        .any(|&ancestor: &usize| self.is_html(ancestor, "table"));
+                    if in_table {
+                        InsertionMode::InSelectInTable
+                    } else {
+                        InsertionMode::InSelect
+                    }
+                }
+                "td" | "th" if !last => InsertionMode::InCell,
+                "tr" => InsertionMode::InRow,
+                "tbody" | "thead" | "tfoot" => InsertionMode::InTableBody,
+                "caption" => InsertionMode::InCaption,
+                "colgroup" => InsertionMode::InColumnGroup,
+                "table" => InsertionMode::InTable,
+                "head" if !last => InsertionMode::InHead,
+                "body" => InsertionMode::InBody,
+                "frameset" => InsertionMode::InFrameset,
+                "html" if self.head.is_none() => InsertionMode::BeforeHead,
+                "html" => InsertionMode::AfterHead,
+                _ if last => InsertionMode::InBody,
+                _ => continue,
+            };
+            return;
+        }
+    }
+
+    // ---- inserting nodes ----
+
+    // <title>とか<style>を開いて、閉じタグまでをただの文字として読む
+    fn parse_raw_text(&mut self, tag: &Tag, state: State) {
+        self.insert_element(tag);
+        self.tokenizer.state = state;
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    // 要素を作って今の場所に入れて開く
+    fn insert_element(&mut self, tag: &Tag) -> usize {
+        let element: usize = self.create_element(tag, false);
+        let point: InsertionPoint = self.appropriate_place(None);
+        self.insert_at(point, element);
+        self.open_elements.push(element);
+        element
+    }
+
+    fn insert_foreign_element(&mut self, tag: &Tag) -> usize {
+        let element: usize = self.create_element(tag, true);
+        let point: InsertionPoint = self.appropriate_place(None);
+        self.insert_at(point, element);
+        self.open_elements.push(element);
+        element
+    }
+
+    // 文字は直前のtext nodeにくっつける
+    fn insert_character(&mut self, c: char) {
+        let point: InsertionPoint = self.appropriate_place(None);
+        let (parent, previous): (usize, Option<usize>) = match point {
+            InsertionPoint::Append(parent) => (parent, self.nodes[parent].children.last().copied()),
+            InsertionPoint::Before(parent, sibling) => {
+                let children: &Vec<usize> = &self.nodes[parent].children;
+                let index: usize = children
+                    .iter()
+                    .position(|&child: &usize| child == sibling)
+                    .unwrap();
+                (parent, index.checked_sub(1).map(|i: usize| children[i]))
+            }
+        };
+        // documentの直下に文字は置けない
+        if parent == DOCUMENT {
+            return;
+        }
+        if let Some(previous) = previous {
+            if let NodeData::Text(ref mut text) = self.nodes[previous].data {
+                text.push(c);
+                return;
+            }
+        }
+        let text: usize = self.new_node(NodeData::Text(c.to_string()));
+        self.insert_at(point, text);
+    }
+
+    fn insert_comment(&mut self, data: String) {
+        let comment: usize = self.new_node(NodeData::Comment(data));
+        let point: InsertionPoint = self.appropriate_place(None);
+        self.insert_at(point, comment);
+    }
+
+    fn append_comment(&mut self, parent: usize, data: String) {
+        let comment: usize = self.new_node(NodeData::Comment(data));
+        self.append(parent, comment);
+    }
+
+    // 普通は今の要素の最後に入れる
+    // foster parentingのときにtableの中に置けないものはtableの前に入れる
+    // https://html.spec.whatwg.org/multipage/parsing.html#appropriate-place-for-inserting-a-node
+    fn appropriate_place(&self, target: Option<usize>) -> InsertionPoint {
+        let target: usize = target.unwrap_or_else(|| self.current_node());
+        if !self.foster_parenting
+            || !self.is_html_one_of(target, &["table", "tbody", "tfoot", "thead", "tr"])
+        {
+            return InsertionPoint::Append(target);
+        }
+        match self
+            .open_elements
+            .iter()
+            .rposition(|&node: &usize| self.is_html(node, "table"))
+        {
+            Some(index) => {
+                let table: usize = self.open_elements[index];
+                match self.nodes[table].parent {
+                    Some(parent) => InsertionPoint::Before(parent, table),
+                    None => InsertionPoint::Append(self.open_elements[index - 1]),
+                }
+            }
+            None => InsertionPoint::Append(self.open_elements[0]),
+        }
+    }
+
+    fn add_missing_attributes(&mut self, element: usize, tag: Tag) {
+        if let NodeData::Element {
+            ref mut attributes, ..
+        } = self.nodes[element].data
+        {
+            for (name, value) in tag.attributes {
+                attributes.entry(name).or_insert(value);
+            }
+        }
+    }
+
+    // ---- tree ----
+
+    fn new_node(&mut self, data: NodeData) -> usize {
+        self.nodes.push(TreeNode {
+            parent: None,
+            children: Vec::new(),
+            data,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn create_element(&mut self, tag: &Tag, foreign: bool) -> usize {
+        let attributes: dom::AttrMap = tag
+            .attributes
+            .iter()
+            .cloned()
+            .collect::<HashMap<String, String>>();
+        self.new_node(NodeData::Element {
+            name: tag.name.clone(),
+            attributes,
+            foreign,
+        })
+    }
+
+    // 同じ名前とattributeの要素を新しく作る。中身はコピーしない
+    fn clone_element(&mut self, node: usize) -> usize {
+        let data: NodeData = match self.nodes[node].data {
+            NodeData::Element {
+                ref name,
+                ref attributes,
+                foreign,
+            } => NodeData::Element {
+                name: name.clone(),
+                attributes: attributes.clone(),
+                foreign,
+            },
+            _ => unreachable!(),
+        };
+        self.new_node(data)
+    }
+
+    fn append(&mut self, parent: usize, child: usize) {
+        self.nodes[child].parent = Some(parent);
+        self.nodes[parent].children.push(child);
+    }
+
+    fn insert_at(&mut self, point: InsertionPoint, child: usize) {
+        match point {
+            InsertionPoint::Append(parent) => self.append(parent, child),
+            InsertionPoint::Before(parent, sibling) => {
+                let index: usize = self.nodes[parent]
+                    .children
+                    .iter()
+                    .position(|&node: &usize| node == sibling)
+                    .unwrap();
+                self.nodes[child].parent = Some(parent);
+                self.nodes[parent].children.insert(index, child);
+            }
+        }
+    }
+
+    // 親から外す
+    fn detach(&mut self, node: usize) {
+        if let Some(parent) = self.nodes[node].parent.take() {
+            self.nodes[parent]
+                .children
+                .retain(|&child: &usize| child != node);
+        }
+    }
+
+    // ---- element helpers ----
+
+    fn name(&self, node: usize) -> &str {
+        match self.nodes[node].data {
+            NodeData::Element { ref name, .. } => name,
+            _ => "",
+        }
+    }
+
+    fn is_foreign(&self, node: usize) -> bool {
+        matches!(
+            self.nodes[node].data,
+            NodeData::Element { foreign: true, .. }
+        )
+    }
+
+    // svgとかの中の要素ではない、nameの要素か
+    fn is_html(&self, node: usize, name: &str) -> bool {
+        !self.is_foreign(node) && self.name(node) == name
+    }
+
+    fn is_html_one_of(&self, node: usize, names: &[&str]) -> bool {
+        !self.is_foreign(node) && names.contains(&self.name(node))
+    }
+
+    fn is_special(&self, node: usize) -> bool {
+        if self.is_foreign(node) {
+            FOREIGN_SPECIAL.contains(&self.name(node))
+        } else {
+            SPECIAL.contains(&self.name(node))
+        }
+    }
+
+    fn same_element(&self, a: usize, b: usize) -> bool {
+        match (&self.nodes[a].data, &self.nodes[b].data) {
+            (
+                NodeData::Element {
+                    name: name_a,
+                    attributes: attributes_a,
+                    foreign: foreign_a,
+                },
+                NodeData::Element {
+                    name: name_b,
+                    attributes: attributes_b,
+                    foreign: foreign_b,
+                },
+            ) => name_a == name_b && attributes_a == attributes_b && foreign_a == foreign_b,
+            _ => false,
+        }
+    }
+}
+
+// attributeのないタグ
+fn named_tag(name: &str) -> Tag {
+    Tag {
+        name: name.to_string(),
+        ..Tag::default()
+    }
+}
+
+// html5libのtree-construction testsと同じ形で書いている
+// https://github.com/html5lib/html5lib-tests/tree/master/tree-construction
+// 名前空間はdomに持っていないので<svg svg>ではなく<svg>と書く
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(source: &str) -> String {
+        let mut out: String = String::new();
+        serialize(&mut out, &parse_document(source.to_string()).root, 0);
+        out
+    }
+
+    fn serialize(out: &mut String, node: &dom::Node, depth: usize) {
+        let indent: String = "  ".repeat(depth);
+        match node.node_type {
+            dom::NodeType::Element(ref elem) => {
+                out.push_str(&format!("| {}<{}>\n", indent, elem.tag_name));
+                let mut attributes: Vec<(&String, &String)> = elem.attributes.iter().collect();
+                attributes.sort();
+                for (name, value) in attributes {
+                    out.push_str(&format!("| {}  {}=\"{}\"\n", indent, name, value));
+                }
+            }
+            dom::NodeType::Text(ref text) => out.push_str(&format!("| {}\"{}\"\n", indent, text)),
+            dom::NodeType::Comment(ref data) => {
+                out.push_str(&format!("| {}<!-- {} -->\n", indent, data))
+            }
+        }
+        for child in &node.children {
+            serialize(out, child, depth + 1);
+        }
+    }
+
+    // 期待する形の行頭の空白を取る
+    fn expected(lines: &str) -> String {
+        lines
+            .lines()
+            .map(str::trim_start)
+            .filter(|line: &&str| !line.is_empty())
+            .map(|line: &str| format!("{}\n", line))
+            .collect()
+    }
+
+    #[test]
+    fn misnested_formatting_elements() {
+        assert_eq!(
+            tree("<b><i></b></i>"),
+            expected(
+                r#"
+                | <html>
+                |   <head>
+                |   <body>
+                |     <b>
+                |       <i>
+                "#
+            )
+        );
+    }
+
+    // adoption agency algorithmでfurthest blockがあるとき
+    #[test]
+    fn formatting_element_around_block() {
+        assert_eq!(
+            tree("<b>1<p>2</b>3</p>"),
+            expected(
+                r#"
+                | <html>
+                |   <head>
+                |   <body>
+                |     <b>
+                |       "1"
+                |     <p>
+                |       <b>
+                |         "2"
+                |       "3"
+                "#
+            )
+        );
+        assert_eq!(
+            tree("<a><p>x</a>y"),
+            expected(
+                r#"
+                | <html>
+                |   <head>
+                |   <body>
+                |     <a>
+                |     <p>
+                |       <a>
+                |         "x"
+                |       "y"
+                "#
+            )
+        );
+    }
+
+    // 同じformatting elementは3つまでしか作り直さない
+    #[test]
+    fn noahs_ark_clause() {
+        assert_eq!(
+            tree("<p><b><b><b><b>x</p>y"),
+            expected(
+                r#"
+                | <html>
+                |   <head>
+                |   <body>
+                |     <p>
+                |       <b>
+                |         <b>
+                |           <b>
+                |             <b>
+                |               "x"
+                |     <b>
+                |       <b>
+                |         <b>
+                |           "y"
+                "#
+            )
+        );
+    }
+
+    #[test]
+    fn table_foster_parenting() {
+        assert_eq!(
+            tree("<table><tr><td>x</td></tr>foo<b>bar</b></table>"),
+            expected(
+                r#"
+                | <html>
+                |   <head>
+                |   <body>
+                |     "foo"
+                |     <b>
+                |       "bar"
+                |     <table>
+                |       <tbody>
+                |         <tr>
+                |           <td>
+                |             "x"
+                "#
+            )
+        );
+        // tableの前に出したtextは前のtextとつなげる
+        assert_eq!(
+            tree("<table>a<tr>b</table>"),
+            expected(
+                r#"
+                | <html>
+                |   <head>
+                |   <body>
+                |     "ab"
+                |     <table>
+                |       <tbody>
+                |         <tr>
+                "#
+            )
+        );
+    }
+
+    #[test]
+    fn stray_end_tag_p() {
+        assert_eq!(
+            tree("<div></p></div>"),
+            expected(
+                r#"
+                | <html>
+                |   <head>
+                |   <body>
+                |     <div>
+                |       <p>
+                "#
+            )
+        );
+        assert_eq!(
+            tree("<p>a</p></p>b"),
+            expected(
+                r#"
+                | <html>
+                |   <head>
+                |   <body>
+                |     <p>
+                |       "a"
+                |     <p>
+                |     "b"
+                "#
+            )
+        );
+        // htmlができる前の</p>は無視する
+        assert_eq!(
+            tree("</p>"),
+            expected(
+                r#"
+                | <html>
+                |   <head>
+                |   <body>
+                "#
+            )
+        );
+    }
+
+    #[test]
+    fn cdata_in_foreign_content() {
+        assert_eq!(
+            tree("<svg><![CDATA[foo<b>]]></svg>"),
+            expected(
+                r#"
+                | <html>
+                |   <head>
+                |   <body>
+                |     <svg>
+                |       "foo<b>"
+                "#
+            )
+        );
+        // HTMLの中ではbogus comment
+        assert_eq!(
+            tree("<math></math><![CDATA[y]]>"),
+            expected(
+                r#"
+                | <html>
+                |   <head>
+                |   <body>
+                |     <math>
+                |     <!-- [CDATA[y]] -->
+                "#
+            )
+        );
+    }
+
+    // </head>のあとのscriptとかはheadに入れる
+    #[test]
+    fn head_element_after_head() {
+        assert_eq!(
+            tree("<head></head><script>x</script>"),
+            expected(
+                r#"
+                | <html>
+                |   <head>
+                |     <script>
+                |       "x"
+                |   <body>
+                "#
+            )
+        );
+    }
+
+    // どんな入力でもpanicしない
+    #[test]
+    fn malformed_inputs_do_not_panic() {
+        for source in [
+            "",
+            "<!DOCTYPE html><script> <frameset>",
+            "<table><caption><table>",
+            "<select><table><tr><td><select>",
+            "<a><a><a><a><table><a>",
+            "<b><table><td></b><i></table>x",
+            "<template><tr></template><td>",
+            "x<template><table><select></template><td>",
+            "<svg><desc><svg><foreignObject></p></svg>",
+            "<frameset><frame></frameset></html><noframes>",
+            "</html></body></head><p>",
+            "<html><html><body><body>",
+            "<table><tr><td><b></tr></table>x",
+        ] {
+            parse_document(source.to_string());
+        }
+    }
+}